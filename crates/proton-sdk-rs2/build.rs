@@ -1,3 +1,25 @@
 fn main() {
-    prost_build::compile_protos(&["protos/proton.sdk.proto"], &["protos"]).unwrap();
-}
\ No newline at end of file
+    prost_build::Config::new()
+        .enable_type_names()
+        .type_name_domain(["."], "type.googleapis.com")
+        .compile_protos(&["protos/proton.sdk.proto"], &["protos"])
+        .unwrap();
+
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src/bindings");
+    println!("cargo:rerun-if-env-changed=PROTON_SDK_UPDATE_HEADER");
+
+    let config = cbindgen::Config::from_file("cbindgen.toml").unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/bindings/exports.rs")
+        .generate()
+        .unwrap();
+
+    // The checked-in header is only rewritten on request, so that builds leave the source tree untouched
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    bindings.write_to_file(out_dir.join("proton_sdk.h"));
+    if std::env::var_os("PROTON_SDK_UPDATE_HEADER").is_some() {
+        bindings.write_to_file("include/proton_sdk.h");
+    }
+}
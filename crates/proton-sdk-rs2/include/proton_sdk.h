@@ -0,0 +1,60 @@
+#ifndef PROTON_SDK_H
+#define PROTON_SDK_H
+
+/* Generated by cbindgen from src/bindings/exports.rs, do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Borrowed view over bytes owned by whoever passes it; only valid for the duration of the call.
+ */
+typedef struct ByteArray {
+  const uint8_t *pointer;
+  size_t length;
+} ByteArray;
+
+/**
+ * Callback receiving bytes from the SDK, along with the opaque state the host registered it with.
+ */
+typedef void (*array_action)(const void *state, struct ByteArray array);
+
+/**
+ * Callback receiving a request from the SDK, which the host answers by passing an encoded
+ * `proton.sdk.Response` to [`proton_sdk_complete_request`] along with `completion_handle`.
+ */
+typedef void (*request_action)(const void *state,
+                               struct ByteArray request,
+                               int64_t completion_handle);
+
+/**
+ * Starts executing an encoded `proton.sdk.Request` and returns without waiting for it to complete.
+ *
+ * `response_action` is invoked at most once with the encoded `proton.sdk.Response`, possibly from
+ * another thread and possibly before this function returns. The response bytes are only valid until
+ * `response_action` returns. It is always invoked, except for requests using a cancellation token source
+ * that gets freed: once the response to the free request itself has arrived, `response_action` is not
+ * invoked anymore for them, while it may still be invoked before that.
+ *
+ * # Safety
+ *
+ * `request` must point to `request.length` readable bytes for the duration of the call, and `state`
+ * must remain usable from any thread until `response_action` is invoked.
+ */
+void proton_sdk_handle_request(struct ByteArray request,
+                               const void *state,
+                               array_action response_action);
+
+/**
+ * Answers a request the SDK sent through a [`RequestCallback`] with an encoded `proton.sdk.Response`.
+ *
+ * Completing a request that was already completed or abandoned by the SDK has no effect.
+ *
+ * # Safety
+ *
+ * `response` must point to `response.length` readable bytes for the duration of the call.
+ */
+void proton_sdk_complete_request(int64_t completion_handle,
+                                 struct ByteArray response);
+
+#endif  /* PROTON_SDK_H */
@@ -0,0 +1,84 @@
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    SessionId,
+    serialization::{int_bool, unix_time},
+};
+
+/// Session of the account's user on any client, as listed by the API.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ActiveSession {
+    #[serde(rename = "UID")]
+    pub session_id: SessionId,
+    /// Technical name of the client application, e.g. `web-drive`.
+    #[serde(rename = "ClientID")]
+    pub client_id: String,
+    /// Name of the client application for display, in the language of the user.
+    #[serde(rename = "LocalizedClientName")]
+    pub client_name: String,
+    #[serde(rename = "CreateTime", with = "unix_time")]
+    pub created_at: SystemTime,
+    /// Unset for sessions the user is not allowed to end, e.g. those opened by an administrator.
+    #[serde(rename = "Revocable", default = "default_is_revocable", with = "int_bool")]
+    pub is_revocable: bool,
+    /// Whether this is the session the list was requested from.
+    #[serde(skip)]
+    pub is_current: bool,
+}
+
+fn default_is_revocable() -> bool {
+    true
+}
+
+/// Outcome of revoking a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionRevocation {
+    /// Another session was revoked, its tokens being rejected from now on.
+    Revoked,
+    /// The session was already ended or never existed.
+    NotFound,
+    /// The revoked session was the current one, which was ended as through `end_from_session`.
+    CurrentSessionEnded,
+}
+
+/// Outcome of revoking every session of the account but the current one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OtherSessionsRevocation {
+    /// Sessions that were revoked, as listed right before revoking them.
+    pub revoked_session_ids: Vec<SessionId>,
+    /// Sessions the user is not allowed to end, which were left open.
+    pub kept_session_ids: Vec<SessionId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::api::response::SessionsResponse;
+
+    #[test]
+    fn sessions_are_read_from_the_api_response() {
+        let response: SessionsResponse = serde_json::from_str(
+            r#"{
+                "Code": 1000,
+                "Sessions": [
+                    {"UID": "session-1", "ClientID": "web-drive", "LocalizedClientName": "Drive for web", "CreateTime": 1700000000, "Revocable": 0},
+                    {"UID": "session-2", "ClientID": "windows-drive", "LocalizedClientName": "Drive for Windows", "CreateTime": 1700000100}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let sessions = response.sessions;
+        assert_eq!(sessions[0].session_id, SessionId::new("session-1".to_string()));
+        assert_eq!(sessions[0].client_name, "Drive for web");
+        assert_eq!(sessions[0].created_at, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        assert!(!sessions[0].is_revocable);
+        assert!(sessions[1].is_revocable);
+        assert!(!sessions[1].is_current);
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    UserId,
+    api::addresses::AddressesApiClient,
+    cache::{CacheRepositoryTrait, TypedCache},
+    http::HttpClient,
+    proton,
+    serialization::int_bool,
+};
+
+const ADDRESS_CACHE_NAMESPACE: &str = "addresses";
+const ADDRESS_CACHE_SCHEMA_VERSION: u32 = 2;
+
+/// Email address of the session's user, with the keys used to sign and decrypt on its behalf.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Address {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub email: String,
+    pub status: AddressStatus,
+    /// Position of the address in the user's preferences, the lowest being the default address.
+    pub order: i32,
+    pub keys: Vec<AddressKey>,
+}
+
+impl Address {
+    pub fn primary_key(&self) -> Option<&AddressKey> {
+        self.keys.iter().find(|key| key.is_primary)
+    }
+}
+
+impl From<&Address> for proton::Address {
+    fn from(address: &Address) -> Self {
+        Self {
+            address_id: address.id.clone(),
+            order: address.order,
+            email_address: address.email.clone(),
+            status: proton::AddressStatus::from(address.status).into(),
+            keys: address
+                .keys
+                .iter()
+                .map(|key| proton::AddressKey {
+                    address_id: address.id.clone(),
+                    address_key_id: key.id.clone(),
+                    is_active: key.is_active,
+                    is_allowed_for_encryption: key.flags.contains(AddressKeyFlags::ENCRYPTION),
+                    is_allowed_for_verification: key.flags.contains(AddressKeyFlags::VERIFICATION),
+                })
+                .collect(),
+            primary_key_index: address.keys.iter().position(|key| key.is_primary).map(|index| index as i32),
+        }
+    }
+}
+
+/// Private key of an address, locked with a passphrase that is itself encrypted to the user keys.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddressKey {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub version: u32,
+    /// Armored, passphrase-protected private key.
+    pub private_key: String,
+    /// Armored message carrying the key passphrase, absent for legacy keys locked with the account key passphrase.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Armored signature of the passphrase carried by `token`, made with a user key.
+    #[serde(default)]
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    pub flags: AddressKeyFlags,
+    #[serde(rename = "Primary", with = "int_bool")]
+    pub is_primary: bool,
+    #[serde(rename = "Active", with = "int_bool")]
+    pub is_active: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum AddressStatus {
+    Disabled = 0,
+    Enabled = 1,
+    Deleting = 2,
+    /// A status this version of the SDK does not know, which must not make the other addresses unreadable.
+    Unknown = u8::MAX as isize,
+}
+
+impl From<u8> for AddressStatus {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => AddressStatus::Disabled,
+            1 => AddressStatus::Enabled,
+            2 => AddressStatus::Deleting,
+            _ => AddressStatus::Unknown,
+        }
+    }
+}
+
+impl From<AddressStatus> for u8 {
+    fn from(value: AddressStatus) -> Self {
+        value as u8
+    }
+}
+
+impl From<AddressStatus> for proton::AddressStatus {
+    fn from(value: AddressStatus) -> Self {
+        match value {
+            AddressStatus::Disabled => proton::AddressStatus::Disabled,
+            AddressStatus::Enabled => proton::AddressStatus::Enabled,
+            AddressStatus::Deleting => proton::AddressStatus::Deleting,
+            AddressStatus::Unknown => proton::AddressStatus::Unknown,
+        }
+    }
+}
+
+/// Bit field of what an address key may still be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AddressKeyFlags(u32);
+
+impl AddressKeyFlags {
+    /// Set unless the key was compromised, in which case its signatures must not be trusted.
+    pub const VERIFICATION: Self = Self(1);
+    /// Set unless the key is obsolete, in which case nothing new may be encrypted to it.
+    pub const ENCRYPTION: Self = Self(2);
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+/// Address Drive signs with on behalf of the user: the enabled address that comes first in the user's order.
+pub fn get_default_address(addresses: &[Address]) -> Option<&Address> {
+    addresses
+        .iter()
+        .filter(|address| address.status == AddressStatus::Enabled)
+        .min_by_key(|address| address.order)
+}
+
+/// Reads the addresses of a session's user through the entity cache.
+pub(crate) struct AddressesClient {
+    api_client: AddressesApiClient,
+    cache: TypedCache<Vec<Address>>,
+}
+
+impl AddressesClient {
+    pub(crate) fn new(http_client: HttpClient, entity_cache_repository: Arc<dyn CacheRepositoryTrait>) -> Self {
+        Self {
+            api_client: AddressesApiClient::new(http_client),
+            cache: TypedCache::new(entity_cache_repository, ADDRESS_CACHE_NAMESPACE, ADDRESS_CACHE_SCHEMA_VERSION),
+        }
+    }
+
+    pub(crate) async fn get_addresses(
+        &self,
+        user_id: &UserId,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Vec<Address>> {
+        if let Some(addresses) = self.cache.try_get(user_id.raw(), cancellation_token.clone()).await? {
+            return Ok(addresses);
+        }
+
+        let addresses = self.api_client.get_addresses(cancellation_token.clone()).await?;
+
+        self.cache.set(user_id.raw(), &addresses, Vec::new(), cancellation_token).await?;
+
+        Ok(addresses)
+    }
+
+    /// Drops the cached addresses, e.g. when an event reports that one of them changed.
+    pub(crate) async fn invalidate_addresses(
+        &self,
+        user_id: &UserId,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.cache.remove(user_id.raw(), cancellation_token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(id: &str, status: AddressStatus, order: i32) -> Address {
+        Address {
+            id: id.to_string(),
+            email: format!("{}@proton.me", id),
+            status,
+            order,
+            keys: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn default_address_is_the_first_enabled_one() {
+        let addresses = [
+            address("disabled", AddressStatus::Disabled, 1),
+            address("second", AddressStatus::Enabled, 3),
+            address("first", AddressStatus::Enabled, 2),
+            address("deleting", AddressStatus::Deleting, 0),
+        ];
+
+        assert_eq!(get_default_address(&addresses).unwrap().id, "first");
+        assert!(get_default_address(&addresses[..1]).is_none());
+    }
+
+    #[test]
+    fn address_converts_to_proto() {
+        let address: Address = serde_json::from_str(
+            r#"{
+                "ID": "address-1",
+                "Email": "alice@proton.me",
+                "Status": 1,
+                "Order": 1,
+                "Keys": [
+                    {"ID": "key-1", "Version": 3, "PrivateKey": "", "Flags": 1, "Primary": 0, "Active": 1},
+                    {"ID": "key-2", "Version": 3, "PrivateKey": "", "Token": "", "Flags": 3, "Primary": 1, "Active": 1}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let proto_address = proton::Address::from(&address);
+
+        assert_eq!(proto_address.status(), proton::AddressStatus::Enabled);
+        assert_eq!(proto_address.primary_key_index, Some(1));
+        assert!(!proto_address.keys[0].is_allowed_for_encryption);
+        assert!(proto_address.keys[0].is_allowed_for_verification);
+        assert!(proto_address.keys[1].is_allowed_for_encryption);
+    }
+
+    #[test]
+    fn unknown_address_status_does_not_fail_deserialization() {
+        let status: AddressStatus = serde_json::from_str("42").unwrap();
+
+        assert_eq!(status, AddressStatus::Unknown);
+        assert_eq!(proton::AddressStatus::from(status), proton::AddressStatus::Unknown);
+    }
+
+    #[test]
+    fn address_without_primary_key_has_no_primary_key_index() {
+        let proto_address = proton::Address::from(&address("address-1", AddressStatus::Enabled, 1));
+
+        assert_eq!(proto_address.primary_key_index, None);
+    }
+}
@@ -1,20 +1,66 @@
+pub(crate) mod account;
+pub(crate) mod addresses;
+pub(crate) mod auth;
+pub(crate) mod events;
+pub(crate) mod features;
+pub(crate) mod keys;
 pub(crate) mod response;
+pub(crate) mod sessions;
+pub(crate) mod users;
+
+use std::fmt;
+
+use serde::Deserialize;
 
 use crate::api::ResponseCode::ProtonDriveUnknown;
 use crate::api::ResponseCode::CustomCode;
 
+#[derive(Debug, Deserialize)]
+#[serde(from = "ApiResponseBody")]
 pub struct ApiResponse {
     code: ResponseCode,
+    raw_code: i64,
     error_message: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ApiResponseBody {
+    code: i64,
+    error: Option<String>,
+}
+
+impl From<ApiResponseBody> for ApiResponse {
+    fn from(body: ApiResponseBody) -> Self {
+        Self {
+            code: body.code.into(),
+            raw_code: body.code,
+            error_message: body.error,
+        }
+    }
+}
+
 impl ApiResponse {
     fn is_success(&self) -> bool {
-        self.code == ResponseCode::Success
+        self.code == ResponseCode::Success || self.code == ResponseCode::MultipleResponses
+    }
+
+    /// Turns an unsuccessful response into the error it carries.
+    pub(crate) fn into_result(self, http_status: http::StatusCode) -> Result<(), ProtonApiError> {
+        if self.is_success() && http_status.is_success() {
+            return Ok(());
+        }
+
+        Err(ProtonApiError {
+            code: self.code,
+            raw_code: self.raw_code,
+            http_status,
+            message: self.error_message,
+        })
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseCode
 {
     Unknown = 0,
@@ -62,3 +108,41 @@ pub enum ResponseCode
     SrpError = CustomCode as isize + 4,
 }
 
+impl From<i64> for ResponseCode {
+    fn from(code: i64) -> Self {
+        use ResponseCode::*;
+
+        [
+            Unauthorized, Forbidden, RequestTimeout, Success, MultipleResponses, InvalidRequirements,
+            InvalidValue, InvalidEncryptedIdFormat, AlreadyExists, DoesNotExist, Timeout, IncompatibleState,
+            InvalidApp, OutdatedApp, Offline, IncorrectLoginCredentials, AccountDeleted, AccountDisabled,
+            InvalidRefreshToken, NoActiveSubscription, UnknownAddress, ProtonDriveUnknown, InsufficientQuota,
+            InsufficientSpace, MaxFileSizeForFreeUser, TooManyChildren, CustomCode, SocketError,
+            SessionRefreshFailed, SrpError,
+        ]
+        .into_iter()
+        .find(|known| *known as i64 == code)
+        .unwrap_or(Unknown)
+    }
+}
+
+/// Error returned by the Proton API, either through a non-success `Code` or an HTTP error status.
+#[derive(Debug)]
+pub struct ProtonApiError {
+    pub code: ResponseCode,
+    /// The code as sent by the server, kept because `code` is `Unknown` for codes the SDK does not know.
+    pub raw_code: i64,
+    pub http_status: http::StatusCode,
+    pub message: Option<String>,
+}
+
+impl fmt::Display for ProtonApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "API error {} ({}): {}", self.raw_code, self.http_status, message),
+            None => write!(f, "API error {} ({})", self.raw_code, self.http_status),
+        }
+    }
+}
+
+impl std::error::Error for ProtonApiError {}
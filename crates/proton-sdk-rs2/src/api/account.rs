@@ -0,0 +1,121 @@
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    PasswordMode,
+    api::response::{ModulusResponse, ServerProofResponse, UserSettingsResponse},
+    http::HttpClient,
+    serialization::base64_bytes,
+};
+
+/// Proof that the user knows their current login password, required by every sensitive account change.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct PasswordProof {
+    #[serde(with = "base64_bytes")]
+    pub(crate) client_ephemeral: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) client_proof: Vec<u8>,
+    #[serde(rename = "SRPSession")]
+    pub(crate) srp_session_id: String,
+}
+
+/// SRP verifier of a new login password, from which the server can check future logins.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct PasswordVerifier {
+    pub(crate) version: u8,
+    #[serde(rename = "ModulusID")]
+    pub(crate) modulus_id: String,
+    pub(crate) salt: String,
+    pub(crate) verifier: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct UpdatePasswordRequest<'a> {
+    #[serde(flatten)]
+    proof: &'a PasswordProof,
+    auth: &'a PasswordVerifier,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct PrivateKeyUpdate {
+    #[serde(rename = "ID")]
+    pub(crate) key_id: String,
+    pub(crate) private_key: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct UpdatePrivateKeysRequest<'a> {
+    #[serde(flatten)]
+    proof: &'a PasswordProof,
+    #[serde(with = "base64_bytes")]
+    key_salt: Vec<u8>,
+    user_keys: &'a [PrivateKeyUpdate],
+    /// Legacy address keys, which are locked with the same passphrase as the user keys.
+    keys: &'a [PrivateKeyUpdate],
+    /// Set in single-password mode, where the login password changes along with the key passphrase.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth: Option<&'a PasswordVerifier>,
+}
+
+/// Calls the `core/v4` endpoints that read and change the passwords of the account.
+pub(crate) struct AccountApiClient {
+    http_client: HttpClient,
+}
+
+impl AccountApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    /// Whether the account has a separate data password, as set in its user settings.
+    pub(crate) async fn get_password_mode(&self, cancellation_token: CancellationToken) -> anyhow::Result<PasswordMode> {
+        let response: UserSettingsResponse = self.http_client.get("core/v4/settings", cancellation_token).await?;
+
+        Ok(response.user_settings.password.mode)
+    }
+
+    /// Fetches a fresh SRP modulus, signed by the server, for generating a password verifier.
+    pub(crate) async fn get_modulus(&self, cancellation_token: CancellationToken) -> anyhow::Result<ModulusResponse> {
+        self.http_client.get("core/v4/auth/modulus", cancellation_token).await
+    }
+
+    pub(crate) async fn update_password(
+        &self,
+        proof: &PasswordProof,
+        verifier: &PasswordVerifier,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ServerProofResponse> {
+        let request = UpdatePasswordRequest { proof, auth: verifier };
+
+        self.http_client
+            .put("core/v4/settings/password", &request, cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn update_private_keys(
+        &self,
+        proof: &PasswordProof,
+        key_salt: &[u8],
+        user_keys: &[PrivateKeyUpdate],
+        legacy_address_keys: &[PrivateKeyUpdate],
+        verifier: Option<&PasswordVerifier>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ServerProofResponse> {
+        let request = UpdatePrivateKeysRequest {
+            proof,
+            key_salt: key_salt.to_vec(),
+            user_keys,
+            keys: legacy_address_keys,
+            auth: verifier,
+        };
+
+        self.http_client
+            .put("core/v4/keys/private", &request, cancellation_token)
+            .await
+    }
+}
@@ -0,0 +1,20 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{addresses::Address, api::response::AddressesResponse, http::HttpClient};
+
+/// Calls the `core/v4/addresses` endpoints about the addresses of the session's user.
+pub(crate) struct AddressesApiClient {
+    http_client: HttpClient,
+}
+
+impl AddressesApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_addresses(&self, cancellation_token: CancellationToken) -> anyhow::Result<Vec<Address>> {
+        let response: AddressesResponse = self.http_client.get("core/v4/addresses", cancellation_token).await?;
+
+        Ok(response.addresses)
+    }
+}
@@ -0,0 +1,133 @@
+use http::{HeaderMap, HeaderValue, Method};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    SessionId,
+    api::response::{
+        AuthenticationResponse, RefreshSessionResponse, SesisonInitiationResponse, UnauthenticatedSessionResponse,
+    },
+    auth::AuthenticationApiClientTrait,
+    http::{HttpClient, SESSION_ID_HEADER_NAME},
+    serialization::base64_bytes,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SessionInitiationRequest {
+    username: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct AuthenticationRequest {
+    username: String,
+    #[serde(with = "base64_bytes")]
+    client_ephemeral: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    client_proof: Vec<u8>,
+    #[serde(rename = "SRPSession")]
+    srp_session_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RefreshSessionRequest {
+    response_type: &'static str,
+    grant_type: &'static str,
+    refresh_token: String,
+    #[serde(rename = "RedirectURI")]
+    redirect_uri: String,
+}
+
+/// Calls the `auth/v4` endpoints, which are used before a session exists or to renew its tokens.
+pub(crate) struct AuthenticationApiClient {
+    http_client: HttpClient,
+    refresh_redirect_uri: http::Uri,
+}
+
+impl AuthenticationApiClient {
+    pub(crate) fn new(http_client: HttpClient, refresh_redirect_uri: http::Uri) -> Self {
+        Self {
+            http_client,
+            refresh_redirect_uri,
+        }
+    }
+
+    /// Creates a session without a user, which a later login upgrades while keeping its UID.
+    pub(crate) async fn create_unauthenticated_session(
+        &self,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<UnauthenticatedSessionResponse> {
+        self.http_client
+            .post("auth/v4/sessions", &serde_json::json!({}), cancellation_token)
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl AuthenticationApiClientTrait for AuthenticationApiClient {
+    async fn initiate_session(
+        &self,
+        username: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<SesisonInitiationResponse> {
+        self.http_client
+            .post("auth/v4/info", &SessionInitiationRequest { username }, cancellation_token)
+            .await
+    }
+
+    async fn authenticate(
+        &self,
+        username: String,
+        initiation_response: SesisonInitiationResponse,
+        srp_client_handshake: proton_crypto::srp::ClientProof,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<AuthenticationResponse> {
+        let request = AuthenticationRequest {
+            username,
+            client_ephemeral: srp_client_handshake.client_ephemeral,
+            client_proof: srp_client_handshake.client_proof,
+            srp_session_id: initiation_response.srp_session_id,
+        };
+
+        let response: AuthenticationResponse = self
+            .http_client
+            .post("auth/v4", &request, cancellation_token)
+            .await?;
+
+        if response.server_proof != srp_client_handshake.expected_server_proof {
+            return Err(anyhow::anyhow!("Server proof verification failed"));
+        }
+
+        Ok(response)
+    }
+
+    async fn refresh_session(
+        &self,
+        session_id: SessionId,
+        _access_token: String,
+        refresh_token: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<RefreshSessionResponse> {
+        let request = RefreshSessionRequest {
+            response_type: "token",
+            grant_type: "refresh_token",
+            refresh_token,
+            redirect_uri: self.refresh_redirect_uri.to_string(),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SESSION_ID_HEADER_NAME, HeaderValue::from_str(session_id.raw())?);
+
+        self.http_client
+            .send(
+                Method::POST,
+                "auth/v4/refresh",
+                Some(serde_json::to_vec(&request)?),
+                headers,
+                cancellation_token,
+            )
+            .await
+    }
+}
@@ -0,0 +1,34 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    EventId,
+    api::response::{EventsResponse, LatestEventResponse},
+    http::HttpClient,
+};
+
+/// Calls the `core/v4/events` endpoints, which report changes to the account since a given event.
+pub(crate) struct EventsApiClient {
+    http_client: HttpClient,
+}
+
+impl EventsApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_latest_event_id(&self, cancellation_token: CancellationToken) -> anyhow::Result<EventId> {
+        let response: LatestEventResponse = self.http_client.get("core/v4/events/latest", cancellation_token).await?;
+
+        Ok(response.event_id)
+    }
+
+    pub(crate) async fn get_events(
+        &self,
+        since_event_id: &EventId,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<EventsResponse> {
+        self.http_client
+            .get(&format!("core/v4/events/{}", since_event_id.raw()), cancellation_token)
+            .await
+    }
+}
@@ -0,0 +1,21 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{api::response::FeatureTogglesResponse, http::HttpClient};
+
+/// Calls the `feature/v2` endpoints, which proxy the Unleash frontend API.
+pub(crate) struct FeaturesApiClient {
+    http_client: HttpClient,
+}
+
+impl FeaturesApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_toggles(
+        &self,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<FeatureTogglesResponse> {
+        self.http_client.get("feature/v2/frontend", cancellation_token).await
+    }
+}
@@ -0,0 +1,37 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::response::{KeySalt, KeySaltsResponse, PublicKeysResponse},
+    http::HttpClient,
+};
+
+/// Calls the `core/v4/keys` endpoints about the keys of other users and the key salts of the session's user.
+pub(crate) struct KeysApiClient {
+    http_client: HttpClient,
+}
+
+impl KeysApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_public_keys(
+        &self,
+        email: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<PublicKeysResponse> {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("Email", email)
+            .finish();
+
+        self.http_client
+            .get(&format!("core/v4/keys/all?{}", query), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn get_key_salts(&self, cancellation_token: CancellationToken) -> anyhow::Result<Vec<KeySalt>> {
+        let response: KeySaltsResponse = self.http_client.get("core/v4/keys/salts", cancellation_token).await?;
+
+        Ok(response.key_salts)
+    }
+}
@@ -1,21 +1,262 @@
-use crate::{EventId, SessionId, UserId, api::ApiResponse};
+use serde::Deserialize;
 
+use crate::{active_sessions::ActiveSession, addresses::{Address, AddressKeyFlags}, EventId, PasswordMode, SessionId, UserId, api::ApiResponse, serialization::{base64_bytes, int_bool}, users::User};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct SesisonInitiationResponse {
-    version: i32,
-    modulus: String,
-    server_ephemeral: Vec<u8>,
-    salt: Vec<u8>,
-    srp_session_id: String,
-    response: ApiResponse,
+    pub(crate) version: i32,
+    pub(crate) modulus: String,
+    #[serde(with = "base64_bytes")]
+    pub(crate) server_ephemeral: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) salt: Vec<u8>,
+    #[serde(rename = "SRPSession")]
+    pub(crate) srp_session_id: String,
+    #[serde(flatten)]
+    pub(crate) response: ApiResponse,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct AuthenticationResponse {
-    session_id: SessionId,
-    user_id: UserId,
-    event_id: Option<EventId>,
+    #[serde(rename = "UID")]
+    pub(crate) session_id: SessionId,
+    #[serde(rename = "UserID")]
+    pub(crate) user_id: UserId,
+    #[serde(rename = "EventID")]
+    pub(crate) event_id: Option<EventId>,
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: String,
+    #[serde(default)]
+    pub(crate) scopes: Vec<String>,
+    #[serde(with = "base64_bytes")]
+    pub(crate) server_proof: Vec<u8>,
+    pub(crate) password_mode: PasswordMode,
+    #[serde(rename = "2FA")]
+    pub(crate) second_factor: SecondFactorInfo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecondFactorInfo {
+    /// Bit field of the enabled second factor methods, 0 if the account has none.
+    pub(crate) enabled: u32,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct RefreshSessionResponse {
     pub access_token: String,
     pub refresh_token: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserResponse {
+    pub(crate) user: User,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddressesResponse {
+    pub(crate) addresses: Vec<Address>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PublicKeysResponse {
+    /// Keys of the address itself, served by Proton.
+    pub(crate) address: PublicKeyGroup,
+    /// Keys found elsewhere for external addresses, e.g. through WKD, which Proton cannot vouch for.
+    #[serde(default)]
+    pub(crate) unverified: Option<PublicKeyGroup>,
+    #[serde(rename = "IsProton", default, with = "int_bool")]
+    pub(crate) is_proton_address: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PublicKeyGroup {
+    pub(crate) keys: Vec<PublicKeyEntry>,
+    #[serde(default)]
+    pub(crate) signed_key_list: Option<SignedKeyList>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PublicKeyEntry {
+    pub(crate) flags: AddressKeyFlags,
+    pub(crate) public_key: String,
+}
+
+/// List of an address's keys signed by the address owner, so the server cannot substitute keys unnoticed.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SignedKeyList {
+    pub(crate) data: Option<String>,
+    pub(crate) signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KeySaltsResponse {
+    pub(crate) key_salts: Vec<KeySalt>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KeySalt {
+    #[serde(rename = "ID")]
+    pub(crate) key_id: String,
+    /// Base64 salt the key passphrase is derived with, absent for keys not locked with a derived passphrase.
+    pub(crate) key_salt: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LatestEventResponse {
+    #[serde(rename = "EventID")]
+    pub(crate) event_id: EventId,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EventsResponse {
+    #[serde(rename = "EventID")]
+    pub(crate) event_id: EventId,
+    /// Set when more events are pending after this batch.
+    #[serde(default, with = "int_bool")]
+    pub(crate) more: bool,
+    /// Bit field of the data the client must fetch again from scratch, because the changes were not tracked.
+    #[serde(default)]
+    pub(crate) refresh: u32,
+    #[serde(default)]
+    pub(crate) user: Option<User>,
+    #[serde(default)]
+    pub(crate) addresses: Vec<AddressEvent>,
+    #[serde(default)]
+    pub(crate) user_settings: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AddressEvent {
+    #[serde(rename = "ID")]
+    pub(crate) address_id: String,
+    pub(crate) action: EventAction,
+    /// Absent when the address was deleted.
+    #[serde(default)]
+    pub(crate) address: Option<Address>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "u8")]
+pub enum EventAction {
+    Delete = 0,
+    Create = 1,
+    Update = 2,
+    UpdateFlags = 3,
+}
+
+impl TryFrom<u8> for EventAction {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(EventAction::Delete),
+            1 => Ok(EventAction::Create),
+            2 => Ok(EventAction::Update),
+            3 => Ok(EventAction::UpdateFlags),
+            _ => Err(anyhow::anyhow!("Unknown event action {}", value)),
+        }
+    }
+}
+
+/// Toggles enabled for the client, the Unleash frontend API leaving out the disabled ones.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureTogglesResponse {
+    pub(crate) toggles: Vec<FeatureToggle>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureToggle {
+    pub(crate) name: String,
+    pub(crate) enabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ModulusResponse {
+    /// Armored message signed by the server, carrying the modulus.
+    pub(crate) modulus: String,
+    #[serde(rename = "ModulusID")]
+    pub(crate) modulus_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServerProofResponse {
+    #[serde(with = "base64_bytes")]
+    pub(crate) server_proof: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SessionsResponse {
+    pub(crate) sessions: Vec<ActiveSession>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserSettingsResponse {
+    pub(crate) user_settings: UserSettings,
+}
+
+/// Part of the user settings the SDK reads, the others being ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserSettings {
+    pub(crate) password: PasswordSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PasswordSettings {
+    pub(crate) mode: PasswordMode,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SessionForkResponse {
+    pub(crate) selector: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ForkedSessionResponse {
+    #[serde(rename = "UID")]
+    pub(crate) session_id: SessionId,
+    #[serde(rename = "UserID")]
+    pub(crate) user_id: UserId,
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: String,
+    #[serde(default)]
+    pub(crate) scopes: Vec<String>,
+    /// Payload set by the parent session, encrypted with a key the child received along with the selector.
+    #[serde(default)]
+    pub(crate) payload: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UnauthenticatedSessionResponse {
+    #[serde(rename = "UID")]
+    pub(crate) session_id: SessionId,
+    pub(crate) access_token: String,
+    pub(crate) refresh_token: String,
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    SessionId,
+    active_sessions::ActiveSession,
+    api::{
+        ApiResponse,
+        response::{ForkedSessionResponse, SessionForkResponse, SessionsResponse},
+    },
+    http::HttpClient,
+    serialization::int_bool,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SessionForkRequest<'a> {
+    #[serde(rename = "ChildClientID")]
+    child_client_id: &'a str,
+    #[serde(with = "int_bool")]
+    independent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<&'a str>,
+}
+
+/// Checks that an ID only holds the URL-safe characters of Proton IDs, so that it cannot reach another path.
+fn path_segment<'a>(name: &str, id: &'a str) -> anyhow::Result<&'a str> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '=')) {
+        return Err(anyhow::anyhow!("Invalid {} \"{}\"", name, id));
+    }
+
+    Ok(id)
+}
+
+/// Calls the `auth/v4/sessions` endpoints, which manage every session of the account.
+pub(crate) struct SessionsApiClient {
+    http_client: HttpClient,
+}
+
+impl SessionsApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_sessions(&self, cancellation_token: CancellationToken) -> anyhow::Result<Vec<ActiveSession>> {
+        let response: SessionsResponse = self.http_client.get("auth/v4/sessions", cancellation_token).await?;
+
+        Ok(response.sessions)
+    }
+
+    pub(crate) async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let path = format!("auth/v4/sessions/{}", path_segment("session ID", session_id.raw())?);
+
+        self.http_client
+            .delete::<ApiResponse>(&path, cancellation_token)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Revokes every session of the account except the one making the request.
+    pub(crate) async fn revoke_other_sessions(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.http_client
+            .delete::<ApiResponse>("auth/v4/sessions", cancellation_token)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Creates a fork of the requesting session, to be pulled by the child client with the returned selector.
+    pub(crate) async fn fork_session(
+        &self,
+        child_client_id: &str,
+        is_independent: bool,
+        encrypted_payload: Option<&str>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<String> {
+        let request = SessionForkRequest {
+            child_client_id,
+            independent: is_independent,
+            payload: encrypted_payload,
+        };
+
+        let response: SessionForkResponse = self
+            .http_client
+            .post("auth/v4/sessions/forks", &request, cancellation_token)
+            .await?;
+
+        Ok(response.selector)
+    }
+
+    /// Pulls the session forked under the selector, which requires no session of its own.
+    pub(crate) async fn get_forked_session(
+        &self,
+        selector: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ForkedSessionResponse> {
+        let path = format!("auth/v4/sessions/forks/{}", path_segment("fork selector", selector)?);
+
+        self.http_client.get(&path, cancellation_token).await
+    }
+}
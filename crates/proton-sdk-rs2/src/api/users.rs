@@ -0,0 +1,20 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{api::response::UserResponse, http::HttpClient, users::User};
+
+/// Calls the `core/v4/users` endpoints about the user of the session.
+pub(crate) struct UsersApiClient {
+    http_client: HttpClient,
+}
+
+impl UsersApiClient {
+    pub(crate) fn new(http_client: HttpClient) -> Self {
+        Self { http_client }
+    }
+
+    pub(crate) async fn get_user(&self, cancellation_token: CancellationToken) -> anyhow::Result<User> {
+        let response: UserResponse = self.http_client.get("core/v4/users", cancellation_token).await?;
+
+        Ok(response.user)
+    }
+}
@@ -1,19 +1,41 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use tokio::sync::{OnceCell, RwLock, broadcast};
+use futures::{FutureExt, future::{BoxFuture, Shared}};
+use tokio::sync::{RwLock, broadcast};
 use tokio_util::sync::CancellationToken;
 
-use crate::{SessionId, api::response::{AuthenticationResponse, SesisonInitiationResponse, RefreshSessionResponse}};
+use crate::{OperationCancelledError, SessionId, api::{ProtonApiError, ResponseCode, response::{AuthenticationResponse, SesisonInitiationResponse, RefreshSessionResponse}}};
+
+/// Resolves to the current token pair, or to the refreshed one while a refresh is in flight.
+type TokensTask = Shared<BoxFuture<'static, TokensState>>;
+
+/// Token pair, which cannot be used until it has been persisted.
+#[derive(Clone)]
+struct TokensState {
+    tokens: (String, String),
+    is_persisted: bool,
+}
+
+impl TokensState {
+    fn persisted(tokens: (String, String)) -> Self {
+        Self { tokens, is_persisted: true }
+    }
+}
+
+const PERSIST_TOKENS_ATTEMPTS: u32 = 3;
+const PERSIST_TOKENS_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 pub struct TokenCredential {
     client: Arc<dyn AuthenticationApiClientTrait>,
     session_id: SessionId,
     access_token: String,
     refresh_token: String,
-    tokens_task: Arc<RwLock<Arc<OnceCell<(String, String)>>>>,
+    persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+    tokens_task: Arc<RwLock<TokensTask>>,
 
     tokens_refreshed_tx: broadcast::Sender<(String, String)>,
     refresh_token_expired_tx: broadcast::Sender<()>,
+    token_persistence_failed_tx: broadcast::Sender<Arc<anyhow::Error>>,
 }
 
 impl TokenCredential {
@@ -22,38 +44,107 @@ impl TokenCredential {
         session_id: SessionId,
         access_token: String,
         refresh_token: String,
+        persistence: Option<Arc<dyn TokenPersistenceTrait>>,
     ) -> Self {
-        let tokens_task = OnceCell::new();
-        let _ = tokens_task.set((access_token.clone(), refresh_token.clone()));
+        let tokens_task = futures::future::ready(TokensState::persisted((access_token.clone(), refresh_token.clone())))
+            .boxed()
+            .shared();
 
         let (tokens_refreshed_tx, _) = broadcast::channel(16);
         let (refresh_token_expired_tx, _) = broadcast::channel(16);
-        
+        let (token_persistence_failed_tx, _) = broadcast::channel(16);
+
         Self {
             client,
             session_id,
-            tokens_task: Arc::new(RwLock::new(Arc::new(tokens_task))),
+            persistence,
+            tokens_task: Arc::new(RwLock::new(tokens_task)),
             tokens_refreshed_tx,
             refresh_token_expired_tx,
+            token_persistence_failed_tx,
             access_token,
             refresh_token,
         }
     }
 
+    pub(crate) fn persistence(&self) -> Option<Arc<dyn TokenPersistenceTrait>> {
+        self.persistence.clone()
+    }
+
+    /// Switches to new tokens of the same session, e.g. once a login upgraded an unauthenticated session.
+    ///
+    /// A refresh in flight is waited for first, so that it cannot persist or publish its tokens after the new ones.
+    pub(crate) async fn replace_tokens(&self, access_token: String, refresh_token: String) {
+        let mut tokens_task_guard = self.tokens_task.write().await;
+
+        // The refresh runs in a task of its own that never takes the lock, so it completes while the lock is held
+        tokens_task_guard.clone().await;
+
+        *tokens_task_guard = futures::future::ready(TokensState::persisted((access_token, refresh_token)))
+            .boxed()
+            .shared();
+    }
+
+    /// Fails while refreshed tokens could not be persisted, see [`TokenPersistenceTrait`].
     pub async fn get_tokens(
         &self,
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<(String, String)> {
+        let task = self.tokens_task.read().await.clone();
+        let state = Self::wait(task.clone(), &cancellation_token).await?;
+
+        if state.is_persisted {
+            return Ok(state.tokens);
+        }
+
+        self.persist_pending_tokens(task, state.tokens, cancellation_token).await
+    }
+
+    async fn wait(task: TokensTask, cancellation_token: &CancellationToken) -> anyhow::Result<TokensState> {
         tokio::select! {
-            _ = cancellation_token.cancelled() => {
-                Err(anyhow::anyhow!("Operation cancelled"))
+            _ = cancellation_token.cancelled() => Err(OperationCancelledError.into()),
+            state = task => Ok(state)
+        }
+    }
+
+    /// Retries persisting refreshed tokens whose persistence failed, unless another caller already does.
+    async fn persist_pending_tokens(
+        &self,
+        pending_tokens_task: TokensTask,
+        pending_tokens: (String, String),
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<(String, String)> {
+        let mut tokens_task_guard = self.tokens_task.write().await;
+        let tokens_task_unchanged = tokens_task_guard.ptr_eq(&pending_tokens_task);
+
+        if tokens_task_unchanged {
+            let persist_handle = tokio::spawn(Self::persist_refreshed_tokens(
+                self.session_id.clone(),
+                self.persistence.clone(),
+                pending_tokens,
+                self.tokens_refreshed_tx.clone(),
+                self.token_persistence_failed_tx.clone(),
+            ));
+
+            *tokens_task_guard = async move {
+                match persist_handle.await {
+                    Ok(state) => state,
+                    Err(_) => pending_tokens_task.await,
+                }
             }
-            result = async {
-                let task = self.tokens_task.read().await.clone();
-                let tokens = task.get().ok_or_else(|| anyhow::anyhow!("Tokens not initialized"))?;
-                Ok((tokens.0.clone(), tokens.1.clone()))
-            } => result
+            .boxed()
+            .shared();
+        }
+
+        let task = tokens_task_guard.clone();
+        drop(tokens_task_guard);
+
+        let state = Self::wait(task, &cancellation_token).await?;
+        if !state.is_persisted {
+            return Err(anyhow::anyhow!("Refreshed session tokens could not be persisted"));
         }
+
+        Ok(state.tokens)
     }
 
     pub async fn get_access_token(
@@ -70,15 +161,10 @@ impl TokenCredential {
     pub fn subscribe_refresh_token_expired(&self) -> broadcast::Receiver<()> {
         self.refresh_token_expired_tx.subscribe()
     }
-    
-    /// aka TokenCredential.OnTokensRefreshed
-    fn trigger_tokens_refreshed(&self, access_token: String, refresh_token: String) {
-        let _ = self.tokens_refreshed_tx.send((access_token, refresh_token));
-    }
-    
-    /// aka TokenCredential.OnRefreshTokenExpired
-    fn trigger_refresh_token_expired(&self) {
-        let _ = self.refresh_token_expired_tx.send(());
+
+    /// Notified when refreshed tokens could not be persisted, see [`TokenPersistenceTrait`].
+    pub fn subscribe_token_persistence_failed(&self) -> broadcast::Receiver<Arc<anyhow::Error>> {
+        self.token_persistence_failed_tx.subscribe()
     }
 
     pub async fn get_refreshed_access_token(
@@ -87,84 +173,186 @@ impl TokenCredential {
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<String> {
         let current_tokens_task = self.tokens_task.read().await.clone();
+        let current_state = Self::wait(current_tokens_task.clone(), &cancellation_token).await?;
 
-        let (current_access_token, current_refresh_token) = tokio::select! {
-            _ = cancellation_token.cancelled() => {
-                return Err(anyhow::anyhow!("Operation cancelled"));
-            }
-            result = async {
-                let tokens = current_tokens_task.get().ok_or_else(|| anyhow::anyhow!("Tokens not initialized"))?;
-                Ok::<_, anyhow::Error>((tokens.0.clone(), tokens.1.clone()))
-            } => result?
-        };
+        // Tokens the server already rotated to must not be refreshed again before they are persisted
+        if !current_state.is_persisted {
+            let (access_token, _) = self
+                .persist_pending_tokens(current_tokens_task, current_state.tokens, cancellation_token)
+                .await?;
+            return Ok(access_token);
+        }
+
+        let (current_access_token, current_refresh_token) = current_state.tokens;
 
         let is_likely_already_refreshed = current_access_token != rejected_access_token;
         if is_likely_already_refreshed {
             return Ok(current_access_token);
         }
 
-        let refreshed_tokens_task = Arc::new(OnceCell::new());
-        let refreshed_task_clone = refreshed_tokens_task.clone();
-        let client = self.client.clone();
-        let session_id = self.session_id.clone();
-        let current_access = current_access_token.clone();
-        let current_refresh = current_refresh_token.clone();
-        let cancellation = cancellation_token.clone();
-
-        let refresh_handle = tokio::spawn(async move {
-            let result = async {
-                let response = client
-                    .refresh_session(
-                        session_id,
-                        current_access.clone(),
-                        current_refresh.clone(),
-                        cancellation,
-                    )
-                    .await?;
-                Ok::<_, anyhow::Error>((response.access_token, response.refresh_token))
+        // Only the first caller to get here starts a refresh, the others wait for its outcome,
+        // because refresh tokens rotate and a second refresh would invalidate the first one.
+        let mut tokens_task_guard = self.tokens_task.write().await;
+        let tokens_task_unchanged = tokens_task_guard.ptr_eq(&current_tokens_task);
+
+        if tokens_task_unchanged {
+            // The refresh is spawned so that it runs to completion, persistence and notifications included,
+            // even if the caller that started it is cancelled.
+            let refresh_handle = tokio::spawn(Self::refresh(
+                self.client.clone(),
+                self.session_id.clone(),
+                self.persistence.clone(),
+                (current_access_token.clone(), current_refresh_token.clone()),
+                self.tokens_refreshed_tx.clone(),
+                self.refresh_token_expired_tx.clone(),
+                self.token_persistence_failed_tx.clone(),
+            ));
+
+            let current_tokens = (current_access_token, current_refresh_token);
+            *tokens_task_guard = async move {
+                refresh_handle
+                    .await
+                    .unwrap_or_else(|_| TokensState::persisted(current_tokens))
             }
+            .boxed()
+            .shared();
+        }
+
+        let refreshed_tokens_task = tokens_task_guard.clone();
+        drop(tokens_task_guard);
+
+        let refreshed_state = Self::wait(refreshed_tokens_task, &cancellation_token).await?;
+        if !refreshed_state.is_persisted {
+            return Err(anyhow::anyhow!("Refreshed session tokens could not be persisted"));
+        }
+
+        let (access_token, _) = refreshed_state.tokens;
+        if access_token == rejected_access_token {
+            return Err(anyhow::anyhow!("Failed to refresh the session tokens"));
+        }
+
+        Ok(access_token)
+    }
+
+    /// Returns the refreshed tokens, or the current ones if the refresh failed.
+    async fn refresh(
+        client: Arc<dyn AuthenticationApiClientTrait>,
+        session_id: SessionId,
+        persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+        current_tokens: (String, String),
+        tokens_refreshed_tx: broadcast::Sender<(String, String)>,
+        refresh_token_expired_tx: broadcast::Sender<()>,
+        token_persistence_failed_tx: broadcast::Sender<Arc<anyhow::Error>>,
+    ) -> TokensState {
+        let response = client
+            .refresh_session(
+                session_id.clone(),
+                current_tokens.0.clone(),
+                current_tokens.1.clone(),
+                CancellationToken::new(),
+            )
             .await;
 
-            match result {
-                Ok(tokens) => {
-                    let _ = refreshed_task_clone.set(tokens);
-                }
-                Err(_) => {
-                    let _ = refreshed_task_clone.set((current_access, current_refresh));
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("Session token refresh failed: {:#}", e);
+
+                if Self::is_refresh_token_rejection(&e) {
+                    // aka TokenCredential.OnRefreshTokenExpired
+                    let _ = refresh_token_expired_tx.send(());
                 }
+
+                return TokensState::persisted(current_tokens);
             }
-        });
+        };
 
-        let mut tokens_task_guard = self.tokens_task.write().await;
-        let tokens_task_replaced = Arc::ptr_eq(&*tokens_task_guard, &current_tokens_task);
-        
-        if tokens_task_replaced {
-            *tokens_task_guard = refreshed_tokens_task.clone();
-        }
-        drop(tokens_task_guard);
+        let tokens = (response.access_token, response.refresh_token);
 
-        tokio::select! {
-            _ = cancellation_token.cancelled() => {
-                return Err(anyhow::anyhow!("Operation cancelled"));
-            }
-            result = refresh_handle => {
-                result.map_err(|e| anyhow::anyhow!("Refresh task panicked: {}", e))?;
-            }
+        Self::persist_refreshed_tokens(
+            session_id,
+            persistence,
+            tokens,
+            tokens_refreshed_tx,
+            token_persistence_failed_tx,
+        )
+        .await
+    }
+
+    /// Write-ahead: the previous refresh token stops working once the server has rotated it,
+    /// so the new pair must be stored durably before anything gets to use it.
+    async fn persist_refreshed_tokens(
+        session_id: SessionId,
+        persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+        tokens: (String, String),
+        tokens_refreshed_tx: broadcast::Sender<(String, String)>,
+        token_persistence_failed_tx: broadcast::Sender<Arc<anyhow::Error>>,
+    ) -> TokensState {
+        if let Some(persistence) = persistence
+            && let Err(e) = Self::persist_tokens(persistence.as_ref(), &session_id, &tokens).await
+        {
+            // The previous tokens are already invalidated by the server, so the new ones are kept until they persist
+            log::error!("Refreshed session tokens could not be persisted: {:#}", e);
+            let _ = token_persistence_failed_tx.send(Arc::new(e));
+
+            return TokensState { tokens, is_persisted: false };
         }
 
-        let (access_token, refresh_token) = refreshed_tokens_task
-            .get()
-            .ok_or_else(|| anyhow::anyhow!("Failed to get refreshed tokens"))?
-            .clone();
+        // aka TokenCredential.OnTokensRefreshed
+        let _ = tokens_refreshed_tx.send(tokens.clone());
+
+        TokensState::persisted(tokens)
+    }
+
+    async fn persist_tokens(
+        persistence: &dyn TokenPersistenceTrait,
+        session_id: &SessionId,
+        (access_token, refresh_token): &(String, String),
+    ) -> anyhow::Result<()> {
+        let mut attempt = 1;
+        loop {
+            let result = persistence
+                .persist_tokens(session_id.clone(), access_token.clone(), refresh_token.clone())
+                .await;
 
-        if tokens_task_replaced {
-            self.trigger_tokens_refreshed(access_token.clone(), refresh_token);
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= PERSIST_TOKENS_ATTEMPTS => {
+                    return Err(e.context("Failed to persist refreshed tokens"));
+                }
+                Err(e) => {
+                    log::warn!("Persisting refreshed tokens failed, retrying: {:#}", e);
+                    tokio::time::sleep(PERSIST_TOKENS_RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+            }
         }
+    }
 
-        Ok(access_token)
+    fn is_refresh_token_rejection(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<ProtonApiError>().is_some_and(|e| {
+            e.code == ResponseCode::InvalidRefreshToken
+                || e.http_status == http::StatusCode::BAD_REQUEST
+                || e.http_status == http::StatusCode::UNPROCESSABLE_ENTITY
+        })
     }
 }
 
+/// Durable storage for the session tokens, called every time they are refreshed.
+///
+/// The new tokens are only used once `persist_tokens` has returned. It is retried a few times on failure,
+/// after which the failure is reported through [`TokenCredential::subscribe_token_persistence_failed`]
+/// and requests fail. The server has already invalidated the previous tokens, so the new ones are kept
+/// in memory meanwhile, and persisting them is tried again on the next request.
+#[async_trait::async_trait]
+pub trait TokenPersistenceTrait: Send + Sync {
+    async fn persist_tokens(
+        &self,
+        session_id: SessionId,
+        access_token: String,
+        refresh_token: String,
+    ) -> anyhow::Result<()>;
+}
 
 #[async_trait::async_trait]
 pub trait AuthenticationApiClientTrait: Send + Sync {
@@ -176,8 +364,10 @@ pub trait AuthenticationApiClientTrait: Send + Sync {
 
     async fn authenticate(
         &self,
+        username: String,
         initiation_response: SesisonInitiationResponse,
         srp_client_handshake: proton_crypto::srp::ClientProof,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<AuthenticationResponse>;
 
     async fn refresh_session(
@@ -188,3 +378,352 @@ pub trait AuthenticationApiClientTrait: Send + Sync {
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<RefreshSessionResponse>;
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    /// Rotates both tokens on every refresh, like the real API.
+    struct RotatingAuthenticationApiClient {
+        refresh_count: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl AuthenticationApiClientTrait for RotatingAuthenticationApiClient {
+        async fn initiate_session(
+            &self,
+            _username: String,
+            _cancellation_token: CancellationToken,
+        ) -> anyhow::Result<SesisonInitiationResponse> {
+            unimplemented!()
+        }
+
+        async fn authenticate(
+            &self,
+            _username: String,
+            _initiation_response: SesisonInitiationResponse,
+            _srp_client_handshake: proton_crypto::srp::ClientProof,
+            _cancellation_token: CancellationToken,
+        ) -> anyhow::Result<AuthenticationResponse> {
+            unimplemented!()
+        }
+
+        async fn refresh_session(
+            &self,
+            _session_id: SessionId,
+            _access_token: String,
+            _refresh_token: String,
+            _cancellation_token: CancellationToken,
+        ) -> anyhow::Result<RefreshSessionResponse> {
+            let count = self.refresh_count.fetch_add(1, Ordering::SeqCst) + 1;
+
+            Ok(RefreshSessionResponse {
+                access_token: format!("access-{}", count),
+                refresh_token: format!("refresh-{}", count),
+                scopes: vec![],
+            })
+        }
+    }
+
+    /// Stands in for the host's durable storage.
+    #[derive(Default)]
+    struct TestTokenPersistence {
+        stored_tokens: Mutex<Option<(String, String)>>,
+        persist_started: Notify,
+        release: Option<Notify>,
+        /// Number of upcoming calls that fail, as if the storage was unavailable.
+        failures: AtomicUsize,
+        attempts: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl TokenPersistenceTrait for TestTokenPersistence {
+        async fn persist_tokens(
+            &self,
+            _session_id: SessionId,
+            access_token: String,
+            refresh_token: String,
+        ) -> anyhow::Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            self.persist_started.notify_one();
+
+            if let Some(release) = &self.release {
+                release.notified().await;
+            }
+
+            let is_failing = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| failures.checked_sub(1))
+                .is_ok();
+            if is_failing {
+                return Err(anyhow::anyhow!("Storage is unavailable"));
+            }
+
+            *self.stored_tokens.lock().unwrap() = Some((access_token, refresh_token));
+            Ok(())
+        }
+    }
+
+    fn create_credential(
+        persistence: Arc<TestTokenPersistence>,
+    ) -> (Arc<TokenCredential>, Arc<RotatingAuthenticationApiClient>) {
+        let client = Arc::new(RotatingAuthenticationApiClient {
+            refresh_count: AtomicUsize::new(0),
+        });
+
+        let credential = TokenCredential::new(
+            client.clone(),
+            SessionId::new("session".to_string()),
+            "access-0".to_string(),
+            "refresh-0".to_string(),
+            Some(persistence),
+        );
+
+        (Arc::new(credential), client)
+    }
+
+    #[tokio::test]
+    async fn refreshed_tokens_are_not_used_before_being_persisted() {
+        let persistence = Arc::new(TestTokenPersistence {
+            release: Some(Notify::new()),
+            ..Default::default()
+        });
+        let (credential, _) = create_credential(persistence.clone());
+
+        let refresh = tokio::spawn({
+            let credential = credential.clone();
+            async move {
+                credential
+                    .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+                    .await
+            }
+        });
+
+        persistence.persist_started.notified().await;
+
+        let tokens = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            credential.get_tokens(CancellationToken::new()),
+        )
+        .await;
+        assert!(tokens.is_err(), "tokens were handed out before being persisted");
+
+        persistence.release.as_ref().unwrap().notify_one();
+
+        assert_eq!(refresh.await.unwrap().unwrap(), "access-1");
+        assert_eq!(
+            *persistence.stored_tokens.lock().unwrap(),
+            Some(("access-1".to_string(), "refresh-1".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_persistence_failures_are_retried() {
+        let persistence = Arc::new(TestTokenPersistence {
+            failures: AtomicUsize::new(PERSIST_TOKENS_ATTEMPTS as usize - 1),
+            ..Default::default()
+        });
+        let (credential, _) = create_credential(persistence.clone());
+        let mut token_persistence_failed_rx = credential.subscribe_token_persistence_failed();
+
+        let access_token = credential
+            .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(access_token, "access-1");
+        assert_eq!(persistence.attempts.load(Ordering::SeqCst), PERSIST_TOKENS_ATTEMPTS as usize);
+        assert_eq!(
+            *persistence.stored_tokens.lock().unwrap(),
+            Some(("access-1".to_string(), "refresh-1".to_string()))
+        );
+        assert!(token_persistence_failed_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_fail_until_refreshed_tokens_are_persisted() {
+        let persistence = Arc::new(TestTokenPersistence {
+            failures: AtomicUsize::new(PERSIST_TOKENS_ATTEMPTS as usize),
+            ..Default::default()
+        });
+        let (credential, client) = create_credential(persistence.clone());
+        let mut tokens_refreshed_rx = credential.subscribe_tokens_refreshed();
+        let mut token_persistence_failed_rx = credential.subscribe_token_persistence_failed();
+
+        let result = credential
+            .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(persistence.attempts.load(Ordering::SeqCst), PERSIST_TOKENS_ATTEMPTS as usize);
+        assert_eq!(*persistence.stored_tokens.lock().unwrap(), None);
+        assert!(token_persistence_failed_rx.try_recv().is_ok());
+        assert!(tokens_refreshed_rx.try_recv().is_err());
+
+        // The server invalidated the previous pair, so the next request persists the new one instead of refreshing
+        assert_eq!(
+            credential.get_tokens(CancellationToken::new()).await.unwrap(),
+            ("access-1".to_string(), "refresh-1".to_string())
+        );
+        assert_eq!(client.refresh_count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *persistence.stored_tokens.lock().unwrap(),
+            Some(("access-1".to_string(), "refresh-1".to_string()))
+        );
+        assert_eq!(
+            tokens_refreshed_rx.try_recv().unwrap(),
+            ("access-1".to_string(), "refresh-1".to_string())
+        );
+
+        let access_token = credential
+            .get_refreshed_access_token("access-1".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(access_token, "access-2");
+    }
+
+    /// The runtime shutting down while tokens are being persisted stands for the process crashing.
+    #[test]
+    fn crash_between_refresh_and_persist_hands_out_no_unpersisted_tokens() {
+        let persistence = Arc::new(TestTokenPersistence {
+            release: Some(Notify::new()),
+            ..Default::default()
+        });
+        let (credential, client) = create_credential(persistence.clone());
+        let mut tokens_refreshed_rx = credential.subscribe_tokens_refreshed();
+        let handed_out_tokens = Arc::new(Mutex::new(Vec::new()));
+
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            tokio::spawn({
+                let credential = credential.clone();
+                async move {
+                    credential
+                        .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+                        .await
+                }
+            });
+            persistence.persist_started.notified().await;
+
+            tokio::spawn({
+                let credential = credential.clone();
+                let handed_out_tokens = handed_out_tokens.clone();
+                async move {
+                    let tokens = credential.get_tokens(CancellationToken::new()).await;
+                    handed_out_tokens.lock().unwrap().push(tokens);
+                }
+            });
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        });
+        runtime.shutdown_background();
+
+        assert_eq!(client.refresh_count.load(Ordering::SeqCst), 1);
+        assert_eq!(*persistence.stored_tokens.lock().unwrap(), None);
+        assert!(handed_out_tokens.lock().unwrap().is_empty());
+        assert!(tokens_refreshed_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_is_notified_even_if_its_caller_is_cancelled() {
+        let persistence = Arc::new(TestTokenPersistence {
+            release: Some(Notify::new()),
+            ..Default::default()
+        });
+        let (credential, _) = create_credential(persistence.clone());
+        let mut tokens_refreshed_rx = credential.subscribe_tokens_refreshed();
+
+        let cancellation_token = CancellationToken::new();
+        let refresh = tokio::spawn({
+            let credential = credential.clone();
+            let cancellation_token = cancellation_token.clone();
+            async move {
+                credential
+                    .get_refreshed_access_token("access-0".to_string(), cancellation_token)
+                    .await
+            }
+        });
+
+        persistence.persist_started.notified().await;
+        cancellation_token.cancel();
+        assert!(refresh.await.unwrap().is_err());
+
+        persistence.release.as_ref().unwrap().notify_one();
+
+        let refreshed_tokens = tokio::time::timeout(std::time::Duration::from_secs(1), tokens_refreshed_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(refreshed_tokens, ("access-1".to_string(), "refresh-1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn replaced_tokens_wait_for_the_refresh_in_flight() {
+        let persistence = Arc::new(TestTokenPersistence {
+            release: Some(Notify::new()),
+            ..Default::default()
+        });
+        let (credential, _) = create_credential(persistence.clone());
+
+        let refresh = tokio::spawn({
+            let credential = credential.clone();
+            async move {
+                credential
+                    .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+                    .await
+            }
+        });
+        persistence.persist_started.notified().await;
+
+        let replace = tokio::spawn({
+            let credential = credential.clone();
+            async move { credential.replace_tokens("login-access".to_string(), "login-refresh".to_string()).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!replace.is_finished(), "tokens were replaced while a refresh was in flight");
+
+        persistence.release.as_ref().unwrap().notify_one();
+        replace.await.unwrap();
+        refresh.await.unwrap().unwrap();
+
+        assert_eq!(
+            *persistence.stored_tokens.lock().unwrap(),
+            Some(("access-1".to_string(), "refresh-1".to_string()))
+        );
+        assert_eq!(
+            credential.get_tokens(CancellationToken::new()).await.unwrap(),
+            ("login-access".to_string(), "login-refresh".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_rejections_share_a_single_refresh() {
+        let persistence = Arc::new(TestTokenPersistence::default());
+        let (credential, client) = create_credential(persistence.clone());
+
+        let refreshes = (0..32).map(|_| {
+            let credential = credential.clone();
+            tokio::spawn(async move {
+                credential
+                    .get_refreshed_access_token("access-0".to_string(), CancellationToken::new())
+                    .await
+            })
+        });
+
+        for refresh in futures::future::join_all(refreshes).await {
+            assert_eq!(refresh.unwrap().unwrap(), "access-1");
+        }
+
+        assert_eq!(client.refresh_count.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *persistence.stored_tokens.lock().unwrap(),
+            Some(("access-1".to_string(), "refresh-1".to_string()))
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Protobuf request interface equivalent to the C# `Proton.Sdk.CExports` bindings.
+//!
+//! Requests and responses are the `proton.sdk.Request` and `proton.sdk.Response` messages from
+//! `protos/proton.sdk.proto`, so hosts written against the C# bindings can switch to this crate.
+
+mod cancellation;
+mod error;
+mod exports;
+mod gate;
+mod handles;
+mod host;
+mod http;
+mod logging;
+mod session;
+mod stream;
+mod telemetry;
+
+use anyhow::Context;
+use prost::Message;
+use prost_types::Any;
+
+use crate::proton::{self, request::Payload};
+
+pub use exports::{ArrayAction, ByteArray, RequestAction, proton_sdk_complete_request, proton_sdk_handle_request};
+
+const INT32_VALUE_TYPE_URL: &str = "type.googleapis.com/google.protobuf.Int32Value";
+const INT64_VALUE_TYPE_URL: &str = "type.googleapis.com/google.protobuf.Int64Value";
+
+/// Decodes a `proton.sdk.Request`, executes it and returns the encoded `proton.sdk.Response`.
+pub async fn handle_request(request: &[u8]) -> Vec<u8> {
+    let result = match proton::Request::decode(request) {
+        Ok(request) => dispatch(request).await,
+        Err(e) => Err(e.into()),
+    };
+
+    encode_response(result)
+}
+
+async fn dispatch(request: proton::Request) -> anyhow::Result<Option<Any>> {
+    let Some(payload) = request.payload else {
+        return Err(anyhow::anyhow!("Request has no payload"));
+    };
+
+    match payload {
+        Payload::CancellationTokenSourceCreate(request) => cancellation::create(request),
+        Payload::CancellationTokenSourceCancel(request) => cancellation::cancel(request),
+        Payload::CancellationTokenSourceFree(request) => cancellation::free(request).await,
+
+        Payload::SessionBegin(request) => session::begin(request).await,
+        Payload::SessionResume(request) => session::resume(request).await,
+        Payload::SessionRenew(request) => session::renew(request),
+        Payload::SessionEnd(request) => session::end(request).await,
+        Payload::SessionFree(request) => session::free(request).await,
+        Payload::SessionTokensRefreshedSubscribe(request) => session::subscribe_tokens_refreshed(request),
+        Payload::SessionTokensRefreshedUnsubscribe(request) => session::unsubscribe_tokens_refreshed(request).await,
+
+        Payload::StreamRead(request) => stream::read(request).await,
+        Payload::StreamSeek(request) => stream::seek(request).await,
+
+        Payload::LoggerProviderCreate(request) => logging::create_provider(request),
+        Payload::LoggerProviderFree(request) => logging::free_provider(request).await,
+    }
+}
+
+fn encode_response(result: anyhow::Result<Option<Any>>) -> Vec<u8> {
+    let result = match result {
+        Ok(value) => value.map(proton::response::Result::Value),
+        Err(e) => Some(proton::response::Result::Error(error::to_proto_error(&e))),
+    };
+
+    proton::Response { result }.encode_to_vec()
+}
+
+pub(crate) fn int32_value(value: i32) -> Any {
+    Any {
+        type_url: INT32_VALUE_TYPE_URL.to_string(),
+        value: value.encode_to_vec(),
+    }
+}
+
+pub(crate) fn int64_value(value: i64) -> Any {
+    Any {
+        type_url: INT64_VALUE_TYPE_URL.to_string(),
+        value: value.encode_to_vec(),
+    }
+}
+
+/// Decodes the value of a response, such as one the host sent back for a request from the SDK.
+pub(crate) fn unpack<T: Message + Default>(value: Option<Any>) -> anyhow::Result<T> {
+    let value = value.context("The response has no value")?;
+
+    Ok(T::decode(value.value.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PasswordMode,
+        bindings::handles::HANDLES,
+        proton::{ErrorDomain, response::Result as ResponseResult},
+        session::ProtonAPISession,
+    };
+
+    async fn send(payload: Payload) -> Option<ResponseResult> {
+        let request = proton::Request { payload: Some(payload) }.encode_to_vec();
+
+        proton::Response::decode(handle_request(&request).await.as_slice()).unwrap().result
+    }
+
+    fn handle(result: Option<ResponseResult>) -> i64 {
+        match result {
+            Some(ResponseResult::Value(value)) => unpack(Some(value)).unwrap(),
+            result => panic!("Expected a handle, got {:?}", result),
+        }
+    }
+
+    fn error(result: Option<ResponseResult>) -> proton::Error {
+        match result {
+            Some(ResponseResult::Error(error)) => error,
+            result => panic!("Expected an error, got {:?}", result),
+        }
+    }
+
+    fn resume_request(password_mode: proton::PasswordMode) -> proton::SessionResumeRequest {
+        proton::SessionResumeRequest {
+            username: "alice".to_string(),
+            app_version: "1.0.0".to_string(),
+            session_id: "session-1".to_string(),
+            user_id: "user-1".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            is_waiting_for_data_password: true,
+            password_mode: password_mode as i32,
+            options: Some(proton::ProtonClientOptions {
+                tls_policy: proton::ProtonClientTlsPolicy::NoCertificatePinning as i32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn undecodable_and_empty_requests_are_rejected() {
+        let response = proton::Response::decode(handle_request(&[0xff, 0xff]).await.as_slice()).unwrap();
+        assert_eq!(error(response.result).domain, ErrorDomain::Serialization as i32);
+
+        let request = proton::Request { payload: None }.encode_to_vec();
+        let response = proton::Response::decode(handle_request(&request).await.as_slice()).unwrap();
+        assert_eq!(error(response.result).message, "Request has no payload");
+    }
+
+    #[tokio::test]
+    async fn cancellation_token_sources_are_dispatched() {
+        let source_handle = handle(
+            send(Payload::CancellationTokenSourceCreate(proton::CancellationTokenSourceCreateRequest {})).await,
+        );
+
+        let result = send(Payload::CancellationTokenSourceCancel(proton::CancellationTokenSourceCancelRequest {
+            cancellation_token_source_handle: source_handle,
+        }))
+        .await;
+        assert!(result.is_none());
+
+        let free_request = proton::CancellationTokenSourceFreeRequest {
+            cancellation_token_source_handle: source_handle,
+        };
+        assert!(send(Payload::CancellationTokenSourceFree(free_request)).await.is_none());
+        assert_eq!(
+            error(send(Payload::CancellationTokenSourceFree(free_request)).await).r#type,
+            "ArgumentException"
+        );
+    }
+
+    #[tokio::test]
+    async fn resumed_session_has_the_requested_password_mode_which_renewal_keeps() {
+        let session_handle = handle(send(Payload::SessionResume(resume_request(proton::PasswordMode::Dual))).await);
+        let session = HANDLES.get::<ProtonAPISession>(session_handle).unwrap();
+        assert_eq!(session.snapshot().password_mode, PasswordMode::Dual);
+
+        let renewed_session_handle = handle(
+            send(Payload::SessionRenew(proton::SessionRenewRequest {
+                old_session_handle: session_handle,
+                session_id: "session-2".to_string(),
+                access_token: "access-2".to_string(),
+                refresh_token: "refresh-2".to_string(),
+                ..Default::default()
+            }))
+            .await,
+        );
+        let renewed_snapshot = HANDLES.get::<ProtonAPISession>(renewed_session_handle).unwrap().snapshot();
+        assert_eq!(renewed_snapshot.session_id.raw(), "session-2");
+        assert_eq!(renewed_snapshot.username, "alice");
+        assert_eq!(renewed_snapshot.password_mode, PasswordMode::Dual);
+
+        for session_handle in [session_handle, renewed_session_handle] {
+            let result = send(Payload::SessionFree(proton::SessionFreeRequest { session_handle })).await;
+            assert!(result.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_defaults_to_single_password_mode() {
+        let session_handle = handle(send(Payload::SessionResume(resume_request(proton::PasswordMode::Unspecified))).await);
+
+        let session = HANDLES.get::<ProtonAPISession>(session_handle).unwrap();
+        assert_eq!(session.snapshot().password_mode, PasswordMode::Single);
+
+        HANDLES.remove::<ProtonAPISession>(session_handle).unwrap();
+    }
+}
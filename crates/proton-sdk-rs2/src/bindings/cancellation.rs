@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use prost_types::Any;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    bindings::{gate::CallbackGate, handles::HANDLES, int64_value},
+    proton::{self, request::Payload},
+};
+
+/// Cancellation token handed to the host, which also gates the completion callbacks of the requests using it.
+pub(crate) struct CancellationTokenSource {
+    token: CancellationToken,
+    completion_gate: CallbackGate,
+}
+
+impl CancellationTokenSource {
+    fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            completion_gate: CallbackGate::new(),
+        }
+    }
+
+    /// Runs the completion callback of a request, unless the source was freed in the meantime.
+    pub(crate) fn complete(&self, callback: impl FnOnce()) {
+        self.completion_gate.run(callback);
+    }
+
+    /// Cancels the operations still using the source and waits for callbacks in progress to return,
+    /// after which none of them is invoked anymore.
+    async fn free(&self) {
+        self.completion_gate.close().await;
+        self.token.cancel();
+    }
+}
+
+/// Resolves the token of a source created by the host, 0 meaning the operation cannot be cancelled.
+pub(super) fn get_cancellation_token(cancellation_token_source_handle: i64) -> anyhow::Result<CancellationToken> {
+    if cancellation_token_source_handle == 0 {
+        return Ok(CancellationToken::new());
+    }
+
+    Ok(HANDLES
+        .get::<CancellationTokenSource>(cancellation_token_source_handle)?
+        .token
+        .clone())
+}
+
+/// Source whose lifetime bounds the completion callback of the request, if the request refers to one.
+pub(super) fn get_request_source(payload: &Payload) -> Option<Arc<CancellationTokenSource>> {
+    let handle = match payload {
+        Payload::SessionBegin(request) => request.cancellation_token_source_handle,
+        Payload::SessionEnd(request) => request.cancellation_token_source_handle,
+        _ => return None,
+    };
+
+    // An invalid handle fails the request itself, which is reported like any other error
+    HANDLES.get::<CancellationTokenSource>(handle).ok()
+}
+
+pub(super) fn create(_request: proton::CancellationTokenSourceCreateRequest) -> anyhow::Result<Option<Any>> {
+    Ok(Some(int64_value(HANDLES.add(CancellationTokenSource::new()))))
+}
+
+pub(super) fn cancel(request: proton::CancellationTokenSourceCancelRequest) -> anyhow::Result<Option<Any>> {
+    HANDLES
+        .get::<CancellationTokenSource>(request.cancellation_token_source_handle)?
+        .token
+        .cancel();
+
+    Ok(None)
+}
+
+pub(super) async fn free(request: proton::CancellationTokenSourceFreeRequest) -> anyhow::Result<Option<Any>> {
+    HANDLES
+        .remove::<CancellationTokenSource>(request.cancellation_token_source_handle)?
+        .free()
+        .await;
+
+    Ok(None)
+}
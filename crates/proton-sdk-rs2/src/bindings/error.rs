@@ -0,0 +1,163 @@
+use std::error::Error as StdError;
+
+use crate::{
+    OperationCancelledError,
+    api::ProtonApiError,
+    bindings::{handles::HandleError, host::HostError},
+    proton::{self, ErrorDomain},
+};
+
+/// Converts an error and its chain of causes into nested `proton.sdk.Error` messages.
+pub(super) fn to_proto_error(error: &anyhow::Error) -> proton::Error {
+    convert_chain(error.chain()).unwrap_or_default()
+}
+
+fn convert_chain(mut chain: anyhow::Chain<'_>) -> Option<proton::Error> {
+    let error = chain.next()?;
+
+    // Errors coming from the host are passed back unchanged
+    if let Some(host_error) = error.downcast_ref::<HostError>() {
+        return Some(host_error.0.clone());
+    }
+
+    let inner_error = convert_chain(chain);
+
+    let (type_name, domain, primary_code, secondary_code) = classify(error);
+
+    // Context added on top of a typed error inherits its domain
+    let domain = match (&inner_error, domain) {
+        (Some(inner_error), ErrorDomain::Undefined) => inner_error.domain,
+        _ => domain as i32,
+    };
+
+    Some(proton::Error {
+        r#type: type_name.to_string(),
+        message: error.to_string(),
+        domain,
+        primary_code,
+        secondary_code,
+        context: String::new(),
+        inner_error: inner_error.map(Box::new),
+        additional_data: None,
+    })
+}
+
+fn classify(error: &(dyn StdError + 'static)) -> (&'static str, ErrorDomain, i64, i64) {
+    if error.is::<OperationCancelledError>() {
+        return ("OperationCanceledException", ErrorDomain::SuccessfulCancellation, 0, 0);
+    }
+
+    if let Some(e) = error.downcast_ref::<ProtonApiError>() {
+        return ("ProtonApiException", ErrorDomain::Api, e.raw_code, e.http_status.as_u16().into());
+    }
+
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        let domain = if e.is_connect() || e.is_timeout() {
+            ErrorDomain::Network
+        } else {
+            ErrorDomain::Transport
+        };
+        let status = e.status().map(|status| status.as_u16().into()).unwrap_or_default();
+
+        return ("HttpRequestException", domain, status, 0);
+    }
+
+    if error.is::<HandleError>() {
+        return ("ArgumentException", ErrorDomain::Undefined, 0, 0);
+    }
+
+    if error.is::<serde_json::Error>() || error.is::<prost::DecodeError>() || error.is::<base64::DecodeError>() {
+        return ("SerializationException", ErrorDomain::Serialization, 0, 0);
+    }
+
+    ("Exception", ErrorDomain::Undefined, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+    use crate::api::ResponseCode;
+
+    fn api_error() -> ProtonApiError {
+        ProtonApiError {
+            code: ResponseCode::InvalidValue,
+            raw_code: 2001,
+            http_status: http::StatusCode::UNPROCESSABLE_ENTITY,
+            message: Some("Invalid value".to_string()),
+        }
+    }
+
+    #[test]
+    fn cancellation_is_a_successful_cancellation() {
+        let error = to_proto_error(&OperationCancelledError.into());
+
+        assert_eq!(error.r#type, "OperationCanceledException");
+        assert_eq!(error.domain, ErrorDomain::SuccessfulCancellation as i32);
+        assert!(error.inner_error.is_none());
+    }
+
+    #[test]
+    fn api_errors_carry_the_response_and_http_status_codes() {
+        let error = to_proto_error(&api_error().into());
+
+        assert_eq!(error.r#type, "ProtonApiException");
+        assert_eq!(error.domain, ErrorDomain::Api as i32);
+        assert_eq!(error.primary_code, 2001);
+        assert_eq!(error.secondary_code, 422);
+    }
+
+    #[test]
+    fn context_inherits_the_domain_of_the_error_it_wraps() {
+        let result: anyhow::Result<()> = Err(api_error().into());
+        let error = to_proto_error(&result.context("Failed to get the user").unwrap_err());
+
+        assert_eq!(error.r#type, "Exception");
+        assert_eq!(error.message, "Failed to get the user");
+        assert_eq!(error.domain, ErrorDomain::Api as i32);
+
+        let inner_error = error.inner_error.unwrap();
+        assert_eq!(inner_error.r#type, "ProtonApiException");
+        assert_eq!(inner_error.primary_code, 2001);
+    }
+
+    #[test]
+    fn host_errors_are_passed_back_unchanged() {
+        let host_error = proton::Error {
+            r#type: "IOException".to_string(),
+            message: "Disk full".to_string(),
+            domain: ErrorDomain::Transport as i32,
+            ..Default::default()
+        };
+        let result: anyhow::Result<()> = Err(HostError(host_error.clone()).into());
+
+        let error = to_proto_error(&result.context("Failed to read the stream").unwrap_err());
+
+        assert_eq!(error.domain, ErrorDomain::Transport as i32);
+        assert_eq!(*error.inner_error.unwrap(), host_error);
+    }
+
+    #[test]
+    fn argument_and_serialization_errors_are_classified() {
+        let handle_error = HandleError::Invalid {
+            handle: 42,
+            expected: "session",
+        };
+        assert_eq!(to_proto_error(&handle_error.into()).r#type, "ArgumentException");
+
+        let json_error = serde_json::from_str::<u32>("nope").unwrap_err();
+        let error = to_proto_error(&json_error.into());
+        assert_eq!(error.r#type, "SerializationException");
+        assert_eq!(error.domain, ErrorDomain::Serialization as i32);
+    }
+
+    #[test]
+    fn other_errors_are_undefined() {
+        let error = to_proto_error(&anyhow::anyhow!("Something happened"));
+
+        assert_eq!(error.r#type, "Exception");
+        assert_eq!(error.message, "Something happened");
+        assert_eq!(error.domain, ErrorDomain::Undefined as i32);
+    }
+}
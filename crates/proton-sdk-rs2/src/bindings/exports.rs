@@ -0,0 +1,184 @@
+//! C ABI surface of the bindings, see `include/proton_sdk.h` for the generated declarations.
+
+use std::{
+    ffi::c_void,
+    panic::AssertUnwindSafe,
+    sync::LazyLock,
+};
+
+use futures::FutureExt;
+use prost::Message;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{
+    bindings::{cancellation, dispatch, encode_response, host},
+    proton,
+};
+
+/// Runtime executing every request received through the C ABI, so hosts need not run one themselves.
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    Builder::new_multi_thread()
+        .thread_name("proton-sdk")
+        .enable_all()
+        .build()
+        .expect("Failed to start the bindings runtime")
+});
+
+/// Borrowed view over bytes owned by whoever passes it; only valid for the duration of the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ByteArray {
+    pub pointer: *const u8,
+    pub length: usize,
+}
+
+impl ByteArray {
+    fn from_slice(bytes: &[u8]) -> Self {
+        Self {
+            pointer: bytes.as_ptr(),
+            length: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `pointer` must be valid for reads of `length` bytes, or `length` must be 0.
+    pub(crate) unsafe fn as_slice<'a>(&self) -> &'a [u8] {
+        if self.pointer.is_null() || self.length == 0 {
+            return &[];
+        }
+
+        unsafe { std::slice::from_raw_parts(self.pointer, self.length) }
+    }
+}
+
+/// Callback receiving bytes from the SDK, along with the opaque state the host registered it with.
+pub type ArrayAction = extern "C" fn(state: *const c_void, array: ByteArray);
+
+/// Callback receiving a request from the SDK, which the host answers by passing an encoded
+/// `proton.sdk.Response` to [`proton_sdk_complete_request`] along with `completion_handle`.
+pub type RequestAction = extern "C" fn(state: *const c_void, request: ByteArray, completion_handle: i64);
+
+/// [`ArrayAction`] registered along with its state.
+///
+/// The `*_action` fields of the proto that refer to `array_action` carry the bare function pointer,
+/// and the `callback_state` field of the same message carries the state it is invoked with. Nothing
+/// can check these pointers, so the host is trusted with them as with every pointer of the protocol.
+#[derive(Clone, Copy)]
+pub(crate) struct ArrayCallback {
+    pub(crate) state: *const c_void,
+    pub(crate) action: ArrayAction,
+}
+
+/// [`RequestAction`] registered along with its state, see [`ArrayCallback`].
+#[derive(Clone, Copy)]
+pub(crate) struct RequestCallback {
+    pub(crate) state: *const c_void,
+    pub(crate) action: RequestAction,
+}
+
+// The host guarantees that the state it registers may be used from any thread
+unsafe impl Send for ArrayCallback {}
+unsafe impl Sync for ArrayCallback {}
+unsafe impl Send for RequestCallback {}
+unsafe impl Sync for RequestCallback {}
+
+impl ArrayCallback {
+    pub(crate) fn new(state: *const c_void, action: ArrayAction) -> Self {
+        Self { state, action }
+    }
+
+    /// Reads the callback of an `*_action` field and its `callback_state` field, 0 meaning that none was registered.
+    ///
+    /// # Safety
+    ///
+    /// A non-zero `action_field` must be the address of a function with the signature of [`ArrayAction`].
+    pub(crate) unsafe fn from_action_field(action_field: i64, state_field: i64) -> Option<Self> {
+        (action_field != 0).then(|| Self {
+            state: state_field as *const c_void,
+            action: unsafe { std::mem::transmute::<*const c_void, ArrayAction>(action_field as *const c_void) },
+        })
+    }
+
+    pub(crate) fn invoke(&self, bytes: &[u8]) {
+        (self.action)(self.state, ByteArray::from_slice(bytes));
+    }
+}
+
+impl RequestCallback {
+    /// Reads the callback of an `*_action` field and its `callback_state` field, 0 meaning that none was registered.
+    ///
+    /// # Safety
+    ///
+    /// A non-zero `action_field` must be the address of a function with the signature of [`RequestAction`].
+    pub(crate) unsafe fn from_action_field(action_field: i64, state_field: i64) -> Option<Self> {
+        (action_field != 0).then(|| Self {
+            state: state_field as *const c_void,
+            action: unsafe { std::mem::transmute::<*const c_void, RequestAction>(action_field as *const c_void) },
+        })
+    }
+
+    pub(crate) fn invoke(&self, request: &[u8], completion_handle: i64) {
+        (self.action)(self.state, ByteArray::from_slice(request), completion_handle);
+    }
+}
+
+/// Starts executing an encoded `proton.sdk.Request` and returns without waiting for it to complete.
+///
+/// `response_action` is invoked at most once with the encoded `proton.sdk.Response`, possibly from
+/// another thread and possibly before this function returns. The response bytes are only valid until
+/// `response_action` returns. It is always invoked, except for requests using a cancellation token source
+/// that gets freed: once the response to the free request itself has arrived, `response_action` is not
+/// invoked anymore for them, while it may still be invoked before that.
+///
+/// # Safety
+///
+/// `request` must point to `request.length` readable bytes for the duration of the call, and `state`
+/// must remain usable from any thread until `response_action` is invoked.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn proton_sdk_handle_request(
+    request: ByteArray,
+    state: *const c_void,
+    response_action: ArrayAction,
+) {
+    let request = unsafe { request.as_slice() }.to_vec();
+    let callback = ArrayCallback::new(state, response_action);
+
+    RUNTIME.spawn(async move {
+        let (result, source) = match proton::Request::decode(request.as_slice()) {
+            Ok(request) => {
+                let source = request.payload.as_ref().and_then(cancellation::get_request_source);
+
+                // Unwinding into the host is undefined behavior, so panics are reported as errors instead
+                let result = AssertUnwindSafe(dispatch(request))
+                    .catch_unwind()
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("The SDK panicked while handling the request")));
+
+                (result, source)
+            }
+            Err(e) => (Err(e.into()), None),
+        };
+
+        let response = encode_response(result);
+
+        match source {
+            Some(source) => source.complete(|| callback.invoke(&response)),
+            None => callback.invoke(&response),
+        }
+    });
+}
+
+/// Answers a request the SDK sent through a [`RequestCallback`] with an encoded `proton.sdk.Response`.
+///
+/// Completing a request that was already completed or abandoned by the SDK has no effect.
+///
+/// # Safety
+///
+/// `response` must point to `response.length` readable bytes for the duration of the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn proton_sdk_complete_request(completion_handle: i64, response: ByteArray) {
+    let response = unsafe { response.as_slice() }.to_vec();
+
+    host::complete(completion_handle, response);
+}
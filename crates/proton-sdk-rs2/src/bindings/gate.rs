@@ -0,0 +1,136 @@
+use std::sync::Mutex;
+
+use tokio::sync::Notify;
+
+/// Guards host callbacks so that none of them runs anymore once the gate is closed.
+///
+/// No lock is held while a callback runs, so callbacks may run concurrently and may call back into
+/// the SDK, for instance by logging, which runs other callbacks through the same gate.
+pub(crate) struct CallbackGate {
+    state: Mutex<GateState>,
+    idle: Notify,
+}
+
+struct GateState {
+    is_closed: bool,
+    running_callback_count: usize,
+}
+
+/// Counts a callback as running for as long as it lives, even if the callback panics.
+struct RunningCallback<'a>(&'a CallbackGate);
+
+impl Drop for RunningCallback<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock_state();
+        state.running_callback_count -= 1;
+
+        if state.running_callback_count == 0 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl CallbackGate {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(GateState {
+                is_closed: false,
+                running_callback_count: 0,
+            }),
+            idle: Notify::new(),
+        }
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, GateState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs a callback, unless the gate was closed in the meantime.
+    pub(crate) fn run(&self, callback: impl FnOnce()) {
+        {
+            let mut state = self.lock_state();
+            if state.is_closed {
+                return;
+            }
+
+            state.running_callback_count += 1;
+        }
+
+        let _running_callback = RunningCallback(self);
+        callback();
+    }
+
+    /// Waits for the callbacks in progress to return without blocking the thread, after which no
+    /// callback is run anymore.
+    ///
+    /// Must not be awaited from one of the gate's callbacks, which cannot happen through the exports
+    /// since every request, the free requests included, is executed on a task of its own.
+    pub(crate) async fn close(&self) {
+        self.lock_state().is_closed = true;
+
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            // Registered before checking the count, so that the last callback returning in between is not missed
+            idle.as_mut().enable();
+
+            if self.lock_state().running_callback_count == 0 {
+                return;
+            }
+
+            idle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn closing_waits_for_the_running_callback_without_blocking_the_runtime() {
+        let gate = Arc::new(CallbackGate::new());
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+        let callback_thread = std::thread::spawn({
+            let gate = gate.clone();
+            move || {
+                gate.run(|| {
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                })
+            }
+        });
+        started_rx.recv().unwrap();
+
+        let close = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.close().await }
+        });
+
+        // The current-thread runtime keeps running other tasks while the close waits
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!close.is_finished());
+
+        release_tx.send(()).unwrap();
+        callback_thread.join().unwrap();
+        close.await.unwrap();
+
+        let mut is_run = false;
+        gate.run(|| is_run = true);
+        assert!(!is_run);
+    }
+
+    #[test]
+    fn callbacks_may_run_other_callbacks_of_the_gate() {
+        let gate = CallbackGate::new();
+        let mut is_nested_run = false;
+
+        gate.run(|| gate.run(|| is_nested_run = true));
+
+        assert!(is_nested_run);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    any::Any,
+    fmt,
+    marker::PhantomData,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use crate::{
+    bindings::{
+        cancellation::CancellationTokenSource, host::PendingHostRequest, logging::LoggerProvider, session::TokensRefreshedSubscription,
+        stream::SdkStream,
+    },
+    session::ProtonAPISession,
+};
+
+/// Every resource the host refers to by an `int64` handle.
+pub(crate) static HANDLES: LazyLock<HandleTable> = LazyLock::new(HandleTable::new);
+
+/// Resource that can be handed to the host as a handle.
+pub(crate) trait HandleType: Send + Sync + 'static {
+    /// Describes the resource in errors about mismatched handles.
+    const NAME: &'static str;
+}
+
+impl HandleType for ProtonAPISession {
+    const NAME: &'static str = "session";
+}
+
+impl HandleType for CancellationTokenSource {
+    const NAME: &'static str = "cancellation token source";
+}
+
+impl HandleType for PendingHostRequest {
+    const NAME: &'static str = "host request";
+}
+
+impl HandleType for LoggerProvider {
+    const NAME: &'static str = "logger provider";
+}
+
+impl HandleType for SdkStream {
+    const NAME: &'static str = "stream";
+}
+
+impl HandleType for TokensRefreshedSubscription {
+    const NAME: &'static str = "tokens refreshed subscription";
+}
+
+/// Frees the resource of a handle the SDK lends to the host for a limited time.
+pub(crate) struct OwnedHandle<T: HandleType> {
+    handle: i64,
+    _type: PhantomData<T>,
+}
+
+impl<T: HandleType> OwnedHandle<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            handle: HANDLES.add(value),
+            _type: PhantomData,
+        }
+    }
+
+    pub(crate) fn handle(&self) -> i64 {
+        self.handle
+    }
+}
+
+impl<T: HandleType> Drop for OwnedHandle<T> {
+    fn drop(&mut self) {
+        let _ = HANDLES.remove::<T>(self.handle);
+    }
+}
+
+struct HandleEntry {
+    type_name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// Thread-safe mapping from handles to resources.
+///
+/// Handles are never reused, so a freed handle is always reported as such rather than
+/// resolving to an unrelated resource allocated later.
+pub(crate) struct HandleTable {
+    entries: DashMap<i64, HandleEntry>,
+    next_handle: AtomicI64,
+}
+
+impl HandleTable {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+            // 0 is the default value of proto fields, so it never refers to a resource
+            next_handle: AtomicI64::new(1),
+        }
+    }
+
+    pub(crate) fn add<T: HandleType>(&self, value: T) -> i64 {
+        self.add_shared(Arc::new(value))
+    }
+
+    /// Adds a resource the SDK keeps using besides the host.
+    pub(crate) fn add_shared<T: HandleType>(&self, value: Arc<T>) -> i64 {
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+
+        self.entries.insert(
+            handle,
+            HandleEntry {
+                type_name: T::NAME,
+                value,
+            },
+        );
+
+        handle
+    }
+
+    pub(crate) fn get<T: HandleType>(&self, handle: i64) -> Result<Arc<T>, HandleError> {
+        let entry = self.entries.get(&handle).ok_or_else(|| self.missing(handle, T::NAME))?;
+
+        entry
+            .value
+            .clone()
+            .downcast::<T>()
+            .map_err(|_| HandleError::WrongType {
+                handle,
+                expected: T::NAME,
+                actual: entry.type_name,
+            })
+    }
+
+    /// Removes the resource, leaving it alive only as long as operations in progress hold on to it.
+    pub(crate) fn remove<T: HandleType>(&self, handle: i64) -> Result<Arc<T>, HandleError> {
+        match self.entries.entry(handle) {
+            Entry::Occupied(entry) if entry.get().value.is::<T>() => Ok(entry
+                .remove()
+                .value
+                .downcast::<T>()
+                .unwrap_or_else(|_| unreachable!("The type of the handle was checked"))),
+            Entry::Occupied(entry) => Err(HandleError::WrongType {
+                handle,
+                expected: T::NAME,
+                actual: entry.get().type_name,
+            }),
+            Entry::Vacant(_) => Err(self.missing(handle, T::NAME)),
+        }
+    }
+
+    fn missing(&self, handle: i64, expected: &'static str) -> HandleError {
+        if handle > 0 && handle < self.next_handle.load(Ordering::Relaxed) {
+            HandleError::Freed { handle, expected }
+        } else {
+            HandleError::Invalid { handle, expected }
+        }
+    }
+}
+
+/// Error returned when the host passes a handle that does not refer to a live resource of the expected type.
+#[derive(Debug, PartialEq)]
+pub(crate) enum HandleError {
+    Invalid {
+        handle: i64,
+        expected: &'static str,
+    },
+    Freed {
+        handle: i64,
+        expected: &'static str,
+    },
+    WrongType {
+        handle: i64,
+        expected: &'static str,
+        actual: &'static str,
+    },
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::Invalid { handle, expected } => write!(f, "Invalid {} handle {}", expected, handle),
+            HandleError::Freed { handle, expected } => {
+                write!(f, "The {} handle {} was already freed", expected, handle)
+            }
+            HandleError::WrongType { handle, expected, actual } => {
+                write!(f, "Handle {} refers to a {}, not a {}", handle, actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct First(u32);
+    struct Second;
+
+    impl HandleType for First {
+        const NAME: &'static str = "first";
+    }
+
+    impl HandleType for Second {
+        const NAME: &'static str = "second";
+    }
+
+    #[test]
+    fn resolves_live_handles() {
+        let table = HandleTable::new();
+        let handle = table.add(First(42));
+
+        assert_eq!(table.get::<First>(handle).unwrap().0, 42);
+        assert_eq!(table.remove::<First>(handle).unwrap().0, 42);
+    }
+
+    #[test]
+    fn detects_use_after_free() {
+        let table = HandleTable::new();
+        let handle = table.add(First(1));
+        table.remove::<First>(handle).unwrap();
+
+        assert_eq!(
+            table.get::<First>(handle).err(),
+            Some(HandleError::Freed { handle, expected: "first" })
+        );
+        assert_eq!(
+            table.remove::<First>(handle).err(),
+            Some(HandleError::Freed { handle, expected: "first" })
+        );
+        assert_eq!(
+            table.get::<First>(0).err(),
+            Some(HandleError::Invalid { handle: 0, expected: "first" })
+        );
+    }
+
+    #[test]
+    fn detects_wrong_type_without_freeing() {
+        let table = HandleTable::new();
+        let handle = table.add(Second);
+
+        let expected = HandleError::WrongType {
+            handle,
+            expected: "first",
+            actual: "second",
+        };
+        assert_eq!(table.get::<First>(handle).err(), Some(expected));
+        assert!(table.remove::<First>(handle).is_err());
+        assert!(table.get::<Second>(handle).is_ok());
+    }
+}
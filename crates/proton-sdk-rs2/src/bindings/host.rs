@@ -0,0 +1,150 @@
+//! Requests sent by the SDK to the host, answered through `proton_sdk_complete_request`.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use prost::Message;
+use prost_types::Any;
+use tokio::sync::oneshot;
+
+use crate::{
+    bindings::{exports::RequestCallback, handles::HANDLES},
+    proton::{self, response},
+};
+
+/// Request awaiting its response from the host.
+pub(crate) struct PendingHostRequest {
+    response_sender: Mutex<Option<oneshot::Sender<Vec<u8>>>>,
+    _lent_buffer: Option<Arc<HostBuffer>>,
+}
+
+/// Abandons the pending request when the SDK stops waiting for it, so that a late response is ignored.
+///
+/// Requests that lent a buffer stay registered until the host answers them, keeping the buffer alive.
+struct PendingHostRequestGuard {
+    handle: i64,
+    lends_buffer: bool,
+}
+
+impl Drop for PendingHostRequestGuard {
+    fn drop(&mut self) {
+        if !self.lends_buffer {
+            let _ = HANDLES.remove::<PendingHostRequest>(self.handle);
+        }
+    }
+}
+
+/// Memory lent to the host for it to write into, such as the buffer of a `StreamReadRequest`.
+pub(crate) struct HostBuffer {
+    pointer: *mut u8,
+    length: usize,
+}
+
+// The buffer is only accessed by the host while a request is pending, and by the SDK once it was answered
+unsafe impl Send for HostBuffer {}
+unsafe impl Sync for HostBuffer {}
+
+impl HostBuffer {
+    pub(crate) fn new(length: usize) -> Self {
+        let buffer = Box::into_raw(vec![0u8; length].into_boxed_slice());
+
+        Self {
+            pointer: buffer as *mut u8,
+            length,
+        }
+    }
+
+    pub(crate) fn pointer(&self) -> i64 {
+        self.pointer as i64
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Bytes written by the host, which must have answered the request the buffer was lent with.
+    pub(crate) fn filled(&self, length: usize) -> anyhow::Result<&[u8]> {
+        if length > self.length {
+            anyhow::bail!("The host reported {} bytes in a buffer of {}", length, self.length);
+        }
+
+        Ok(unsafe { std::slice::from_raw_parts(self.pointer, length) })
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.pointer, self.length)) });
+    }
+}
+
+/// Error reported by the host in response to a request from the SDK.
+#[derive(Debug)]
+pub(crate) struct HostError(pub(crate) proton::Error);
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.message)
+    }
+}
+
+impl std::error::Error for HostError {}
+
+impl RequestCallback {
+    /// Sends a request to the host and waits for its response value.
+    pub(crate) async fn send(&self, request: &impl Message) -> anyhow::Result<Option<Any>> {
+        self.send_lending(request, None).await
+    }
+
+    /// Sends a request referring to a buffer the host writes into, see [`HostBuffer`].
+    pub(crate) async fn send_lending(
+        &self,
+        request: &impl Message,
+        lent_buffer: Option<Arc<HostBuffer>>,
+    ) -> anyhow::Result<Option<Any>> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        let guard = PendingHostRequestGuard {
+            lends_buffer: lent_buffer.is_some(),
+            handle: HANDLES.add(PendingHostRequest {
+                response_sender: Mutex::new(Some(response_sender)),
+                _lent_buffer: lent_buffer,
+            }),
+        };
+
+        self.invoke(&request.encode_to_vec(), guard.handle);
+
+        let response = response_receiver
+            .await
+            .context("The host request was abandoned")?;
+
+        match proton::Response::decode(response.as_slice())?.result {
+            None => Ok(None),
+            Some(response::Result::Value(value)) => Ok(Some(value)),
+            Some(response::Result::Error(error)) => Err(HostError(error).into()),
+        }
+    }
+}
+
+pub(super) fn complete(completion_handle: i64, response: Vec<u8>) {
+    let pending_request = match HANDLES.remove::<PendingHostRequest>(completion_handle) {
+        Ok(pending_request) => pending_request,
+        Err(e) => {
+            log::debug!("Ignoring response to host request: {}", e);
+            return;
+        }
+    };
+
+    let response_sender = pending_request
+        .response_sender
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+
+    if let Some(response_sender) = response_sender {
+        let _ = response_sender.send(response);
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    OperationCancelledError,
+    bindings::{
+        exports::RequestCallback,
+        handles::OwnedHandle,
+        stream::{HostStream, SdkStream},
+        unpack,
+    },
+    client::HttpMessageHandler,
+    proton::{self, HttpRequestType},
+};
+
+/// [`HttpMessageHandler`] delegating every request to the host, so that it can use its own networking stack.
+///
+/// Requests are sent to the host as `HttpRequest`, whose content the host reads through
+/// `StreamReadRequest`. The type of request is the [`HttpRequestType`] extension the SDK's HTTP client
+/// attaches to every request, requests without one being treated as regular API calls.
+pub(crate) struct BindingsHttpMessageHandler {
+    http_request: RequestCallback,
+    stream_read: Option<RequestCallback>,
+    stream_seek: Option<RequestCallback>,
+}
+
+impl BindingsHttpMessageHandler {
+    pub(crate) fn new(
+        http_request: RequestCallback,
+        stream_read: Option<RequestCallback>,
+        stream_seek: Option<RequestCallback>,
+    ) -> Self {
+        Self {
+            http_request,
+            stream_read,
+            stream_seek,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpMessageHandler for BindingsHttpMessageHandler {
+    async fn send(
+        &self,
+        request: http::Request<Vec<u8>>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<http::Response<Vec<u8>>> {
+        let (parts, body) = request.into_parts();
+
+        let request_type = parts
+            .extensions
+            .get::<HttpRequestType>()
+            .copied()
+            .unwrap_or(HttpRequestType::RegularApi);
+
+        // The host finishes reading the content before answering, after which the handle is freed
+        let content = (!body.is_empty()).then(|| OwnedHandle::new(SdkStream::new(Cursor::new(body))));
+
+        let http_request = proton::HttpRequest {
+            r#type: request_type as i32,
+            url: parts.uri.to_string(),
+            method: parts.method.to_string(),
+            headers: to_proto_headers(&parts.headers),
+            sdk_content_handle: content.as_ref().map_or(0, OwnedHandle::handle),
+        };
+
+        let http_response: proton::HttpResponse = tokio::select! {
+            _ = cancellation_token.cancelled() => return Err(OperationCancelledError.into()),
+            value = self.http_request.send(&http_request) => unpack(value?)?,
+        };
+        drop(content);
+
+        let mut builder = http::Response::builder().status(u16::try_from(http_response.status_code)?);
+        if let Some(headers) = builder.headers_mut() {
+            for header in http_response.headers {
+                let name = HeaderName::try_from(header.name)?;
+                for value in header.values {
+                    headers.append(name.clone(), HeaderValue::try_from(value)?);
+                }
+            }
+        }
+
+        let mut content = Vec::new();
+        if http_response.bindings_content_handle != 0 {
+            let stream_read = self
+                .stream_read
+                .context("The host returned response content without registering a stream read action")?;
+            let mut stream = HostStream::new(http_response.bindings_content_handle, stream_read, self.stream_seek);
+
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return Err(OperationCancelledError.into()),
+                result = stream.read_to_end(&mut content) => { result?; }
+            }
+        }
+
+        Ok(builder.body(content)?)
+    }
+}
+
+fn to_proto_headers(headers: &HeaderMap) -> Vec<proton::HttpHeader> {
+    headers
+        .keys()
+        .map(|name| proton::HttpHeader {
+            name: name.to_string(),
+            values: headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::c_void, sync::mpsc};
+
+    use prost::Message;
+    use prost_types::Any;
+
+    use super::*;
+    use crate::bindings::{exports::ByteArray, host, stream};
+
+    /// Host networking stack which reads the request content through `StreamReadRequest`, as hosts do.
+    struct HostHttp {
+        runtime: tokio::runtime::Handle,
+        received_requests: mpsc::Sender<(proton::HttpRequest, Vec<u8>)>,
+    }
+
+    /// Reads less than the request content at once, so that it is streamed through several requests.
+    const HOST_READ_LENGTH: usize = 4;
+
+    extern "C" fn host_http_request(state: *const c_void, request: ByteArray, completion_handle: i64) {
+        let host_http = unsafe { &*(state as *const HostHttp) };
+        let request = proton::HttpRequest::decode(unsafe { request.as_slice() }).unwrap();
+        let received_requests = host_http.received_requests.clone();
+
+        host_http.runtime.spawn(async move {
+            let mut content = Vec::new();
+            loop {
+                let mut buffer = [0u8; HOST_READ_LENGTH];
+                let read_request = proton::StreamReadRequest {
+                    stream_handle: request.sdk_content_handle,
+                    buffer_pointer: buffer.as_mut_ptr() as i64,
+                    buffer_length: buffer.len() as i32,
+                };
+                let bytes_read = unpack::<i32>(stream::read(read_request).await.unwrap()).unwrap() as usize;
+                if bytes_read == 0 {
+                    break;
+                }
+                content.extend_from_slice(&buffer[..bytes_read]);
+            }
+            received_requests.send((request, content)).unwrap();
+
+            let http_response = proton::HttpResponse {
+                status_code: 204,
+                ..Default::default()
+            };
+            let response = proton::Response {
+                result: Some(proton::response::Result::Value(Any::from_msg(&http_response).unwrap())),
+            };
+            host::complete(completion_handle, response.encode_to_vec());
+        });
+    }
+
+    #[tokio::test]
+    async fn request_content_is_streamed_to_the_host() {
+        let (received_requests, received_requests_rx) = mpsc::channel();
+        let host_http = Box::new(HostHttp {
+            runtime: tokio::runtime::Handle::current(),
+            received_requests,
+        });
+        let http_request = RequestCallback {
+            state: &*host_http as *const HostHttp as *const c_void,
+            action: host_http_request,
+        };
+        let handler = BindingsHttpMessageHandler::new(http_request, None, None);
+
+        let mut request = http::Request::builder()
+            .method(http::Method::PUT)
+            .uri("https://storage.proton.me/blocks/1")
+            .body(b"encrypted block content".to_vec())
+            .unwrap();
+        request.extensions_mut().insert(HttpRequestType::StorageUpload);
+
+        let response = handler.send(request, CancellationToken::new()).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::NO_CONTENT);
+
+        let (request, content) = received_requests_rx.try_recv().unwrap();
+        assert_eq!(request.r#type, HttpRequestType::StorageUpload as i32);
+        assert_eq!(request.method, "PUT");
+        assert_eq!(content, b"encrypted block content");
+
+        // The content handle is freed once the host has answered
+        assert!(stream::read(proton::StreamReadRequest {
+            stream_handle: request.sdk_content_handle,
+            buffer_pointer: 0,
+            buffer_length: 0,
+        })
+        .await
+        .is_err());
+    }
+}
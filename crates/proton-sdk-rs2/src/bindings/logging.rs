@@ -0,0 +1,246 @@
+use std::sync::{Arc, Once, RwLock, Weak};
+
+use anyhow::Context;
+use log::{LevelFilter, Log, Metadata, Record};
+use prost::Message;
+use prost_types::Any;
+
+use crate::{
+    bindings::{exports::ArrayCallback, gate::CallbackGate, handles::HANDLES, int64_value},
+    proton,
+};
+
+const DEFAULT_FILTER: &str = "info";
+
+static LOGGER_PROVIDERS: RwLock<Vec<Weak<LoggerProvider>>> = RwLock::new(Vec::new());
+static INSTALL_LOGGER: Once = Once::new();
+
+/// Forwards the records of the `log` facade to a host callback as `LogEvent`, filtered per category.
+///
+/// The category of a record is its target, which is the module path unless specified otherwise.
+pub(crate) struct LoggerProvider {
+    log_action: ArrayCallback,
+    callback_gate: CallbackGate,
+    filter: env_filter::Filter,
+}
+
+impl LoggerProvider {
+    /// Creates a provider receiving records for as long as it is alive.
+    ///
+    /// `filter` uses the `RUST_LOG` syntax, an empty filter enabling records from `info` up.
+    /// Nothing is forwarded if the process installed another logger in the `log` facade.
+    pub(crate) fn register(log_action: ArrayCallback, filter: &str) -> anyhow::Result<Arc<Self>> {
+        let filter = if filter.is_empty() { DEFAULT_FILTER } else { filter };
+        let filter = env_filter::Builder::new()
+            .try_parse(filter)
+            .with_context(|| format!("Invalid log filter \"{}\"", filter))?
+            .build();
+
+        let provider = Arc::new(Self {
+            log_action,
+            callback_gate: CallbackGate::new(),
+            filter,
+        });
+
+        let mut providers = LOGGER_PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+        providers.retain(|provider| provider.strong_count() > 0);
+        providers.push(Arc::downgrade(&provider));
+
+        INSTALL_LOGGER.call_once(|| {
+            if log::set_logger(&BindingsLogger).is_err() {
+                log::warn!("A logger is already installed, log records will not be forwarded to the host");
+            }
+        });
+
+        update_max_level(&providers);
+
+        Ok(provider)
+    }
+
+    /// Stops forwarding records and waits for the callback in progress to return, after which the
+    /// log action is not invoked anymore.
+    pub(crate) async fn unregister(self: &Arc<Self>) {
+        self.callback_gate.close().await;
+
+        let mut providers = LOGGER_PROVIDERS.write().unwrap_or_else(|e| e.into_inner());
+        let provider = Arc::downgrade(self);
+        providers.retain(|other| other.strong_count() > 0 && !other.ptr_eq(&provider));
+
+        update_max_level(&providers);
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let event = proton::LogEvent {
+            level: to_log_event_level(record.level()),
+            message: record.args().to_string(),
+            category_name: record.target().to_string(),
+        };
+
+        self.callback_gate.run(|| self.log_action.invoke(&event.encode_to_vec()));
+    }
+}
+
+/// Keeps the facade from formatting records that no provider wants.
+fn update_max_level(providers: &[Weak<LoggerProvider>]) {
+    let max_level = providers
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|provider| provider.filter.filter())
+        .max()
+        .unwrap_or(LevelFilter::Off);
+
+    log::set_max_level(max_level);
+}
+
+/// Maps to the values of .NET's `LogLevel`, which hosts written against the C# bindings expect.
+fn to_log_event_level(level: log::Level) -> i32 {
+    match level {
+        log::Level::Trace => 0,
+        log::Level::Debug => 1,
+        log::Level::Info => 2,
+        log::Level::Warn => 3,
+        log::Level::Error => 4,
+    }
+}
+
+/// Logger installed in the `log` facade, dispatching records to every live [`LoggerProvider`].
+struct BindingsLogger;
+
+impl BindingsLogger {
+    fn providers() -> Vec<Arc<LoggerProvider>> {
+        LOGGER_PROVIDERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+}
+
+impl Log for BindingsLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        Self::providers().iter().any(|provider| provider.filter.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        // The lock is not held while calling into the host, which may log in turn
+        for provider in Self::providers() {
+            provider.log(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub(super) fn create_provider(request: proton::LoggerProviderCreate) -> anyhow::Result<Option<Any>> {
+    // SAFETY: the log action takes LogEvent arrays, and its state stays valid until the provider is freed
+    let log_action = unsafe { ArrayCallback::from_action_field(request.log_action, request.callback_state) }
+        .context("The log action is missing")?;
+
+    let provider = LoggerProvider::register(log_action, &request.filter)?;
+
+    Ok(Some(int64_value(HANDLES.add_shared(provider))))
+}
+
+pub(super) async fn free_provider(request: proton::LoggerProviderFreeRequest) -> anyhow::Result<Option<Any>> {
+    HANDLES
+        .remove::<LoggerProvider>(request.logger_provider_handle)?
+        .unregister()
+        .await;
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::c_void, sync::Mutex};
+
+    use super::*;
+    use crate::bindings::exports::ByteArray;
+
+    /// Serializes the tests registering providers, which share the `log` facade's max level and the provider list.
+    static REGISTRATION_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    extern "C" fn collect_event(state: *const c_void, array: ByteArray) {
+        let events = unsafe { &*(state as *const Mutex<Vec<proton::LogEvent>>) };
+
+        events
+            .lock()
+            .unwrap()
+            .push(proton::LogEvent::decode(unsafe { array.as_slice() }).unwrap());
+    }
+
+    fn log(provider: &LoggerProvider, level: log::Level, target: &str, message: &str) {
+        provider.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_records_per_category() {
+        let _registration_lock = REGISTRATION_LOCK.lock().await;
+        let events: &'static Mutex<Vec<proton::LogEvent>> = Box::leak(Box::new(Mutex::new(Vec::new())));
+        let log_action = ArrayCallback::new(events as *const _ as *const c_void, collect_event);
+
+        // Records of the SDK itself are also dispatched to the provider, hence the default of "off"
+        let provider = LoggerProvider::register(log_action, "off,test::http=debug,test::cache=warn").unwrap();
+
+        log(&provider, log::Level::Debug, "test::http", "sending request");
+        log(&provider, log::Level::Trace, "test::http", "request headers");
+        log(&provider, log::Level::Info, "test::cache", "cache opened");
+        log(&provider, log::Level::Error, "test::cache", "cache corrupted");
+        provider.unregister().await;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                proton::LogEvent {
+                    level: 1,
+                    message: "sending request".to_string(),
+                    category_name: "test::http".to_string(),
+                },
+                proton::LogEvent {
+                    level: 4,
+                    message: "cache corrupted".to_string(),
+                    category_name: "test::cache".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_filters() {
+        extern "C" fn ignore_event(_state: *const c_void, _array: ByteArray) {}
+
+        let _registration_lock = REGISTRATION_LOCK.lock().await;
+        let log_action = ArrayCallback::new(std::ptr::null(), ignore_event);
+
+        assert!(LoggerProvider::register(log_action, "info,=warn=debug").is_err());
+    }
+
+    #[tokio::test]
+    async fn freed_provider_is_not_invoked_and_lowers_the_max_level() {
+        let _registration_lock = REGISTRATION_LOCK.lock().await;
+        let events: &'static Mutex<Vec<proton::LogEvent>> = Box::leak(Box::new(Mutex::new(Vec::new())));
+        let log_action = ArrayCallback::new(events as *const _ as *const c_void, collect_event);
+
+        let provider = LoggerProvider::register(log_action, "off,test::free=trace").unwrap();
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+
+        let logger_provider_handle = HANDLES.add_shared(provider.clone());
+        free_provider(proton::LoggerProviderFreeRequest { logger_provider_handle }).await.unwrap();
+
+        assert_eq!(log::max_level(), LevelFilter::Off);
+        log(&provider, log::Level::Error, "test::free", "after free");
+        assert!(events.lock().unwrap().is_empty());
+        assert!(free_provider(proton::LoggerProviderFreeRequest { logger_provider_handle }).await.is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use prost::Message;
+use prost_types::Any;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+use crate::{
+    PasswordMode, SessionId, UserId,
+    bindings::{
+        cancellation::get_cancellation_token,
+        exports::{ArrayCallback, RequestCallback},
+        gate::CallbackGate,
+        handles::HANDLES,
+        http::BindingsHttpMessageHandler,
+        int64_value,
+        telemetry::{self, BindingsTelemetry},
+    },
+    cache::{EncryptedCacheRepository, FileCacheRepository},
+    client::ProtonClientOptions,
+    proton::{self, ProtonClientTlsPolicy},
+    session::{ProtonAPISession, ProtonSessionOptions, SessionSnapshot},
+};
+
+fn parse_app_version(app_version: &str) -> anyhow::Result<semver::Version> {
+    semver::Version::parse(app_version).with_context(|| format!("Invalid app version \"{}\"", app_version))
+}
+
+/// Options of a session to be handed to the host, along with the telemetry to close once it is freed.
+async fn session_options(
+    options: Option<proton::ProtonClientOptions>,
+    secret_cache_path: &str,
+    secret_cache_encryption_key: &[u8],
+) -> anyhow::Result<(ProtonSessionOptions, Option<Arc<BindingsTelemetry>>)> {
+    let mut client_options = ProtonClientOptions::default();
+    let mut bindings_telemetry = None;
+
+    if let Some(options) = options {
+        if !options.base_url.is_empty() {
+            client_options.base_url = Some(options.base_url.parse()?);
+        }
+        if !options.user_agent.is_empty() {
+            client_options.user_agent = Some(options.user_agent);
+        }
+        if !options.bindings_language.is_empty() {
+            client_options.bindings_language = Some(options.bindings_language);
+        }
+        client_options.tls_policy = Some(ProtonClientTlsPolicy::try_from(options.tls_policy)?);
+
+        // SAFETY: these actions take HttpRequest and stream requests, which the host answers through
+        // proton_sdk_complete_request, and their state outlives the session whose client uses them
+        let state = options.callback_state;
+        let http_request = unsafe { RequestCallback::from_action_field(options.http_request_action, state) };
+        let stream_read = unsafe { RequestCallback::from_action_field(options.stream_read_action, state) };
+        let stream_seek = unsafe { RequestCallback::from_action_field(options.stream_seek_action, state) };
+        if let Some(http_request) = http_request {
+            client_options.custom_http_message_handler_factory = Some(Arc::new(move || {
+                Box::new(BindingsHttpMessageHandler::new(http_request, stream_read, stream_seek))
+            }));
+        }
+
+        if let Some(telemetry) = options.telemetry {
+            let telemetry = Arc::new(BindingsTelemetry::new(telemetry)?);
+            client_options.telemetry = Some(telemetry.clone());
+            bindings_telemetry = Some(telemetry);
+        }
+
+        if !options.entity_cache_path.is_empty() {
+            client_options.entity_cache_repository = Some(Arc::new(FileCacheRepository::open(options.entity_cache_path).await?));
+        }
+    }
+
+    // Key passphrases are only written to disk encrypted
+    if !secret_cache_path.is_empty() {
+        let key: &[u8; 32] = secret_cache_encryption_key
+            .try_into()
+            .context("A secret cache path requires a 32-byte secret cache encryption key")?;
+        let repository = FileCacheRepository::open(secret_cache_path).await?;
+        client_options.secret_cache_repository = Some(Arc::new(EncryptedCacheRepository::new(repository, key)));
+    }
+
+    Ok((ProtonSessionOptions::new(client_options), bindings_telemetry))
+}
+
+fn add_session(session: ProtonAPISession, telemetry: Option<Arc<BindingsTelemetry>>) -> i64 {
+    let session_handle = HANDLES.add(session);
+
+    if let Some(telemetry) = telemetry {
+        telemetry::attach(session_handle, telemetry);
+    }
+
+    session_handle
+}
+
+fn password_mode(value: i32) -> anyhow::Result<Option<PasswordMode>> {
+    Ok(match proton::PasswordMode::try_from(value)? {
+        proton::PasswordMode::Unspecified => None,
+        proton::PasswordMode::Single => Some(PasswordMode::Single),
+        proton::PasswordMode::Dual => Some(PasswordMode::Dual),
+    })
+}
+
+pub(super) async fn begin(request: proton::SessionBeginRequest) -> anyhow::Result<Option<Any>> {
+    let app_version = parse_app_version(&request.app_version)?;
+    let (options, telemetry) = session_options(
+        request.options,
+        &request.secret_cache_path,
+        &request.secret_cache_encryption_key,
+    )
+    .await?;
+    let cancellation_token = get_cancellation_token(request.cancellation_token_source_handle)?;
+
+    let session = ProtonAPISession::begin(
+        request.username,
+        request.password.as_bytes(),
+        app_version,
+        options,
+        cancellation_token,
+    )
+    .await;
+
+    let session = match session {
+        Ok(session) => session,
+        Err(e) => {
+            if let Some(telemetry) = telemetry {
+                telemetry.close().await;
+            }
+            return Err(e);
+        }
+    };
+
+    Ok(Some(int64_value(add_session(session, telemetry))))
+}
+
+pub(super) async fn resume(request: proton::SessionResumeRequest) -> anyhow::Result<Option<Any>> {
+    let snapshot = SessionSnapshot {
+        session_id: SessionId::new(request.session_id),
+        user_id: UserId::new(request.user_id),
+        username: request.username,
+        access_token: request.access_token,
+        refresh_token: request.refresh_token,
+        scopes: request.scopes,
+        is_waiting_for_second_factor_code: request.is_waiting_for_second_factor_code,
+        is_waiting_for_data_password: request.is_waiting_for_data_password,
+        password_mode: password_mode(request.password_mode)?.unwrap_or(PasswordMode::Single),
+        app_version: parse_app_version(&request.app_version)?,
+        event_id: None,
+    };
+    let (options, telemetry) = session_options(
+        request.options,
+        &request.secret_cache_path,
+        &request.secret_cache_encryption_key,
+    )
+    .await?;
+
+    let session = ProtonAPISession::restore(snapshot, options)?;
+
+    Ok(Some(int64_value(add_session(session, telemetry))))
+}
+
+pub(super) fn renew(request: proton::SessionRenewRequest) -> anyhow::Result<Option<Any>> {
+    let expired_session = HANDLES.get::<ProtonAPISession>(request.old_session_handle)?;
+    let expired_snapshot = expired_session.snapshot();
+
+    let snapshot = SessionSnapshot {
+        session_id: SessionId::new(request.session_id),
+        access_token: request.access_token,
+        refresh_token: request.refresh_token,
+        scopes: request.scopes,
+        is_waiting_for_second_factor_code: request.is_waiting_for_second_factor_code,
+        is_waiting_for_data_password: request.is_waiting_for_data_password,
+        password_mode: password_mode(request.password_mode)?.unwrap_or(expired_snapshot.password_mode),
+        ..expired_snapshot
+    };
+
+    let session = ProtonAPISession::renew(&expired_session, snapshot)?;
+
+    // The renewed session keeps using the telemetry of the expired one
+    Ok(Some(int64_value(add_session(session, telemetry::get(request.old_session_handle)))))
+}
+
+pub(super) async fn end(request: proton::SessionEndRequest) -> anyhow::Result<Option<Any>> {
+    let session = HANDLES.get::<ProtonAPISession>(request.session_handle)?;
+    let cancellation_token = get_cancellation_token(request.cancellation_token_source_handle)?;
+
+    session.end_from_session(cancellation_token).await?;
+
+    Ok(None)
+}
+
+/// Forwards the tokens of a session to a host callback every time they are refreshed.
+pub(crate) struct TokensRefreshedSubscription {
+    forwarding_task: JoinHandle<()>,
+    callback_gate: Arc<CallbackGate>,
+}
+
+impl TokensRefreshedSubscription {
+    /// Must be called from within a Tokio runtime, which runs the forwarding task.
+    fn start(mut tokens_refreshed_rx: broadcast::Receiver<(String, String)>, callback: ArrayCallback) -> Self {
+        let callback_gate = Arc::new(CallbackGate::new());
+
+        let forwarding_task = tokio::spawn({
+            let callback_gate = callback_gate.clone();
+            async move {
+                loop {
+                    let (access_token, refresh_token) = match tokens_refreshed_rx.recv().await {
+                        Ok(tokens) => tokens,
+                        // The newer tokens are still queued behind the ones that were skipped
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return,
+                    };
+
+                    let tokens = proton::SessionTokens {
+                        access_token,
+                        refresh_token,
+                    };
+                    callback_gate.run(|| callback.invoke(&tokens.encode_to_vec()));
+                }
+            }
+        });
+
+        Self {
+            forwarding_task,
+            callback_gate,
+        }
+    }
+
+    /// Stops forwarding and waits for the callback in progress to return, after which the callback is not invoked anymore.
+    async fn stop(&self) {
+        self.callback_gate.close().await;
+        self.forwarding_task.abort();
+    }
+}
+
+pub(super) fn subscribe_tokens_refreshed(
+    request: proton::SessionTokensRefreshedSubscribeRequest,
+) -> anyhow::Result<Option<Any>> {
+    let session = HANDLES.get::<ProtonAPISession>(request.session_handle)?;
+
+    // SAFETY: the action takes SessionTokens arrays, and its state stays valid until the host unsubscribes
+    let callback = unsafe { ArrayCallback::from_action_field(request.tokens_refreshed_action, request.callback_state) }
+        .ok_or_else(|| anyhow::anyhow!("Missing tokens refreshed action"))?;
+
+    let subscription = TokensRefreshedSubscription::start(session.subscribe_tokens_refreshed(), callback);
+
+    Ok(Some(int64_value(HANDLES.add(subscription))))
+}
+
+pub(super) async fn unsubscribe_tokens_refreshed(
+    request: proton::SessionTokensRefreshedUnsubscribeRequest,
+) -> anyhow::Result<Option<Any>> {
+    HANDLES
+        .remove::<TokensRefreshedSubscription>(request.subscription_handle)?
+        .stop()
+        .await;
+
+    Ok(None)
+}
+
+pub(super) async fn free(request: proton::SessionFreeRequest) -> anyhow::Result<Option<Any>> {
+    HANDLES.remove::<ProtonAPISession>(request.session_handle)?;
+    telemetry::detach(request.session_handle).await;
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::c_void, sync::mpsc, time::Duration};
+
+    use super::*;
+    use crate::bindings::ByteArray;
+
+    extern "C" fn send_tokens(state: *const c_void, tokens: ByteArray) {
+        let sender = unsafe { &*(state as *const mpsc::Sender<proton::SessionTokens>) };
+        let tokens = proton::SessionTokens::decode(unsafe { tokens.as_slice() }).unwrap();
+        let _ = sender.send(tokens);
+    }
+
+    #[tokio::test]
+    async fn forwards_refreshed_tokens_until_stopped() {
+        let (tokens_tx, tokens_rx) = mpsc::channel::<proton::SessionTokens>();
+        let tokens_tx = Box::new(tokens_tx);
+        let callback = ArrayCallback::new(&*tokens_tx as *const _ as *const c_void, send_tokens);
+
+        let (tokens_refreshed_tx, tokens_refreshed_rx) = broadcast::channel(4);
+        let subscription = TokensRefreshedSubscription::start(tokens_refreshed_rx, callback);
+
+        tokens_refreshed_tx
+            .send(("access-1".to_string(), "refresh-1".to_string()))
+            .unwrap();
+        let tokens = tokio::task::spawn_blocking(move || {
+            let tokens = tokens_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            (tokens, tokens_rx)
+        });
+        let (tokens, tokens_rx) = tokens.await.unwrap();
+        assert_eq!(tokens.access_token, "access-1");
+        assert_eq!(tokens.refresh_token, "refresh-1");
+
+        subscription.stop().await;
+        let _ = tokens_refreshed_tx.send(("access-2".to_string(), "refresh-2".to_string()));
+        tokio::task::yield_now().await;
+
+        assert!(tokens_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
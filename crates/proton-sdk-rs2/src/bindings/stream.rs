@@ -0,0 +1,400 @@
+//! Streams crossing the binding boundary, in both directions.
+//!
+//! SDK streams are exposed to the host as handles it reads with `StreamReadRequest` and moves within
+//! with `StreamSeekRequest`, while host streams are sent the same requests through its callbacks.
+
+use std::{
+    io::{self, SeekFrom},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+};
+
+use anyhow::Context as _;
+use futures::future::BoxFuture;
+use prost_types::Any;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    bindings::{exports::RequestCallback, handles::HANDLES, host::HostBuffer, int32_value, int64_value, unpack},
+    proton,
+};
+
+const HOST_STREAM_BUFFER_LENGTH: usize = 64 * 1024;
+
+trait AsyncReadSeek: AsyncRead + AsyncSeek + Send {}
+
+impl<T: AsyncRead + AsyncSeek + Send> AsyncReadSeek for T {}
+
+/// SDK-side stream the host reads through `StreamReadRequest`, such as the content of an HTTP request.
+pub(crate) struct SdkStream {
+    reader: Mutex<Pin<Box<dyn AsyncReadSeek>>>,
+}
+
+impl SdkStream {
+    pub(crate) fn new(reader: impl AsyncRead + AsyncSeek + Send + 'static) -> Self {
+        Self {
+            reader: Mutex::new(Box::pin(reader)),
+        }
+    }
+}
+
+pub(super) async fn read(request: proton::StreamReadRequest) -> anyhow::Result<Option<Any>> {
+    let buffer_length = usize::try_from(request.buffer_length)
+        .map_err(|_| anyhow::anyhow!("Invalid buffer length {}", request.buffer_length))?;
+    if request.buffer_pointer == 0 && buffer_length > 0 {
+        anyhow::bail!("Invalid buffer pointer");
+    }
+
+    let stream = HANDLES.get::<SdkStream>(request.stream_handle)?;
+
+    let mut buffer = vec![0u8; buffer_length];
+    let bytes_read = stream.reader.lock().await.read(&mut buffer).await?;
+
+    // The host guarantees that the buffer it passed can hold buffer_length bytes
+    unsafe {
+        std::ptr::copy_nonoverlapping(buffer.as_ptr(), request.buffer_pointer as *mut u8, bytes_read);
+    }
+
+    // Cannot overflow since bytes_read is at most buffer_length
+    Ok(Some(int32_value(bytes_read as i32)))
+}
+
+pub(super) async fn seek(request: proton::StreamSeekRequest) -> anyhow::Result<Option<Any>> {
+    let position = match request.origin {
+        0 => SeekFrom::Start(
+            u64::try_from(request.offset).map_err(|_| anyhow::anyhow!("Invalid seek offset {}", request.offset))?,
+        ),
+        1 => SeekFrom::Current(request.offset),
+        2 => SeekFrom::End(request.offset),
+        origin => anyhow::bail!("Invalid seek origin {}", origin),
+    };
+
+    let stream = HANDLES.get::<SdkStream>(request.stream_handle)?;
+
+    let position = stream.reader.lock().await.seek(position).await?;
+
+    Ok(Some(int64_value(i64::try_from(position)?)))
+}
+
+/// Host-side stream read through the host's `stream_read_action` and `stream_seek_action` callbacks.
+///
+/// Reads are performed through a buffer lent to the host, so dropping the stream while a read is
+/// pending is safe: the buffer is only freed once the host answered.
+pub(crate) struct HostStream {
+    stream_handle: i64,
+    stream_read: RequestCallback,
+    stream_seek: Option<RequestCallback>,
+    buffer: Arc<HostBuffer>,
+    /// Bytes the host read that did not fit in the caller's buffer.
+    unread: Vec<u8>,
+    pending_read: Option<BoxFuture<'static, anyhow::Result<usize>>>,
+    pending_seek: Option<BoxFuture<'static, anyhow::Result<u64>>>,
+}
+
+impl HostStream {
+    pub(crate) fn new(stream_handle: i64, stream_read: RequestCallback, stream_seek: Option<RequestCallback>) -> Self {
+        Self {
+            stream_handle,
+            stream_read,
+            stream_seek,
+            buffer: Arc::new(HostBuffer::new(HOST_STREAM_BUFFER_LENGTH)),
+            unread: Vec::new(),
+            pending_read: None,
+            pending_seek: None,
+        }
+    }
+
+    fn start_read(&self, buffer_length: usize) -> BoxFuture<'static, anyhow::Result<usize>> {
+        let stream_read = self.stream_read;
+        let buffer = self.buffer.clone();
+        let request = proton::StreamReadRequest {
+            stream_handle: self.stream_handle,
+            buffer_pointer: buffer.pointer(),
+            // Cannot overflow since the buffer is small
+            buffer_length: buffer_length as i32,
+        };
+
+        Box::pin(async move {
+            let value = stream_read.send_lending(&request, Some(buffer)).await?;
+
+            let bytes_read = unpack::<i32>(value)?;
+            usize::try_from(bytes_read)
+                .ok()
+                .filter(|bytes_read| *bytes_read <= buffer_length)
+                .with_context(|| format!("The host reported {} bytes read into a buffer of {}", bytes_read, buffer_length))
+        })
+    }
+}
+
+impl AsyncRead for HostStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.unread.is_empty() {
+            let length = this.unread.len().min(buf.remaining());
+            buf.put_slice(&this.unread[..length]);
+            this.unread.drain(..length);
+            return Poll::Ready(Ok(()));
+        }
+
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        if this.pending_read.is_none() {
+            this.pending_read = Some(this.start_read(buf.remaining().min(this.buffer.len())));
+        }
+
+        let pending_read = this.pending_read.as_mut().expect("A read is pending");
+        let result = ready!(pending_read.as_mut().poll(cx));
+        this.pending_read = None;
+
+        let bytes_read = result.map_err(io::Error::other)?;
+        let filled = this.buffer.filled(bytes_read).map_err(io::Error::other)?;
+
+        // The caller may have passed a smaller buffer than when the read started
+        let length = bytes_read.min(buf.remaining());
+        buf.put_slice(&filled[..length]);
+        this.unread.extend_from_slice(&filled[length..]);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for HostStream {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+
+        if this.pending_seek.is_some() {
+            return Err(io::Error::other("A seek is already in progress"));
+        }
+        // The host may still move the position for a read that was abandoned before it completed
+        if this.pending_read.is_some() {
+            return Err(io::Error::other("A read is still in progress"));
+        }
+
+        let stream_seek = this
+            .stream_seek
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "The host stream is not seekable"))?;
+
+        let (offset, origin) = match position {
+            SeekFrom::Start(offset) => (i64::try_from(offset).map_err(io::Error::other)?, 0),
+            // The host is ahead of the caller by the bytes that were not consumed yet
+            SeekFrom::Current(offset) => (offset - this.unread.len() as i64, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        this.unread.clear();
+
+        let request = proton::StreamSeekRequest {
+            stream_handle: this.stream_handle,
+            offset,
+            origin,
+        };
+
+        this.pending_seek = Some(Box::pin(async move {
+            let position = unpack::<i64>(stream_seek.send(&request).await?)?;
+
+            u64::try_from(position).with_context(|| format!("The host reported an invalid position {}", position))
+        }));
+
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        if self.pending_seek.is_none() {
+            self.as_mut().start_seek(SeekFrom::Current(0))?;
+        }
+
+        let this = self.get_mut();
+        let pending_seek = this.pending_seek.as_mut().expect("A seek is pending");
+        let result = ready!(pending_seek.as_mut().poll(cx));
+        this.pending_seek = None;
+
+        Poll::Ready(result.map_err(io::Error::other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::c_void,
+        io::{Cursor, Read, Seek},
+        sync::Mutex as StdMutex,
+    };
+
+    use futures::FutureExt;
+    use prost::Message;
+
+    use super::*;
+    use crate::bindings::{exports::ByteArray, host, int32_value};
+
+    const CONTENT: &[u8] = b"hello world";
+
+    /// Serves at most this many bytes per read, to exercise partial reads.
+    const HOST_READ_LENGTH: usize = 3;
+
+    fn complete(completion_handle: i64, value: Any) {
+        let response = proton::Response {
+            result: Some(proton::response::Result::Value(value)),
+        };
+
+        host::complete(completion_handle, response.encode_to_vec());
+    }
+
+    extern "C" fn host_read(state: *const c_void, request: ByteArray, completion_handle: i64) {
+        let content = unsafe { &*(state as *const StdMutex<Cursor<Vec<u8>>>) };
+        let request = proton::StreamReadRequest::decode(unsafe { request.as_slice() }).unwrap();
+
+        let length = (request.buffer_length as usize).min(HOST_READ_LENGTH);
+        let buffer = unsafe { std::slice::from_raw_parts_mut(request.buffer_pointer as *mut u8, length) };
+        let bytes_read = Read::read(&mut *content.lock().unwrap(), buffer).unwrap();
+
+        complete(completion_handle, int32_value(bytes_read as i32));
+    }
+
+    extern "C" fn host_seek(state: *const c_void, request: ByteArray, completion_handle: i64) {
+        let content = unsafe { &*(state as *const StdMutex<Cursor<Vec<u8>>>) };
+        let request = proton::StreamSeekRequest::decode(unsafe { request.as_slice() }).unwrap();
+
+        let position = match request.origin {
+            0 => SeekFrom::Start(request.offset as u64),
+            1 => SeekFrom::Current(request.offset),
+            _ => SeekFrom::End(request.offset),
+        };
+        let position = Seek::seek(&mut *content.lock().unwrap(), position).unwrap();
+
+        complete(completion_handle, int64_value(position as i64));
+    }
+
+    fn host_stream() -> HostStream {
+        let content: &'static StdMutex<Cursor<Vec<u8>>> = Box::leak(Box::new(StdMutex::new(Cursor::new(CONTENT.to_vec()))));
+        let state = content as *const StdMutex<Cursor<Vec<u8>>> as *const c_void;
+
+        HostStream::new(
+            1,
+            RequestCallback { state, action: host_read },
+            Some(RequestCallback { state, action: host_seek }),
+        )
+    }
+
+    async fn read_sdk_stream(stream_handle: i64, buffer_length: usize) -> Vec<u8> {
+        let mut buffer = vec![0u8; buffer_length];
+        let request = proton::StreamReadRequest {
+            stream_handle,
+            buffer_pointer: buffer.as_mut_ptr() as i64,
+            buffer_length: buffer_length as i32,
+        };
+
+        let bytes_read = unpack::<i32>(read(request).await.unwrap()).unwrap();
+        buffer.truncate(bytes_read as usize);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn sdk_stream_reads_partially_until_eof() {
+        let stream_handle = HANDLES.add(SdkStream::new(Cursor::new(CONTENT.to_vec())));
+
+        assert_eq!(read_sdk_stream(stream_handle, 4).await, b"hell");
+        assert_eq!(read_sdk_stream(stream_handle, 64).await, b"o world");
+        assert_eq!(read_sdk_stream(stream_handle, 64).await, b"");
+
+        HANDLES.remove::<SdkStream>(stream_handle).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sdk_stream_seeks_from_end() {
+        let stream_handle = HANDLES.add(SdkStream::new(Cursor::new(CONTENT.to_vec())));
+
+        let request = proton::StreamSeekRequest {
+            stream_handle,
+            offset: -5,
+            origin: 2,
+        };
+        assert_eq!(unpack::<i64>(seek(request).await.unwrap()).unwrap(), 6);
+        assert_eq!(read_sdk_stream(stream_handle, 64).await, b"world");
+
+        let request = proton::StreamSeekRequest {
+            stream_handle,
+            offset: 0,
+            origin: 3,
+        };
+        assert!(seek(request).await.is_err());
+
+        HANDLES.remove::<SdkStream>(stream_handle).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sdk_stream_rejects_negative_buffer_length() {
+        let stream_handle = HANDLES.add(SdkStream::new(Cursor::new(CONTENT.to_vec())));
+        let request = proton::StreamReadRequest {
+            stream_handle,
+            buffer_pointer: 0,
+            buffer_length: -1,
+        };
+
+        assert!(read(request).await.is_err());
+
+        HANDLES.remove::<SdkStream>(stream_handle).unwrap();
+    }
+
+    #[tokio::test]
+    async fn host_stream_reads_partially_until_eof() {
+        let mut stream = host_stream();
+
+        // The host answers with fewer bytes than requested
+        let mut buffer = [0u8; 8];
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), HOST_READ_LENGTH);
+        assert_eq!(&buffer[..HOST_READ_LENGTH], b"hel");
+
+        let mut buffer = [0u8; 2];
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 2);
+        assert_eq!(&buffer, b"lo");
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b" world");
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn host_stream_seeks_from_end() {
+        let mut stream = host_stream();
+
+        assert_eq!(stream.seek(SeekFrom::End(-5)).await.unwrap(), 6);
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"world");
+    }
+
+    extern "C" fn host_read_later(state: *const c_void, _request: ByteArray, completion_handle: i64) {
+        let pending_completion_handle = unsafe { &*(state as *const StdMutex<Option<i64>>) };
+        *pending_completion_handle.lock().unwrap() = Some(completion_handle);
+    }
+
+    #[tokio::test]
+    async fn host_stream_does_not_seek_while_a_read_is_in_progress() {
+        let pending_completion_handle = Box::new(StdMutex::new(None));
+        let state = &*pending_completion_handle as *const StdMutex<Option<i64>> as *const c_void;
+        let mut stream = HostStream::new(
+            1,
+            RequestCallback { state, action: host_read_later },
+            Some(RequestCallback { state, action: host_seek }),
+        );
+
+        // The read is abandoned before the host answered
+        let mut buffer = [0u8; 8];
+        assert!(stream.read(&mut buffer).now_or_never().is_none());
+
+        let error = Pin::new(&mut stream).start_seek(SeekFrom::Start(0)).unwrap_err();
+        assert_eq!(error.to_string(), "A read is still in progress");
+
+        let completion_handle = pending_completion_handle.lock().unwrap().take().unwrap();
+        complete(completion_handle, int32_value(0));
+        assert_eq!(stream.read(&mut buffer).await.unwrap(), 0);
+    }
+}
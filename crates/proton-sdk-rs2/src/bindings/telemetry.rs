@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use anyhow::Context;
+use prost::Message;
+use prost_types::Any;
+
+use crate::{
+    bindings::{exports::ArrayCallback, gate::CallbackGate, handles::HANDLES, logging::LoggerProvider},
+    client::TelemetryTrait,
+    proton::{self, telemetry::Logger},
+};
+
+/// Telemetry of the sessions handed to the host, by session handle.
+///
+/// Renewed sessions share the telemetry of the session they renew, so it is only closed once the last
+/// session using it is freed.
+static SESSION_TELEMETRY: LazyLock<Mutex<HashMap<i64, Arc<BindingsTelemetry>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// [`TelemetryTrait`] configured from the `Telemetry` of the proto's `ProtonClientOptions`.
+///
+/// Metrics are forwarded as `MetricEvent` to the host's `record_metric_action`, while the logger
+/// provider is kept alive for as long as the session using it.
+pub(crate) struct BindingsTelemetry {
+    logger_provider: Option<Arc<LoggerProvider>>,
+    /// Set when the provider was created for this telemetry rather than passed by handle.
+    is_logger_provider_owned: bool,
+    record_metric: Option<ArrayCallback>,
+    callback_gate: CallbackGate,
+}
+
+impl BindingsTelemetry {
+    pub(crate) fn new(telemetry: proton::Telemetry) -> anyhow::Result<Self> {
+        let is_logger_provider_owned = matches!(telemetry.logger, Some(Logger::LogAction(_)));
+        let logger_provider = match telemetry.logger {
+            Some(Logger::LogAction(log_action)) => {
+                // SAFETY: the log action takes LogEvent arrays, and its state outlives the session using it
+                let log_action = unsafe { ArrayCallback::from_action_field(log_action, telemetry.callback_state) }
+                    .context("The log action is missing")?;
+
+                Some(LoggerProvider::register(log_action, "")?)
+            }
+            Some(Logger::LoggerProviderHandle(handle)) => Some(HANDLES.get::<LoggerProvider>(handle)?),
+            None => None,
+        };
+
+        // SAFETY: the metric action takes MetricEvent arrays, with the same state as the log action
+        let record_metric =
+            unsafe { ArrayCallback::from_action_field(telemetry.record_metric_action, telemetry.callback_state) };
+
+        Ok(Self {
+            logger_provider,
+            is_logger_provider_owned,
+            record_metric,
+            callback_gate: CallbackGate::new(),
+        })
+    }
+
+    /// Waits for the callbacks in progress to return, after which the host is not called anymore
+    /// through this telemetry, nor through the logger provider it created.
+    pub(crate) async fn close(&self) {
+        self.callback_gate.close().await;
+
+        if self.is_logger_provider_owned
+            && let Some(logger_provider) = &self.logger_provider
+        {
+            logger_provider.unregister().await;
+        }
+    }
+}
+
+/// Records the telemetry of a session handed to the host, to be closed when the session is freed.
+pub(crate) fn attach(session_handle: i64, telemetry: Arc<BindingsTelemetry>) {
+    SESSION_TELEMETRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(session_handle, telemetry);
+}
+
+pub(crate) fn get(session_handle: i64) -> Option<Arc<BindingsTelemetry>> {
+    SESSION_TELEMETRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&session_handle)
+        .cloned()
+}
+
+/// Forgets the telemetry of a freed session, closing it unless another session still uses it.
+pub(crate) async fn detach(session_handle: i64) {
+    let telemetry = {
+        let mut session_telemetry = SESSION_TELEMETRY.lock().unwrap_or_else(|e| e.into_inner());
+
+        let Some(telemetry) = session_telemetry.remove(&session_handle) else {
+            return;
+        };
+        if session_telemetry.values().any(|other| Arc::ptr_eq(other, &telemetry)) {
+            return;
+        }
+
+        telemetry
+    };
+
+    telemetry.close().await;
+}
+
+#[async_trait::async_trait]
+impl TelemetryTrait for BindingsTelemetry {
+    async fn record_metric(&self, name: String, payload: Option<Any>) {
+        let Some(record_metric) = &self.record_metric else {
+            return;
+        };
+
+        let event = proton::MetricEvent { name, payload };
+
+        self.callback_gate.run(|| record_metric.invoke(&event.encode_to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+
+    use super::*;
+    use crate::bindings::exports::{ArrayAction, ByteArray};
+
+    extern "C" fn collect_metric(state: *const c_void, array: ByteArray) {
+        let metrics = unsafe { &*(state as *const Mutex<Vec<String>>) };
+        let event = proton::MetricEvent::decode(unsafe { array.as_slice() }).unwrap();
+
+        metrics.lock().unwrap().push(event.name);
+    }
+
+    #[tokio::test]
+    async fn metrics_stop_once_the_last_session_using_them_is_freed() {
+        let metrics: &'static Mutex<Vec<String>> = Box::leak(Box::new(Mutex::new(Vec::new())));
+        let telemetry = Arc::new(
+            BindingsTelemetry::new(proton::Telemetry {
+                record_metric_action: collect_metric as ArrayAction as usize as i64,
+                callback_state: metrics as *const _ as i64,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+
+        // Handles no real session gets, standing for a session and its renewal
+        let (session_handle, renewed_session_handle) = (-1, -2);
+        attach(session_handle, telemetry.clone());
+        attach(renewed_session_handle, get(session_handle).unwrap());
+
+        detach(session_handle).await;
+        telemetry.record_metric("after renewal".to_string(), None).await;
+
+        detach(renewed_session_handle).await;
+        telemetry.record_metric("after free".to_string(), None).await;
+
+        assert_eq!(*metrics.lock().unwrap(), vec!["after renewal".to_string()]);
+    }
+}
@@ -1,9 +1,24 @@
+pub(crate) mod encrypted;
+pub(crate) mod file;
+pub(crate) mod typed;
+
 use std::collections::HashSet;
 
+use base64::{Engine as _, engine::general_purpose};
 use dashmap::DashMap;
-use futures::stream::BoxStream;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio_util::sync::CancellationToken;
 
+use crate::serialization::base64_bytes;
+
+pub use encrypted::EncryptedCacheRepository;
+pub use file::FileCacheRepository;
+pub use typed::{BytesCache, TypedCache};
+
+const CHANGE_CHANNEL_CAPACITY: usize = 256;
+
 #[async_trait::async_trait]
 pub trait CacheRepositoryTrait: Send + Sync {
     async fn set(
@@ -39,12 +54,122 @@ pub trait CacheRepositoryTrait: Send + Sync {
         tags: Vec<String>,
         cancellation_token: CancellationToken,
     ) -> BoxStream<'_, anyhow::Result<(String, String)>>;
+
+    /// Stream of changes to the entry with the given key, or to any entry carrying the given tag.
+    ///
+    /// Repositories that cannot observe their changes keep the default, a stream that never yields.
+    fn watch(&self, _key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+        stream::pending().boxed()
+    }
+
+    /// Stores a binary value. Repositories that can only hold strings get a base64 fallback.
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let serialized_value = general_purpose::STANDARD.encode(value);
+        self.set(key, serialized_value, tags, cancellation_token).await
+    }
+
+    async fn try_get_bytes(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.try_get(key, cancellation_token).await? {
+            Some(value) => Ok(Some(general_purpose::STANDARD.decode(value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheChange {
+    Set { key: String, tags: Vec<String> },
+    Removed { key: String, tags: Vec<String> },
+    /// Any entry may have changed, e.g. after a `clear` or when the watcher fell behind.
+    Invalidated,
+}
+
+impl CacheChange {
+    fn concerns(&self, key_or_tag: &str) -> bool {
+        match self {
+            CacheChange::Set { key, tags } | CacheChange::Removed { key, tags } => {
+                key == key_or_tag || tags.iter().any(|tag| tag == key_or_tag)
+            }
+            CacheChange::Invalidated => true,
+        }
+    }
+}
+
+pub(crate) struct CacheChangeNotifier {
+    changes_tx: broadcast::Sender<CacheChange>,
+}
+
+impl CacheChangeNotifier {
+    pub(crate) fn new() -> Self {
+        let (changes_tx, _) = broadcast::channel(CHANGE_CHANNEL_CAPACITY);
+        Self { changes_tx }
+    }
+
+    pub(crate) fn has_watchers(&self) -> bool {
+        self.changes_tx.receiver_count() > 0
+    }
+
+    pub(crate) fn notify(&self, change: CacheChange) {
+        let _ = self.changes_tx.send(change);
+    }
+
+    pub(crate) fn watch(&self, key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+        let key_or_tag = key_or_tag.to_string();
+
+        stream::unfold(self.changes_tx.subscribe(), move |mut changes_rx| {
+            let key_or_tag = key_or_tag.clone();
+            async move {
+                loop {
+                    match changes_rx.recv().await {
+                        Ok(change) if change.concerns(&key_or_tag) => return Some((change, changes_rx)),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(_)) => return Some((CacheChange::Invalidated, changes_rx)),
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+enum CacheValue {
+    Text(String),
+    Bytes(#[serde(with = "base64_bytes")] Vec<u8>),
+}
+
+impl CacheValue {
+    fn to_text(&self) -> String {
+        match self {
+            CacheValue::Text(value) => value.clone(),
+            CacheValue::Bytes(value) => general_purpose::STANDARD.encode(value),
+        }
+    }
+
+    fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            CacheValue::Text(value) => Ok(general_purpose::STANDARD.decode(value)?),
+            CacheValue::Bytes(value) => Ok(value.clone()),
+        }
+    }
 }
 
 pub struct InMemoryCacheRepository {
-    entries: DashMap<String, String>,
+    entries: DashMap<String, CacheValue>,
     key_to_tags: DashMap<String, HashSet<String>>,
     tag_to_keys: DashMap<String, HashSet<String>>,
+    notifier: CacheChangeNotifier,
 }
 
 impl InMemoryCacheRepository {
@@ -53,19 +178,19 @@ impl InMemoryCacheRepository {
             entries: Default::default(),
             key_to_tags: Default::default(),
             tag_to_keys: Default::default(),
+            notifier: CacheChangeNotifier::new(),
         }
     }
 }
 
-#[async_trait::async_trait]
-impl CacheRepositoryTrait for InMemoryCacheRepository {
-    async fn set(
-        &self,
-        key: &str,
-        value: String,
-        tags: Vec<String>,
-        _cancellation_token: CancellationToken,
-    ) -> anyhow::Result<()> {
+impl Default for InMemoryCacheRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryCacheRepository {
+    fn insert(&self, key: &str, value: CacheValue, tags: Vec<String>) {
         // Clear existing tags for this key
         if let Some((_, old_tags)) = self.key_to_tags.remove(key) {
             for tag in old_tags {
@@ -80,13 +205,30 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
         let new_tags: HashSet<String> = tags.into_iter().collect();
         self.key_to_tags.insert(key.to_string(), new_tags.clone());
 
-        for tag in new_tags {
+        for tag in &new_tags {
             self.tag_to_keys
-                .entry(tag)
+                .entry(tag.clone())
                 .or_insert_with(HashSet::new)
                 .insert(key.to_string());
         }
 
+        self.notifier.notify(CacheChange::Set {
+            key: key.to_string(),
+            tags: new_tags.into_iter().collect(),
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheRepositoryTrait for InMemoryCacheRepository {
+    async fn set(
+        &self,
+        key: &str,
+        value: String,
+        tags: Vec<String>,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.insert(key, CacheValue::Text(value), tags);
         Ok(())
     }
 
@@ -95,16 +237,26 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
         key: &str,
         _cancellation_token: CancellationToken,
     ) -> anyhow::Result<()> {
-        self.entries.remove(key);
+        let existed = self.entries.remove(key).is_some();
 
-        if let Some((_, tags)) = self.key_to_tags.remove(key) {
-            for tag in tags {
-                if let Some(mut keys) = self.tag_to_keys.get_mut(&tag) {
-                    keys.remove(key);
-                }
+        let tags = match self.key_to_tags.remove(key) {
+            Some((_, tags)) => tags,
+            None => HashSet::new(),
+        };
+
+        for tag in &tags {
+            if let Some(mut keys) = self.tag_to_keys.get_mut(tag) {
+                keys.remove(key);
             }
         }
 
+        if existed {
+            self.notifier.notify(CacheChange::Removed {
+                key: key.to_string(),
+                tags: tags.into_iter().collect(),
+            });
+        }
+
         Ok(())
     }
 
@@ -126,6 +278,7 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
         self.entries.clear();
         self.key_to_tags.clear();
         self.tag_to_keys.clear();
+        self.notifier.notify(CacheChange::Invalidated);
         Ok(())
     }
 
@@ -134,7 +287,7 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
         key: &str,
         _cancellation_token: CancellationToken,
     ) -> anyhow::Result<Option<String>> {
-        Ok(self.entries.get(key).map(|v| v.clone()))
+        Ok(self.entries.get(key).map(|v| v.to_text()))
     }
 
     fn get_by_tags(
@@ -142,8 +295,6 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
         tags: Vec<String>,
         _cancellation_token: CancellationToken,
     ) -> BoxStream<'_, anyhow::Result<(String, String)>> {
-        use futures::stream::{self, StreamExt};
-
         let mut keys_set = HashSet::new();
 
         for tag in tags {
@@ -157,10 +308,82 @@ impl CacheRepositoryTrait for InMemoryCacheRepository {
             .filter_map(|key| {
                 self.entries
                     .get(&key)
-                    .map(|value| Ok((key.clone(), value.clone())))
+                    .map(|value| Ok((key.clone(), value.to_text())))
             })
             .collect();
 
         stream::iter(results).boxed()
     }
+
+    fn watch(&self, key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+        self.notifier.watch(key_or_tag)
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        tags: Vec<String>,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.insert(key, CacheValue::Bytes(value), tags);
+        Ok(())
+    }
+
+    async fn try_get_bytes(
+        &self,
+        key: &str,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        self.entries.get(key).map(|v| v.to_bytes()).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn watchers_only_see_changes_to_their_key_or_tag() {
+        let repository = InMemoryCacheRepository::new();
+        let mut key_changes = repository.watch("share:1");
+        let mut tag_changes = repository.watch("volume:1");
+
+        repository
+            .set("node:1", "value".to_string(), vec!["volume:1".to_string()], CancellationToken::new())
+            .await
+            .unwrap();
+        repository
+            .set("share:1", "value".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        repository.remove_by_tag("volume:1", CancellationToken::new()).await.unwrap();
+        repository.clear().await.unwrap();
+
+        assert_eq!(
+            key_changes.next().await,
+            Some(CacheChange::Set {
+                key: "share:1".to_string(),
+                tags: Vec::new()
+            })
+        );
+        assert_eq!(key_changes.next().await, Some(CacheChange::Invalidated));
+
+        let volume_tags = vec!["volume:1".to_string()];
+        assert_eq!(
+            tag_changes.next().await,
+            Some(CacheChange::Set {
+                key: "node:1".to_string(),
+                tags: volume_tags.clone()
+            })
+        );
+        assert_eq!(
+            tag_changes.next().await,
+            Some(CacheChange::Removed {
+                key: "node:1".to_string(),
+                tags: volume_tags
+            })
+        );
+        assert_eq!(tag_changes.next().await, Some(CacheChange::Invalidated));
+    }
 }
@@ -0,0 +1,255 @@
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use base64::{Engine as _, engine::general_purpose};
+use futures::stream::{BoxStream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use crate::cache::{CacheChange, CacheRepositoryTrait};
+
+const ENCRYPTION_FORMAT_VERSION: u8 = 1;
+const NONCE_LENGTH: usize = 12;
+
+const TEXT_VALUE_KIND: u8 = 0;
+const BYTES_VALUE_KIND: u8 = 1;
+
+/// Cache repository encrypting the values of another one with AES-256-GCM under a key provided by the host.
+///
+/// Meant for secrets such as key passphrases that must not reach a persistent repository in plaintext.
+/// Keys and tags are left readable, and every value is bound to its key so values cannot be swapped.
+pub struct EncryptedCacheRepository<R> {
+    inner: R,
+    cipher: Aes256Gcm,
+}
+
+impl<R: CacheRepositoryTrait> EncryptedCacheRepository<R> {
+    pub fn new(inner: R, key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    fn encrypt(&self, key: &str, kind: u8, value: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut plaintext = Zeroizing::new(Vec::with_capacity(1 + value.len()));
+        plaintext.push(kind);
+        plaintext.extend_from_slice(value);
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: &plaintext, aad: key.as_bytes() })
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt cache entry"))?;
+
+        let mut encrypted = Vec::with_capacity(1 + NONCE_LENGTH + ciphertext.len());
+        encrypted.push(ENCRYPTION_FORMAT_VERSION);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+
+        Ok(encrypted)
+    }
+
+    /// Decrypts a value into its kind and its plaintext.
+    fn decrypt(&self, key: &str, encrypted: &[u8]) -> anyhow::Result<(u8, Zeroizing<Vec<u8>>)> {
+        let Some((&ENCRYPTION_FORMAT_VERSION, encrypted)) = encrypted.split_first() else {
+            return Err(anyhow::anyhow!("Unsupported encrypted cache entry format"));
+        };
+
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(anyhow::anyhow!("Encrypted cache entry is truncated"));
+        }
+
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let mut plaintext = Zeroizing::new(
+            self.cipher
+                .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: key.as_bytes() })
+                .map_err(|_| anyhow::anyhow!("Failed to decrypt cache entry"))?,
+        );
+
+        if plaintext.is_empty() {
+            return Err(anyhow::anyhow!("Encrypted cache entry is truncated"));
+        }
+        let kind = plaintext.remove(0);
+
+        Ok((kind, plaintext))
+    }
+
+    fn decrypt_text(&self, key: &str, encrypted: &[u8]) -> anyhow::Result<String> {
+        let (kind, plaintext) = self.decrypt(key, encrypted)?;
+
+        match kind {
+            TEXT_VALUE_KIND => Ok(String::from_utf8(plaintext.to_vec())?),
+            BYTES_VALUE_KIND => Ok(general_purpose::STANDARD.encode(&*plaintext)),
+            _ => Err(anyhow::anyhow!("Unknown encrypted cache entry kind {}", kind)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: CacheRepositoryTrait> CacheRepositoryTrait for EncryptedCacheRepository<R> {
+    async fn set(
+        &self,
+        key: &str,
+        value: String,
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let value = Zeroizing::new(value);
+        let encrypted = self.encrypt(key, TEXT_VALUE_KIND, value.as_bytes())?;
+
+        self.inner.set_bytes(key, encrypted, tags, cancellation_token).await
+    }
+
+    async fn remove(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.inner.remove(key, cancellation_token).await
+    }
+
+    async fn remove_by_tag(
+        &self,
+        tag: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.inner.remove_by_tag(tag, cancellation_token).await
+    }
+
+    async fn clear(&self) -> anyhow::Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn try_get(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<String>> {
+        self.inner
+            .try_get_bytes(key, cancellation_token)
+            .await?
+            .map(|encrypted| self.decrypt_text(key, &encrypted))
+            .transpose()
+    }
+
+    fn get_by_tags(
+        &self,
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> BoxStream<'_, anyhow::Result<(String, String)>> {
+        // Repositories list binary values in base64, as read back by the default `try_get_bytes`
+        self.inner
+            .get_by_tags(tags, cancellation_token)
+            .map(|result| {
+                let (key, encoded) = result?;
+                let encrypted = general_purpose::STANDARD.decode(encoded)?;
+                let value = self.decrypt_text(&key, &encrypted)?;
+                Ok((key, value))
+            })
+            .boxed()
+    }
+
+    fn watch(&self, key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+        self.inner.watch(key_or_tag)
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let value = Zeroizing::new(value);
+        let encrypted = self.encrypt(key, BYTES_VALUE_KIND, &value)?;
+
+        self.inner.set_bytes(key, encrypted, tags, cancellation_token).await
+    }
+
+    async fn try_get_bytes(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(encrypted) = self.inner.try_get_bytes(key, cancellation_token).await? else {
+            return Ok(None);
+        };
+
+        let (kind, plaintext) = self.decrypt(key, &encrypted)?;
+        match kind {
+            BYTES_VALUE_KIND => Ok(Some(plaintext.to_vec())),
+            TEXT_VALUE_KIND => Ok(Some(general_purpose::STANDARD.decode(&*plaintext)?)),
+            _ => Err(anyhow::anyhow!("Unknown encrypted cache entry kind {}", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCacheRepository;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[tokio::test]
+    async fn values_are_only_stored_encrypted() {
+        let repository = EncryptedCacheRepository::new(InMemoryCacheRepository::new(), &KEY);
+
+        repository
+            .set("passphrase", "secret".to_string(), vec!["user".to_string()], CancellationToken::new())
+            .await
+            .unwrap();
+        repository
+            .set_bytes("key", vec![0, 255], Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            repository.try_get("passphrase", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            repository.try_get_bytes("key", CancellationToken::new()).await.unwrap(),
+            Some(vec![0, 255])
+        );
+        assert_eq!(
+            repository
+                .get_by_tags(vec!["user".to_string()], CancellationToken::new())
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+                .await,
+            vec![("passphrase".to_string(), "secret".to_string())]
+        );
+
+        let stored = repository.inner.try_get_bytes("passphrase", CancellationToken::new()).await.unwrap().unwrap();
+        assert!(!stored.windows(b"secret".len()).any(|window| window == b"secret"));
+    }
+
+    #[tokio::test]
+    async fn values_do_not_decrypt_under_another_key_or_entry() {
+        let repository = EncryptedCacheRepository::new(InMemoryCacheRepository::new(), &KEY);
+        repository
+            .set("passphrase:1", "secret".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        let stored = repository.inner.try_get_bytes("passphrase:1", CancellationToken::new()).await.unwrap().unwrap();
+
+        // Moved under another entry's key
+        repository
+            .inner
+            .set_bytes("passphrase:2", stored.clone(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(repository.try_get("passphrase:2", CancellationToken::new()).await.is_err());
+
+        let other_repository = EncryptedCacheRepository::new(InMemoryCacheRepository::new(), &[8; 32]);
+        other_repository
+            .inner
+            .set_bytes("passphrase:1", stored, Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        assert!(other_repository.try_get("passphrase:1", CancellationToken::new()).await.is_err());
+    }
+}
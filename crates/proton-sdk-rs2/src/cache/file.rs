@@ -0,0 +1,713 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::{CacheChange, CacheChangeNotifier, CacheRepositoryTrait, CacheValue};
+
+const JOURNAL_FILE_NAME: &str = "journal";
+const LOCK_FILE_NAME: &str = "cache.lock";
+const JOURNAL_HEADER_PREFIX: &str = "proton-sdk-cache ";
+const CHANGE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Journals are compacted once they hold this many records more than there are entries.
+const COMPACTION_THRESHOLD: usize = 1000;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct FileCacheEntry {
+    value: CacheValue,
+    tags: HashSet<String>,
+}
+
+/// Line of the journal, each one applying a change to the entries of the previous lines.
+#[derive(Serialize, Deserialize)]
+enum JournalRecord {
+    Set { key: String, entry: FileCacheEntry },
+    Remove { key: String },
+    Clear,
+}
+
+struct FileCacheState {
+    /// Generation of the journal the entries were read from, 0 before the journal exists.
+    generation: u64,
+    /// Length of the journal up to its last complete record, which is where the next read starts.
+    length: u64,
+    record_count: usize,
+    entries: HashMap<String, FileCacheEntry>,
+}
+
+/// Holds an advisory lock on the cache directory for as long as it lives.
+struct DirectoryLock<'a>(&'a File);
+
+impl<'a> DirectoryLock<'a> {
+    fn shared(file: &'a File) -> anyhow::Result<Self> {
+        file.lock_shared()?;
+        Ok(Self(file))
+    }
+
+    fn exclusive(file: &'a File) -> anyhow::Result<Self> {
+        file.lock()?;
+        Ok(Self(file))
+    }
+}
+
+impl Drop for DirectoryLock<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// Cache directory that may be shared by several processes.
+///
+/// The entries are stored as a journal: a header line with the journal's generation, followed by one
+/// line per change. Writes append their records under an exclusive file lock and sync them before
+/// returning, so a crash loses at most the record being written, whose incomplete line is ignored.
+/// Before touching its in-memory copy, a process only reads what was appended since its last read,
+/// or the whole journal if it was compacted into a new generation in the meantime.
+struct FileCacheStore {
+    directory: PathBuf,
+    lock_file: File,
+    state: Mutex<FileCacheState>,
+    notifier: CacheChangeNotifier,
+}
+
+impl FileCacheStore {
+    fn open(directory: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(directory)?;
+
+        let lock_file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(LOCK_FILE_NAME))?;
+
+        let store = Self {
+            directory: directory.to_path_buf(),
+            lock_file,
+            state: Mutex::new(FileCacheState {
+                generation: 0,
+                length: 0,
+                record_count: 0,
+                entries: HashMap::new(),
+            }),
+            notifier: CacheChangeNotifier::new(),
+        };
+
+        store.read(|_| ())?;
+
+        Ok(store)
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.directory.join(JOURNAL_FILE_NAME)
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&HashMap<String, FileCacheEntry>) -> R) -> anyhow::Result<R> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut changes = Vec::new();
+
+        let result = {
+            let _lock = DirectoryLock::shared(&self.lock_file)?;
+            self.sync(&mut state, &mut changes)?;
+            f(&state.entries)
+        };
+        drop(state);
+
+        self.notify(changes);
+        Ok(result)
+    }
+
+    fn write<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<String, FileCacheEntry>, &mut Vec<CacheChange>) -> R,
+    ) -> anyhow::Result<R> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut changes = Vec::new();
+
+        let result = {
+            let _lock = DirectoryLock::exclusive(&self.lock_file)?;
+            self.sync(&mut state, &mut changes)?;
+
+            let synced_changes_count = changes.len();
+            let result = f(&mut state.entries, &mut changes);
+
+            if changes.len() > synced_changes_count {
+                let records = Self::records(&state.entries, &changes[synced_changes_count..]);
+                self.persist(&mut state, &records)?;
+            }
+
+            result
+        };
+        drop(state);
+
+        self.notify(changes);
+        Ok(result)
+    }
+
+    /// Journal records of the changes just made to the entries.
+    fn records(
+        entries: &HashMap<String, FileCacheEntry>,
+        changes: &[CacheChange],
+    ) -> Vec<JournalRecord> {
+        changes
+            .iter()
+            .map(|change| match change {
+                CacheChange::Set { key, .. } => match entries.get(key) {
+                    Some(entry) => JournalRecord::Set {
+                        key: key.clone(),
+                        entry: entry.clone(),
+                    },
+                    None => JournalRecord::Remove { key: key.clone() },
+                },
+                CacheChange::Removed { key, .. } => JournalRecord::Remove { key: key.clone() },
+                CacheChange::Invalidated => JournalRecord::Clear,
+            })
+            .collect()
+    }
+
+    /// Catches up with the records other processes appended to the journal since it was last read.
+    fn sync(
+        &self,
+        state: &mut FileCacheState,
+        changes: &mut Vec<CacheChange>,
+    ) -> anyhow::Result<()> {
+        let mut journal = match File::open(self.journal_path()) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut header = Vec::new();
+        BufReader::new(&journal).read_until(b'\n', &mut header)?;
+        let generation: u64 = std::str::from_utf8(&header)?
+            .strip_suffix('\n')
+            .and_then(|header| header.strip_prefix(JOURNAL_HEADER_PREFIX))
+            .ok_or_else(|| anyhow::anyhow!("Unsupported cache journal format"))?
+            .parse()?;
+
+        let start = if generation == state.generation {
+            state.length
+        } else {
+            header.len() as u64
+        };
+
+        let mut contents = Vec::new();
+        journal.seek(SeekFrom::Start(start))?;
+        journal.read_to_end(&mut contents)?;
+
+        // A record without its line break was cut short by a crash, and is left for the next writer to drop
+        let end = contents
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map_or(0, |position| position + 1);
+        if generation == state.generation && end == 0 {
+            return Ok(());
+        }
+
+        let (mut entries, mut record_count) = if generation == state.generation {
+            (state.entries.clone(), state.record_count)
+        } else {
+            (HashMap::new(), 0)
+        };
+
+        let lines = contents[..end].split(|&byte| byte == b'\n');
+        for line in lines.filter(|line| !line.is_empty()) {
+            match serde_json::from_slice(line)? {
+                JournalRecord::Set { key, entry } => {
+                    entries.insert(key, entry);
+                }
+                JournalRecord::Remove { key } => {
+                    entries.remove(&key);
+                }
+                JournalRecord::Clear => entries.clear(),
+            }
+            record_count += 1;
+        }
+
+        for (key, old_entry) in &state.entries {
+            if !entries.contains_key(key) {
+                changes.push(CacheChange::Removed {
+                    key: key.clone(),
+                    tags: old_entry.tags.iter().cloned().collect(),
+                });
+            }
+        }
+
+        for (key, entry) in &entries {
+            if state.entries.get(key) != Some(entry) {
+                changes.push(CacheChange::Set {
+                    key: key.clone(),
+                    tags: entry.tags.iter().cloned().collect(),
+                });
+            }
+        }
+
+        state.generation = generation;
+        state.length = start + end as u64;
+        state.record_count = record_count;
+        state.entries = entries;
+
+        Ok(())
+    }
+
+    /// Appends the records to the journal, or compacts it into a new generation once it grew too long.
+    fn persist(&self, state: &mut FileCacheState, records: &[JournalRecord]) -> anyhow::Result<()> {
+        let record_count = state.record_count + records.len();
+        if state.generation == 0 || record_count > state.entries.len() + COMPACTION_THRESHOLD {
+            return self.compact(state);
+        }
+
+        let mut lines = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+
+        let mut journal = File::options().write(true).open(self.journal_path())?;
+        // Drops what a crashed writer may have left after the last complete record
+        journal.set_len(state.length)?;
+        journal.seek(SeekFrom::End(0))?;
+        journal.write_all(&lines)?;
+        journal.sync_data()?;
+
+        state.length += lines.len() as u64;
+        state.record_count += records.len();
+
+        Ok(())
+    }
+
+    fn compact(&self, state: &mut FileCacheState) -> anyhow::Result<()> {
+        let generation = state.generation + 1;
+
+        let mut contents = format!("{}{}\n", JOURNAL_HEADER_PREFIX, generation).into_bytes();
+        for (key, entry) in &state.entries {
+            serde_json::to_writer(
+                &mut contents,
+                &JournalRecord::Set {
+                    key: key.clone(),
+                    entry: entry.clone(),
+                },
+            )?;
+            contents.push(b'\n');
+        }
+
+        let path = self.journal_path();
+        let temporary_path = path.with_extension("tmp");
+        let mut temporary_file = File::create(&temporary_path)?;
+        temporary_file.write_all(&contents)?;
+        temporary_file.sync_all()?;
+        drop(temporary_file);
+
+        fs::rename(&temporary_path, &path)?;
+        Self::sync_directory(&self.directory)?;
+
+        state.generation = generation;
+        state.length = contents.len() as u64;
+        state.record_count = state.entries.len();
+
+        Ok(())
+    }
+
+    /// Makes a rename within the directory durable, which only needs doing explicitly on Unix.
+    fn sync_directory(directory: &Path) -> anyhow::Result<()> {
+        #[cfg(unix)]
+        File::open(directory)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = directory;
+
+        Ok(())
+    }
+
+    fn notify(&self, changes: Vec<CacheChange>) {
+        for change in changes {
+            self.notifier.notify(change);
+        }
+    }
+}
+
+/// Cache repository persisted in a directory, suitable for sharing between processes.
+///
+/// Changes made by other processes are picked up on the next access, and are also polled for
+/// periodically while someone is watching the repository.
+pub struct FileCacheRepository {
+    store: Arc<FileCacheStore>,
+    poll_cancellation_token: CancellationToken,
+}
+
+impl FileCacheRepository {
+    pub async fn open(directory: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let directory = directory.into();
+        let store = tokio::task::spawn_blocking(move || FileCacheStore::open(&directory)).await??;
+        let store = Arc::new(store);
+
+        let poll_cancellation_token = CancellationToken::new();
+        tokio::spawn(Self::poll_changes(
+            Arc::downgrade(&store),
+            poll_cancellation_token.clone(),
+        ));
+
+        Ok(Self {
+            store,
+            poll_cancellation_token,
+        })
+    }
+
+    async fn poll_changes(store: Weak<FileCacheStore>, cancellation_token: CancellationToken) {
+        let mut interval = tokio::time::interval(CHANGE_POLL_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+
+            let Some(store) = store.upgrade() else {
+                return;
+            };
+
+            if !store.notifier.has_watchers() {
+                continue;
+            }
+
+            let result = tokio::task::spawn_blocking(move || store.read(|_| ())).await;
+            if let Ok(Err(e)) = result {
+                log::warn!("Failed to check cache directory for changes: {}", e);
+            }
+        }
+    }
+
+    async fn run<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&FileCacheStore) -> anyhow::Result<R> + Send + 'static,
+    ) -> anyhow::Result<R> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&store)).await?
+    }
+
+    async fn insert(&self, key: &str, value: CacheValue, tags: Vec<String>) -> anyhow::Result<()> {
+        let key = key.to_string();
+
+        self.run(move |store| {
+            store.write(|entries, changes| {
+                let tags: HashSet<String> = tags.into_iter().collect();
+
+                changes.push(CacheChange::Set {
+                    key: key.clone(),
+                    tags: tags.iter().cloned().collect(),
+                });
+
+                entries.insert(key, FileCacheEntry { value, tags });
+            })
+        })
+        .await
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<CacheValue>> {
+        let key = key.to_string();
+
+        self.run(move |store| {
+            store.read(|entries| entries.get(&key).map(|entry| entry.value.clone()))
+        })
+        .await
+    }
+}
+
+impl Drop for FileCacheRepository {
+    fn drop(&mut self) {
+        self.poll_cancellation_token.cancel();
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheRepositoryTrait for FileCacheRepository {
+    async fn set(
+        &self,
+        key: &str,
+        value: String,
+        tags: Vec<String>,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.insert(key, CacheValue::Text(value), tags).await
+    }
+
+    async fn remove(
+        &self,
+        key: &str,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let key = key.to_string();
+
+        self.run(move |store| {
+            store.write(|entries, changes| {
+                if let Some(entry) = entries.remove(&key) {
+                    changes.push(CacheChange::Removed {
+                        key,
+                        tags: entry.tags.into_iter().collect(),
+                    });
+                }
+            })
+        })
+        .await
+    }
+
+    async fn remove_by_tag(
+        &self,
+        tag: &str,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let tag = tag.to_string();
+
+        self.run(move |store| {
+            store.write(|entries, changes| {
+                entries.retain(|key, entry| {
+                    if !entry.tags.contains(&tag) {
+                        return true;
+                    }
+
+                    changes.push(CacheChange::Removed {
+                        key: key.clone(),
+                        tags: entry.tags.iter().cloned().collect(),
+                    });
+                    false
+                });
+            })
+        })
+        .await
+    }
+
+    async fn clear(&self) -> anyhow::Result<()> {
+        self.run(|store| {
+            store.write(|entries, changes| {
+                entries.clear();
+                changes.push(CacheChange::Invalidated);
+            })
+        })
+        .await
+    }
+
+    async fn try_get(
+        &self,
+        key: &str,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(self.get(key).await?.map(|value| value.to_text()))
+    }
+
+    fn get_by_tags(
+        &self,
+        tags: Vec<String>,
+        _cancellation_token: CancellationToken,
+    ) -> BoxStream<'_, anyhow::Result<(String, String)>> {
+        let results = self.run(move |store| {
+            store.read(|entries| {
+                entries
+                    .iter()
+                    .filter(|(_, entry)| tags.iter().any(|tag| entry.tags.contains(tag)))
+                    .map(|(key, entry)| (key.clone(), entry.value.to_text()))
+                    .collect::<Vec<_>>()
+            })
+        });
+
+        stream::once(results)
+            .flat_map(|results| match results {
+                Ok(results) => stream::iter(results.into_iter().map(Ok)).left_stream(),
+                Err(e) => stream::iter([Err(e)]).right_stream(),
+            })
+            .boxed()
+    }
+
+    fn watch(&self, key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+        self.store.notifier.watch(key_or_tag)
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        tags: Vec<String>,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.insert(key, CacheValue::Bytes(value), tags).await
+    }
+
+    async fn try_get_bytes(
+        &self,
+        key: &str,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get(key)
+            .await?
+            .map(|value| value.to_bytes())
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    /// Directory of its own under the system temporary directory, removed on drop.
+    struct TemporaryDirectory(PathBuf);
+
+    impl TemporaryDirectory {
+        fn new() -> Self {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+            Self(std::env::temp_dir().join(format!("proton-sdk-cache-{}-{}", std::process::id(), nanos)))
+        }
+    }
+
+    impl Drop for TemporaryDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_survive_reopening() {
+        let directory = TemporaryDirectory::new();
+
+        let repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        repository
+            .set("text", "value".to_string(), vec!["tag".to_string()], CancellationToken::new())
+            .await
+            .unwrap();
+        repository
+            .set_bytes("bytes", vec![0, 255], Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        drop(repository);
+
+        let repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        assert_eq!(
+            repository.try_get("text", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(
+            repository.try_get_bytes("bytes", CancellationToken::new()).await.unwrap(),
+            Some(vec![0, 255])
+        );
+
+        repository.remove_by_tag("tag", CancellationToken::new()).await.unwrap();
+        assert!(repository.try_get("text", CancellationToken::new()).await.unwrap().is_none());
+    }
+
+    /// Two repositories on the same directory stand for two processes, since they share nothing in memory.
+    #[tokio::test]
+    async fn changes_from_another_process_are_seen_and_watched() {
+        let directory = TemporaryDirectory::new();
+        let app_repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        let extension_repository = FileCacheRepository::open(&directory.0).await.unwrap();
+
+        app_repository
+            .set("node:1", "old".to_string(), vec!["volume:1".to_string()], CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            extension_repository.try_get("node:1", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("old")
+        );
+
+        let mut changes = extension_repository.watch("volume:1");
+
+        app_repository
+            .set("node:1", "new".to_string(), vec!["volume:1".to_string()], CancellationToken::new())
+            .await
+            .unwrap();
+
+        // Picked up by the periodic check, without the extension touching the cache
+        let change = tokio::time::timeout(CHANGE_POLL_INTERVAL * 4, changes.next()).await.unwrap();
+        assert_eq!(
+            change,
+            Some(CacheChange::Set {
+                key: "node:1".to_string(),
+                tags: vec!["volume:1".to_string()]
+            })
+        );
+
+        app_repository.remove_by_tag("volume:1", CancellationToken::new()).await.unwrap();
+
+        assert!(extension_repository.try_get("node:1", CancellationToken::new()).await.unwrap().is_none());
+        assert_eq!(
+            changes.next().await,
+            Some(CacheChange::Removed {
+                key: "node:1".to_string(),
+                tags: vec!["volume:1".to_string()]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn record_cut_short_by_a_crash_is_ignored_then_dropped() {
+        let directory = TemporaryDirectory::new();
+
+        let repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        repository
+            .set("kept", "value".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        drop(repository);
+
+        let journal_path = directory.0.join(JOURNAL_FILE_NAME);
+        let mut journal = File::options().append(true).open(&journal_path).unwrap();
+        journal.write_all(br#"{"Set":{"key":"torn","#).unwrap();
+        drop(journal);
+
+        let repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        assert_eq!(
+            repository.try_get("kept", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("value")
+        );
+
+        repository
+            .set("next", "value".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        drop(repository);
+
+        let repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        assert!(repository.try_get("next", CancellationToken::new()).await.unwrap().is_some());
+        assert!(!fs::read_to_string(&journal_path).unwrap().contains("torn"));
+    }
+
+    #[tokio::test]
+    async fn compaction_by_another_process_is_picked_up() {
+        let directory = TemporaryDirectory::new();
+        let app_repository = FileCacheRepository::open(&directory.0).await.unwrap();
+        let extension_repository = FileCacheRepository::open(&directory.0).await.unwrap();
+
+        for value in ["first", "second"] {
+            app_repository
+                .set("node:1", value.to_string(), Vec::new(), CancellationToken::new())
+                .await
+                .unwrap();
+        }
+        assert!(extension_repository.try_get("node:1", CancellationToken::new()).await.unwrap().is_some());
+
+        {
+            let mut state = app_repository.store.state.lock().unwrap();
+            app_repository.store.compact(&mut state).unwrap();
+            assert_eq!(state.record_count, 1);
+        }
+
+        app_repository
+            .set("node:2", "value".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            extension_repository.try_get("node:1", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("second")
+        );
+        assert!(extension_repository.try_get("node:2", CancellationToken::new()).await.unwrap().is_some());
+    }
+}
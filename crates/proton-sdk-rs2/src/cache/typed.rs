@@ -0,0 +1,385 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use futures::TryStreamExt;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio_util::sync::CancellationToken;
+
+use crate::cache::CacheRepositoryTrait;
+
+const SCHEMA_VERSION_LENGTH: usize = size_of::<u32>();
+
+/// Key prefix and schema version shared by every entry of a typed cache.
+///
+/// Entries are stamped with the schema version they were written with, so bumping the version
+/// after an incompatible change to the cached type makes the old entries read as misses.
+struct CacheNamespace {
+    repository: Arc<dyn CacheRepositoryTrait>,
+    name: String,
+    schema_version: u32,
+}
+
+impl CacheNamespace {
+    fn new(
+        repository: Arc<dyn CacheRepositoryTrait>,
+        name: impl Into<String>,
+        schema_version: u32,
+    ) -> Self {
+        Self {
+            repository,
+            name: name.into(),
+            schema_version,
+        }
+    }
+
+    fn get_cache_key(&self, key: &str) -> String {
+        format!("{}:{}", self.name, key)
+    }
+
+    fn get_namespace_tag(&self) -> String {
+        get_namespace_tag(&self.name)
+    }
+
+    fn get_tags(&self, mut tags: Vec<String>) -> Vec<String> {
+        tags.push(self.get_namespace_tag());
+        tags
+    }
+
+    async fn discard(
+        &self,
+        cache_key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        log::debug!("Discarding incompatible cache entry \"{}\"", cache_key);
+        self.repository.remove(cache_key, cancellation_token).await
+    }
+
+    async fn remove(&self, key: &str, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.repository
+            .remove(&self.get_cache_key(key), cancellation_token)
+            .await
+    }
+
+    async fn clear(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.repository
+            .remove_by_tag(&self.get_namespace_tag(), cancellation_token)
+            .await
+    }
+}
+
+fn get_namespace_tag(name: &str) -> String {
+    format!("namespace:{}", name)
+}
+
+/// Removes every entry of a namespace without knowing the type of its values, e.g. from the event loop.
+pub(crate) async fn clear_namespace(
+    repository: &dyn CacheRepositoryTrait,
+    name: &str,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    repository
+        .remove_by_tag(&get_namespace_tag(name), cancellation_token)
+        .await
+}
+
+/// Empties a repository except for the entries of a namespace, e.g. data the account cannot be fetched again for.
+///
+/// The kept entries are written back with the namespace tag alone, so they must not carry other tags.
+pub(crate) async fn clear_all_but_namespace(
+    repository: &dyn CacheRepositoryTrait,
+    name: &str,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let namespace_tag = get_namespace_tag(name);
+    let kept_entries: Vec<(String, String)> = repository
+        .get_by_tags(vec![namespace_tag.clone()], cancellation_token)
+        .try_collect()
+        .await?;
+
+    repository.clear().await?;
+
+    // Not cancellable, since the kept entries would be lost
+    for (key, value) in kept_entries {
+        repository
+            .set(&key, value, vec![namespace_tag.clone()], CancellationToken::new())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Cache of serde-serializable values stored as JSON on top of a [`CacheRepositoryTrait`].
+pub struct TypedCache<T> {
+    namespace: CacheNamespace,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned> TypedCache<T> {
+    pub fn new(
+        repository: Arc<dyn CacheRepositoryTrait>,
+        namespace: impl Into<String>,
+        schema_version: u32,
+    ) -> Self {
+        Self {
+            namespace: CacheNamespace::new(repository, namespace, schema_version),
+            _value: PhantomData,
+        }
+    }
+
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &T,
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let serialized_value = format!(
+            "{}:{}",
+            self.namespace.schema_version,
+            serde_json::to_string(value)?
+        );
+
+        self.namespace
+            .repository
+            .set(
+                &self.namespace.get_cache_key(key),
+                serialized_value,
+                self.namespace.get_tags(tags),
+                cancellation_token,
+            )
+            .await
+    }
+
+    pub async fn try_get(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<T>> {
+        let cache_key = self.namespace.get_cache_key(key);
+        let Some(serialized_value) = self
+            .namespace
+            .repository
+            .try_get(&cache_key, cancellation_token.clone())
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let value = serialized_value
+            .split_once(':')
+            .filter(|(version, _)| version.parse() == Ok(self.namespace.schema_version))
+            .and_then(|(_, json)| serde_json::from_str(json).ok());
+
+        if value.is_none() {
+            self.namespace
+                .discard(&cache_key, cancellation_token)
+                .await?;
+        }
+
+        Ok(value)
+    }
+
+    pub async fn remove(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.namespace.remove(key, cancellation_token).await
+    }
+
+    /// Removes every entry of this namespace, leaving the rest of the repository untouched.
+    pub async fn clear(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.namespace.clear(cancellation_token).await
+    }
+}
+
+/// Cache of raw binary values, e.g. encrypted blobs, stored without base64 when the repository supports it.
+pub struct BytesCache {
+    namespace: CacheNamespace,
+}
+
+impl BytesCache {
+    pub fn new(
+        repository: Arc<dyn CacheRepositoryTrait>,
+        namespace: impl Into<String>,
+        schema_version: u32,
+    ) -> Self {
+        Self {
+            namespace: CacheNamespace::new(repository, namespace, schema_version),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        tags: Vec<String>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut serialized_value = Vec::with_capacity(SCHEMA_VERSION_LENGTH + value.len());
+        serialized_value.extend_from_slice(&self.namespace.schema_version.to_be_bytes());
+        serialized_value.extend_from_slice(value);
+
+        self.namespace
+            .repository
+            .set_bytes(
+                &self.namespace.get_cache_key(key),
+                serialized_value,
+                self.namespace.get_tags(tags),
+                cancellation_token,
+            )
+            .await
+    }
+
+    pub async fn try_get(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let cache_key = self.namespace.get_cache_key(key);
+        let Some(mut serialized_value) = self
+            .namespace
+            .repository
+            .try_get_bytes(&cache_key, cancellation_token.clone())
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let is_compatible = serialized_value
+            .first_chunk::<SCHEMA_VERSION_LENGTH>()
+            .is_some_and(|version| u32::from_be_bytes(*version) == self.namespace.schema_version);
+
+        if !is_compatible {
+            self.namespace
+                .discard(&cache_key, cancellation_token)
+                .await?;
+            return Ok(None);
+        }
+
+        serialized_value.drain(..SCHEMA_VERSION_LENGTH);
+        Ok(Some(serialized_value))
+    }
+
+    pub async fn remove(
+        &self,
+        key: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.namespace.remove(key, cancellation_token).await
+    }
+
+    /// Removes every entry of this namespace, leaving the rest of the repository untouched.
+    pub async fn clear(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.namespace.clear(cancellation_token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine as _, engine::general_purpose};
+    use futures::stream::BoxStream;
+
+    use super::*;
+    use crate::cache::{CacheChange, InMemoryCacheRepository};
+
+    /// Repository holding strings only, so that binary values go through the base64 fallback.
+    struct StringOnlyCacheRepository(InMemoryCacheRepository);
+
+    #[async_trait::async_trait]
+    impl CacheRepositoryTrait for StringOnlyCacheRepository {
+        async fn set(
+            &self,
+            key: &str,
+            value: String,
+            tags: Vec<String>,
+            cancellation_token: CancellationToken,
+        ) -> anyhow::Result<()> {
+            self.0.set(key, value, tags, cancellation_token).await
+        }
+
+        async fn remove(&self, key: &str, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+            self.0.remove(key, cancellation_token).await
+        }
+
+        async fn remove_by_tag(&self, tag: &str, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+            self.0.remove_by_tag(tag, cancellation_token).await
+        }
+
+        async fn clear(&self) -> anyhow::Result<()> {
+            self.0.clear().await
+        }
+
+        async fn try_get(&self, key: &str, cancellation_token: CancellationToken) -> anyhow::Result<Option<String>> {
+            self.0.try_get(key, cancellation_token).await
+        }
+
+        fn get_by_tags(
+            &self,
+            tags: Vec<String>,
+            cancellation_token: CancellationToken,
+        ) -> BoxStream<'_, anyhow::Result<(String, String)>> {
+            self.0.get_by_tags(tags, cancellation_token)
+        }
+
+        fn watch(&self, key_or_tag: &str) -> BoxStream<'static, CacheChange> {
+            self.0.watch(key_or_tag)
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_of_another_schema_version_are_discarded() {
+        let repository = Arc::new(InMemoryCacheRepository::new());
+        let old_cache = TypedCache::<Vec<u32>>::new(repository.clone(), "numbers", 1);
+        let new_cache = TypedCache::<Vec<u32>>::new(repository.clone(), "numbers", 2);
+
+        old_cache.set("key", &vec![1, 2], Vec::new(), CancellationToken::new()).await.unwrap();
+        assert_eq!(old_cache.try_get("key", CancellationToken::new()).await.unwrap(), Some(vec![1, 2]));
+
+        assert_eq!(new_cache.try_get("key", CancellationToken::new()).await.unwrap(), None);
+        assert!(repository.try_get("numbers:key", CancellationToken::new()).await.unwrap().is_none());
+        assert_eq!(old_cache.try_get("key", CancellationToken::new()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn bytes_round_trip_and_honour_the_schema_version() {
+        let repository = Arc::new(InMemoryCacheRepository::new());
+        let cache = BytesCache::new(repository.clone(), "blobs", 1);
+
+        cache.set("key", &[0, 255, 7], Vec::new(), CancellationToken::new()).await.unwrap();
+        cache.set("empty", &[], Vec::new(), CancellationToken::new()).await.unwrap();
+
+        assert_eq!(cache.try_get("key", CancellationToken::new()).await.unwrap(), Some(vec![0, 255, 7]));
+        assert_eq!(cache.try_get("empty", CancellationToken::new()).await.unwrap(), Some(Vec::new()));
+        assert_eq!(cache.try_get("missing", CancellationToken::new()).await.unwrap(), None);
+
+        let newer_cache = BytesCache::new(repository.clone(), "blobs", 2);
+        assert_eq!(newer_cache.try_get("key", CancellationToken::new()).await.unwrap(), None);
+        assert!(repository.try_get_bytes("blobs:key", CancellationToken::new()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn bytes_fall_back_to_base64_on_string_repositories() {
+        let repository = Arc::new(StringOnlyCacheRepository(InMemoryCacheRepository::new()));
+        let cache = BytesCache::new(repository.clone(), "blobs", 1);
+
+        cache.set("key", &[0, 255, 7], Vec::new(), CancellationToken::new()).await.unwrap();
+
+        let stored_value = repository.0.try_get("blobs:key", CancellationToken::new()).await.unwrap().unwrap();
+        assert_eq!(general_purpose::STANDARD.decode(stored_value).unwrap(), [0, 0, 0, 1, 0, 255, 7]);
+        assert_eq!(cache.try_get("key", CancellationToken::new()).await.unwrap(), Some(vec![0, 255, 7]));
+    }
+
+    #[tokio::test]
+    async fn clearing_a_namespace_keeps_the_other_ones() {
+        let repository = Arc::new(InMemoryCacheRepository::new());
+        let numbers = TypedCache::<u32>::new(repository.clone(), "numbers", 1);
+        let names = TypedCache::<String>::new(repository.clone(), "names", 1);
+
+        numbers.set("key", &1, Vec::new(), CancellationToken::new()).await.unwrap();
+        names.set("key", &"alice".to_string(), Vec::new(), CancellationToken::new()).await.unwrap();
+        numbers.clear(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(numbers.try_get("key", CancellationToken::new()).await.unwrap(), None);
+        assert_eq!(names.try_get("key", CancellationToken::new()).await.unwrap(), Some("alice".to_string()));
+    }
+}
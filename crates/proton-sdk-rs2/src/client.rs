@@ -3,13 +3,15 @@ use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
 use crate::{
-    cache::{CacheRepositoryTrait, InMemoryCacheRepository}, proton::{self, ProtonClientTlsPolicy}
+    cache::{CacheRepositoryTrait, InMemoryCacheRepository}, http::ReqwestHttpMessageHandler, proton::{self, ProtonClientTlsPolicy}
 };
 
 // the protos are bad
 pub struct ProtonClientOptions {
     pub base_url: Option<http::Uri>,
     pub user_agent: Option<String>,
+    /// Defaults to [`ProtonClientTlsPolicy::Strict`], which the built-in HTTP handler refuses for lack of
+    /// certificate pinning: without a custom handler, [`ProtonClientTlsPolicy::NoCertificatePinning`] must be set.
     pub tls_policy: Option<ProtonClientTlsPolicy>,
     pub custom_http_message_handler_factory:
         Option<Arc<dyn Fn() -> Box<dyn HttpMessageHandler> + Send + Sync>>,
@@ -22,13 +24,13 @@ pub struct ProtonClientOptions {
     pub(crate) bindings_language: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct ProtonClientConfiguration {
     pub base_url: http::Uri,
     pub app_version: semver::Version,
     pub user_agent: String,
     pub tls_policy: ProtonClientTlsPolicy,
-    pub custom_http_message_handler_factory:
-        Option<Arc<dyn Fn() -> Box<dyn HttpMessageHandler> + Send + Sync>>,
+    pub http_message_handler_factory: Arc<dyn Fn() -> Box<dyn HttpMessageHandler> + Send + Sync>,
     pub secret_cache_repository: Arc<dyn CacheRepositoryTrait>,
     pub entity_cache_repository: Arc<dyn CacheRepositoryTrait>,
     pub telemetry: Arc<dyn TelemetryTrait>,
@@ -48,16 +50,24 @@ impl ProtonClientConfiguration {
         app_version: semver::Version,
         options: ProtonClientOptions,
     ) -> anyhow::Result<Self> {
+        let tls_policy = options.tls_policy.unwrap_or(ProtonClientTlsPolicy::Strict);
+
         Ok(Self {
             base_url: options.base_url.unwrap_or(ProtonApiDefaults::base_url()),
             app_version,
             user_agent: options.user_agent.unwrap_or(String::new()),
-            tls_policy: options.tls_policy.unwrap_or(ProtonClientTlsPolicy::Strict),
-            custom_http_message_handler_factory: options.custom_http_message_handler_factory, // todo: dont make this null, make it smth else
+            tls_policy,
+            http_message_handler_factory: match options.custom_http_message_handler_factory {
+                Some(factory) => factory,
+                None => {
+                    let handler = ReqwestHttpMessageHandler::new(tls_policy)?;
+                    Arc::new(move || Box::new(handler.clone()))
+                }
+            },
             secret_cache_repository: options.secret_cache_repository.unwrap_or(Arc::new(InMemoryCacheRepository::new())),
             entity_cache_repository: options.entity_cache_repository.unwrap_or(Arc::new(InMemoryCacheRepository::new())),
             telemetry: options.telemetry.unwrap_or(Arc::new(NullTelemetry {})),
-            feature_flag_provider: Arc::new(AlwaysDisabledFeatureFlagProvider),
+            feature_flag_provider: options.feature_flag_provider.unwrap_or(Arc::new(AlwaysDisabledFeatureFlagProvider)),
             refresh_redirect_uri: options.refresh_redirect_uri.unwrap_or(ProtonApiDefaults::refresh_redirect_uri()),
             bindings_language: options.bindings_language.clone(),
         })
@@ -82,18 +92,25 @@ impl ProtonApiDefaults {
     }
 }
 
-pub trait HttpMessageHandler: Send + Sync {}
+#[async_trait::async_trait]
+pub trait HttpMessageHandler: Send + Sync {
+    async fn send(
+        &self,
+        request: http::Request<Vec<u8>>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<http::Response<Vec<u8>>>;
+}
 
 #[async_trait::async_trait]
 pub trait TelemetryTrait: Send + Sync {
-    async fn record_metric(&self, name: String, payload: Option<Vec<u8>>);
+    async fn record_metric(&self, name: String, payload: Option<prost_types::Any>);
 }
 
 pub struct NullTelemetry;
 
 #[async_trait::async_trait]
 impl TelemetryTrait for NullTelemetry {
-    async fn record_metric(&self, _name: String, _payload: Option<Vec<u8>>) {}
+    async fn record_metric(&self, _name: String, _payload: Option<prost_types::Any>) {}
 }
 
 #[async_trait::async_trait]
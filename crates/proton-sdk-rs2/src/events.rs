@@ -0,0 +1,380 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::sync::{
+    Mutex,
+    broadcast::{self, error::RecvError},
+    watch,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    EventId, UserId,
+    addresses::{Address, AddressesClient},
+    api::{
+        events::EventsApiClient,
+        response::{EventAction, EventsResponse},
+    },
+    cache::{self, CacheRepositoryTrait},
+    http::HttpClient,
+    public_keys::{PUBLIC_KEYS_CACHE_NAMESPACE, TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE},
+    secret::{SessionSecretCache, SessionSecretCaching},
+    session::SessionSnapshot,
+    users::{User, UsersClient},
+};
+
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// `Refresh` bits telling that some kind of data changed in ways the events do not describe, all of them
+/// (255) meaning that everything must be fetched again.
+const REFRESH_MAIL: u32 = 1;
+const REFRESH_CONTACTS: u32 = 2;
+
+/// Entity cache namespaces invalidated by each partial refresh: the SDK caches no messages, while contacts change
+/// which public keys other users are looked up with.
+///
+/// The keys pinned for other users are never cleared, since the server could otherwise have them served unverified.
+const REFRESH_NAMESPACES: [(u32, &[&str]); 2] = [
+    (REFRESH_MAIL, &[]),
+    (REFRESH_CONTACTS, &[PUBLIC_KEYS_CACHE_NAMESPACE]),
+];
+
+/// Change to the account reported by the core event loop, after the entity cache was updated for it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreEvent {
+    /// The user changed, including their keys.
+    UserUpdated(User),
+    AddressCreated(Address),
+    /// The address changed, including its keys.
+    AddressUpdated(Address),
+    AddressDeleted { address_id: String },
+    /// The user settings changed, in the raw form of the API since the SDK does not use them.
+    UserSettingsUpdated(serde_json::Value),
+    /// Changes were missed, so everything cached about the account was cleared and must be fetched again.
+    RefreshAll,
+}
+
+/// Polls `core/v4/events` from the latest event the session caught up with, keeping the entity cache up to date.
+pub struct CoreEventLoop {
+    api_client: EventsApiClient,
+    users_client: UsersClient,
+    addresses_client: AddressesClient,
+    entity_cache_repository: Arc<dyn CacheRepositoryTrait>,
+    secret_cache: SessionSecretCache,
+    user_id: UserId,
+    snapshot_tx: Arc<watch::Sender<SessionSnapshot>>,
+    poll_lock: Mutex<()>,
+    events_tx: broadcast::Sender<CoreEvent>,
+}
+
+impl CoreEventLoop {
+    pub(crate) fn new(
+        http_client: HttpClient,
+        entity_cache_repository: Arc<dyn CacheRepositoryTrait>,
+        secret_cache_repository: Arc<dyn CacheRepositoryTrait>,
+        snapshot_tx: Arc<watch::Sender<SessionSnapshot>>,
+    ) -> Self {
+        let (events_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let user_id = snapshot_tx.borrow().user_id.clone();
+
+        Self {
+            api_client: EventsApiClient::new(http_client.clone()),
+            users_client: UsersClient::new(http_client.clone(), entity_cache_repository.clone()),
+            addresses_client: AddressesClient::new(http_client, entity_cache_repository.clone()),
+            entity_cache_repository,
+            secret_cache: SessionSecretCache::new(secret_cache_repository),
+            user_id,
+            snapshot_tx,
+            poll_lock: Mutex::new(()),
+            events_tx,
+        }
+    }
+
+    /// Latest event the loop caught up with, also recorded in the session snapshot.
+    pub fn latest_event_id(&self) -> Option<EventId> {
+        self.snapshot_tx.borrow().event_id.clone()
+    }
+
+    /// Stream of the events polled from now on.
+    ///
+    /// A subscriber that falls too far behind receives [`CoreEvent::RefreshAll`] in place of the events it missed.
+    pub fn subscribe(&self) -> BoxStream<'static, CoreEvent> {
+        stream::unfold(self.events_tx.subscribe(), |mut events_rx| async move {
+            match events_rx.recv().await {
+                Ok(event) => Some((event, events_rx)),
+                Err(RecvError::Lagged(_)) => Some((CoreEvent::RefreshAll, events_rx)),
+                Err(RecvError::Closed) => None,
+            }
+        })
+        .boxed()
+    }
+
+    /// Polls every interval until cancelled, failed polls being retried at the next interval.
+    pub async fn run(&self, interval: Duration, cancellation_token: CancellationToken) {
+        loop {
+            if let Err(e) = self.poll(cancellation_token.clone()).await {
+                if cancellation_token.is_cancelled() {
+                    return;
+                }
+                log::warn!("Failed to poll core events: {:#}", e);
+            }
+
+            tokio::select! {
+                _ = cancellation_token.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    /// Fetches and applies every event since the latest one, concurrent polls waiting for each other.
+    ///
+    /// Without a latest event, e.g. for a session resumed from an older snapshot, the loop starts from
+    /// the current event and only reports changes made from then on.
+    pub async fn poll(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        let _poll_guard = self.poll_lock.lock().await;
+
+        let Some(mut event_id) = self.latest_event_id() else {
+            let event_id = self.api_client.get_latest_event_id(cancellation_token).await?;
+            self.set_latest_event_id(event_id);
+            return Ok(());
+        };
+
+        loop {
+            let response = self.api_client.get_events(&event_id, cancellation_token.clone()).await?;
+            let has_more = response.more;
+            event_id = response.event_id.clone();
+
+            self.apply(response, cancellation_token.clone()).await?;
+            self.set_latest_event_id(event_id.clone());
+
+            if !has_more {
+                return Ok(());
+            }
+        }
+    }
+
+    fn set_latest_event_id(&self, event_id: EventId) {
+        self.snapshot_tx.send_modify(|snapshot| snapshot.event_id = Some(event_id));
+    }
+
+    async fn apply(&self, response: EventsResponse, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        let known_refresh_bits = REFRESH_NAMESPACES.iter().fold(0, |bits, (bit, _)| bits | bit);
+
+        // Refreshing everything sets every bit, and the bits this version of the SDK does not know could concern anything it caches
+        if response.refresh & !known_refresh_bits != 0 {
+            cache::typed::clear_all_but_namespace(
+                self.entity_cache_repository.as_ref(),
+                TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE,
+                cancellation_token,
+            )
+            .await?;
+            self.publish(CoreEvent::RefreshAll);
+            return Ok(());
+        }
+
+        for (_, namespaces) in REFRESH_NAMESPACES.iter().filter(|(bit, _)| response.refresh & bit != 0) {
+            for namespace in namespaces.iter() {
+                cache::typed::clear_namespace(self.entity_cache_repository.as_ref(), namespace, cancellation_token.clone())
+                    .await?;
+            }
+        }
+
+        if let Some(user) = response.user {
+            self.users_client
+                .invalidate_user(&self.user_id, cancellation_token.clone())
+                .await?;
+            self.publish(CoreEvent::UserUpdated(user));
+        }
+
+        if !response.addresses.is_empty() {
+            self.addresses_client
+                .invalidate_addresses(&self.user_id, cancellation_token.clone())
+                .await?;
+        }
+
+        for address_event in response.addresses {
+            let event = match (address_event.action, address_event.address) {
+                (EventAction::Delete, _) => {
+                    self.secret_cache
+                        .remove_address_secrets(address_event.address_id.clone(), cancellation_token.clone())
+                        .await?;
+                    CoreEvent::AddressDeleted {
+                        address_id: address_event.address_id,
+                    }
+                }
+                (EventAction::Create, Some(address)) => CoreEvent::AddressCreated(address),
+                (EventAction::Update | EventAction::UpdateFlags, Some(address)) => CoreEvent::AddressUpdated(address),
+                (action, None) => {
+                    log::warn!("Address event {:?} for {} has no address", action, address_event.address_id);
+                    continue;
+                }
+            };
+            self.publish(event);
+        }
+
+        if let Some(user_settings) = response.user_settings {
+            self.publish(CoreEvent::UserSettingsUpdated(user_settings));
+        }
+
+        Ok(())
+    }
+
+    fn publish(&self, event: CoreEvent) {
+        let _ = self.events_tx.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PasswordMode, SessionId, cache::TypedCache, client::ProtonClientConfiguration, testing::FakeHttpMessageHandler,
+    };
+
+    fn create_event_loop(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (CoreEventLoop, ProtonClientConfiguration, FakeHttpMessageHandler) {
+        let handler = responses
+            .into_iter()
+            .fold(FakeHttpMessageHandler::new(), |handler, (path, body)| handler.with_response(path, body));
+        let config = handler.client_config();
+
+        let snapshot = SessionSnapshot {
+            session_id: SessionId::new("session".to_string()),
+            user_id: UserId::new("user-1".to_string()),
+            username: "alice".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            scopes: Vec::new(),
+            is_waiting_for_second_factor_code: false,
+            is_waiting_for_data_password: false,
+            password_mode: PasswordMode::Single,
+            app_version: semver::Version::new(1, 0, 0),
+            event_id: None,
+        };
+
+        let event_loop = CoreEventLoop::new(
+            handler.http_client(&config),
+            config.entity_cache_repository.clone(),
+            config.secret_cache_repository.clone(),
+            Arc::new(watch::channel(snapshot).0),
+        );
+
+        (event_loop, config, handler)
+    }
+
+    #[tokio::test]
+    async fn events_are_applied_until_caught_up() {
+        let (event_loop, _, handler) = create_event_loop(vec![
+            ("/core/v4/events/latest", r#"{"Code": 1000, "EventID": "event-1"}"#),
+            (
+                "/core/v4/events/event-1",
+                r#"{"Code": 1000, "EventID": "event-2", "More": 1, "Addresses": [{"ID": "address-1", "Action": 0}]}"#,
+            ),
+            (
+                "/core/v4/events/event-2",
+                r#"{"Code": 1000, "EventID": "event-3", "More": 0, "UserSettings": {"Locale": "en_US"}}"#,
+            ),
+        ]);
+        let mut events = event_loop.subscribe();
+
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(event_loop.latest_event_id(), Some(EventId::new("event-1".to_string())));
+
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+        assert_eq!(event_loop.latest_event_id(), Some(EventId::new("event-3".to_string())));
+        let paths: Vec<_> = handler.requests().into_iter().map(|request| request.path).collect();
+        assert_eq!(paths, ["/core/v4/events/latest", "/core/v4/events/event-1", "/core/v4/events/event-2"]);
+
+        assert_eq!(
+            events.next().await,
+            Some(CoreEvent::AddressDeleted {
+                address_id: "address-1".to_string()
+            })
+        );
+        assert_eq!(
+            events.next().await,
+            Some(CoreEvent::UserSettingsUpdated(serde_json::json!({"Locale": "en_US"})))
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_clears_the_entity_cache() {
+        let (event_loop, config, _) = create_event_loop(vec![
+            ("/core/v4/events/latest", r#"{"Code": 1000, "EventID": "event-1"}"#),
+            ("/core/v4/events/event-1", r#"{"Code": 1000, "EventID": "event-2", "Refresh": 255}"#),
+        ]);
+        let mut events = event_loop.subscribe();
+
+        config
+            .entity_cache_repository
+            .set("entry", "value".to_string(), Vec::new(), CancellationToken::new())
+            .await
+            .unwrap();
+        let trusted_keys_cache =
+            TypedCache::<String>::new(config.entity_cache_repository.clone(), TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE, 1);
+        trusted_keys_cache.set("bob@example.com", &"keys".to_string(), Vec::new(), CancellationToken::new()).await.unwrap();
+
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(events.next().await, Some(CoreEvent::RefreshAll));
+        assert!(
+            config
+                .entity_cache_repository
+                .try_get("entry", CancellationToken::new())
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            trusted_keys_cache.try_get("bob@example.com", CancellationToken::new()).await.unwrap().as_deref(),
+            Some("keys")
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_refresh_clears_the_namespaces_it_concerns() {
+        let (event_loop, config, _) = create_event_loop(vec![
+            ("/core/v4/events/latest", r#"{"Code": 1000, "EventID": "event-1"}"#),
+            (
+                "/core/v4/events/event-1",
+                r#"{"Code": 1000, "EventID": "event-2", "Refresh": 3, "UserSettings": {"Locale": "en_US"}}"#,
+            ),
+        ]);
+        let mut events = event_loop.subscribe();
+
+        let public_keys_cache = TypedCache::<String>::new(config.entity_cache_repository.clone(), PUBLIC_KEYS_CACHE_NAMESPACE, 1);
+        let trusted_keys_cache =
+            TypedCache::<String>::new(config.entity_cache_repository.clone(), TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE, 1);
+        let other_cache = TypedCache::<String>::new(config.entity_cache_repository.clone(), "other", 1);
+        public_keys_cache.set("bob@example.com", &"keys".to_string(), Vec::new(), CancellationToken::new()).await.unwrap();
+        trusted_keys_cache.set("bob@example.com", &"keys".to_string(), Vec::new(), CancellationToken::new()).await.unwrap();
+        other_cache.set("entry", &"value".to_string(), Vec::new(), CancellationToken::new()).await.unwrap();
+
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(
+            events.next().await,
+            Some(CoreEvent::UserSettingsUpdated(serde_json::json!({"Locale": "en_US"})))
+        );
+        assert!(public_keys_cache.try_get("bob@example.com", CancellationToken::new()).await.unwrap().is_none());
+        assert!(trusted_keys_cache.try_get("bob@example.com", CancellationToken::new()).await.unwrap().is_some());
+        assert!(other_cache.try_get("entry", CancellationToken::new()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn unknown_refresh_bit_clears_the_entity_cache() {
+        let (event_loop, _, _) = create_event_loop(vec![
+            ("/core/v4/events/latest", r#"{"Code": 1000, "EventID": "event-1"}"#),
+            ("/core/v4/events/event-1", r#"{"Code": 1000, "EventID": "event-2", "Refresh": 4}"#),
+        ]);
+        let mut events = event_loop.subscribe();
+
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+        event_loop.poll(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(events.next().await, Some(CoreEvent::RefreshAll));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::features::FeaturesApiClient,
+    client::{FeatureFlagProvider, ProtonClientConfiguration},
+    http::HttpClient,
+};
+
+struct FetchedToggles {
+    enabled_flags: HashSet<String>,
+    fetched_at: Instant,
+}
+
+/// [`FeatureFlagProvider`] backed by the Unleash frontend API that Proton serves at `feature/v2/frontend`.
+///
+/// Toggles are fetched on first use and again once older than the refresh interval. If a refresh fails,
+/// the previous toggles keep being used until the next attempt.
+pub struct UnleashFeatureFlagProvider {
+    api_client: FeaturesApiClient,
+    refresh_interval: Duration,
+    toggles: Mutex<Option<FetchedToggles>>,
+    overrides: DashMap<String, bool>,
+}
+
+impl UnleashFeatureFlagProvider {
+    pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+    /// Provider fetching the toggles without a session, i.e. those that do not target specific users.
+    pub fn new(config: &ProtonClientConfiguration, refresh_interval: Duration) -> Self {
+        let http_client = HttpClient::new(config, Arc::from((config.http_message_handler_factory)()), None, None, None);
+
+        Self::with_http_client(http_client, refresh_interval)
+    }
+
+    pub(crate) fn with_http_client(http_client: HttpClient, refresh_interval: Duration) -> Self {
+        Self {
+            api_client: FeaturesApiClient::new(http_client),
+            refresh_interval,
+            toggles: Mutex::new(None),
+            overrides: DashMap::new(),
+        }
+    }
+
+    /// Forces a flag on or off regardless of the toggles, e.g. for testing.
+    pub fn set_override(&self, flag_name: impl Into<String>, is_enabled: bool) {
+        self.overrides.insert(flag_name.into(), is_enabled);
+    }
+
+    pub fn remove_override(&self, flag_name: &str) {
+        self.overrides.remove(flag_name);
+    }
+
+    async fn refresh_if_stale(
+        &self,
+        toggles: &mut Option<FetchedToggles>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        if toggles
+            .as_ref()
+            .is_some_and(|toggles| toggles.fetched_at.elapsed() < self.refresh_interval)
+        {
+            return Ok(());
+        }
+
+        match self.api_client.get_toggles(cancellation_token).await {
+            Ok(response) => {
+                *toggles = Some(FetchedToggles {
+                    enabled_flags: response
+                        .toggles
+                        .into_iter()
+                        .filter(|toggle| toggle.enabled)
+                        .map(|toggle| toggle.name)
+                        .collect(),
+                    fetched_at: Instant::now(),
+                });
+                Ok(())
+            }
+            Err(e) if toggles.is_some() => {
+                log::warn!("Failed to refresh feature flags, keeping the previous ones: {:#}", e);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl FeatureFlagProvider for UnleashFeatureFlagProvider {
+    async fn is_enabled(&self, flag_name: String, cancellation_token: CancellationToken) -> anyhow::Result<bool> {
+        if let Some(is_enabled) = self.overrides.get(&flag_name) {
+            return Ok(*is_enabled);
+        }
+
+        // Holding the lock while fetching makes concurrent callers share a single request
+        let mut toggles = self.toggles.lock().await;
+        self.refresh_if_stale(&mut toggles, cancellation_token).await?;
+
+        Ok(toggles
+            .as_ref()
+            .is_some_and(|toggles| toggles.enabled_flags.contains(&flag_name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::ProtonClientOptions, proton::ProtonClientTlsPolicy, testing::FakeHttpMessageHandler};
+
+    const TOGGLES_RESPONSE: &str =
+        r#"{"Code": 1000, "toggles": [{"name": "DriveSharing", "enabled": true, "impressionData": false}]}"#;
+
+    fn toggles_handler() -> FakeHttpMessageHandler {
+        FakeHttpMessageHandler::new().with_response("/feature/v2/frontend", TOGGLES_RESPONSE)
+    }
+
+    fn create_provider(handler: &FakeHttpMessageHandler, refresh_interval: Duration) -> UnleashFeatureFlagProvider {
+        let config = handler.client_config();
+
+        UnleashFeatureFlagProvider::with_http_client(handler.http_client(&config), refresh_interval)
+    }
+
+    async fn is_enabled(provider: &UnleashFeatureFlagProvider, flag_name: &str) -> bool {
+        provider
+            .is_enabled(flag_name.to_string(), CancellationToken::new())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn toggles_are_fetched_once_per_refresh_interval() {
+        let handler = toggles_handler();
+        let provider = create_provider(&handler, UnleashFeatureFlagProvider::DEFAULT_REFRESH_INTERVAL);
+
+        assert!(is_enabled(&provider, "DriveSharing").await);
+        assert!(!is_enabled(&provider, "DrivePhotos").await);
+        assert_eq!(handler.request_count(), 1);
+
+        let provider = create_provider(&handler, Duration::ZERO);
+        is_enabled(&provider, "DriveSharing").await;
+        is_enabled(&provider, "DriveSharing").await;
+        assert_eq!(handler.request_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn overrides_take_precedence() {
+        let handler = toggles_handler();
+        let provider = create_provider(&handler, UnleashFeatureFlagProvider::DEFAULT_REFRESH_INTERVAL);
+
+        provider.set_override("DriveSharing", false);
+        provider.set_override("DrivePhotos", true);
+        assert!(!is_enabled(&provider, "DriveSharing").await);
+        assert!(is_enabled(&provider, "DrivePhotos").await);
+        assert_eq!(handler.request_count(), 0);
+
+        provider.remove_override("DriveSharing");
+        assert!(is_enabled(&provider, "DriveSharing").await);
+    }
+
+    #[tokio::test]
+    async fn configuration_uses_the_provided_provider() {
+        let provider = Arc::new(create_provider(
+            &toggles_handler(),
+            UnleashFeatureFlagProvider::DEFAULT_REFRESH_INTERVAL,
+        ));
+        provider.set_override("DrivePhotos", true);
+
+        let options = ProtonClientOptions {
+            tls_policy: Some(ProtonClientTlsPolicy::NoCertificatePinning),
+            feature_flag_provider: Some(provider),
+            ..Default::default()
+        };
+        let config = ProtonClientConfiguration::new(semver::Version::new(1, 0, 0), options).unwrap();
+
+        assert!(
+            config
+                .feature_flag_provider
+                .is_enabled("DrivePhotos".to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    OperationCancelledError, SessionId,
+    api::ApiResponse,
+    auth::TokenCredential,
+    client::{HttpMessageHandler, ProtonApiDefaults, ProtonClientConfiguration, TelemetryTrait},
+    proton::{self, HttpRequestType, ProtonClientTlsPolicy},
+    telemetry,
+};
+
+pub(crate) const SESSION_ID_HEADER_NAME: HeaderName = HeaderName::from_static("x-pm-uid");
+const APP_VERSION_HEADER_NAME: HeaderName = HeaderName::from_static("x-pm-appversion");
+const API_MEDIA_TYPE: &str = "application/vnd.protonmail.v1+json";
+
+/// Client for the Proton JSON API, optionally authenticated with a session's tokens.
+///
+/// Authenticated requests rejected with `401 Unauthorized` are retried once with refreshed tokens.
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    handler: Arc<dyn HttpMessageHandler>,
+    base_url: String,
+    default_headers: HeaderMap,
+    credential: Option<(SessionId, Arc<TokenCredential>)>,
+    request_type: HttpRequestType,
+    telemetry: Arc<dyn TelemetryTrait>,
+    attempt_timeout: Duration,
+    total_timeout: Duration,
+}
+
+impl HttpClient {
+    pub(crate) fn new(
+        config: &ProtonClientConfiguration,
+        handler: Arc<dyn HttpMessageHandler>,
+        base_route_path: Option<String>,
+        attempt_timeout: Option<Duration>,
+        total_timeout: Option<Duration>,
+    ) -> Self {
+        let default_timeout = Duration::from_secs(ProtonApiDefaults::DEFAULT_TIMEOUT_SECONDS.into());
+
+        let mut base_url = config.base_url.to_string();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        if let Some(base_route_path) = base_route_path {
+            base_url.push_str(base_route_path.trim_matches('/'));
+            base_url.push('/');
+        }
+
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(header::ACCEPT, HeaderValue::from_static(API_MEDIA_TYPE));
+        if let Ok(app_version) = HeaderValue::from_str(&config.app_version.to_string()) {
+            default_headers.insert(APP_VERSION_HEADER_NAME, app_version);
+        }
+        if let Ok(user_agent) = HeaderValue::from_str(&config.user_agent) {
+            if !config.user_agent.is_empty() {
+                default_headers.insert(header::USER_AGENT, user_agent);
+            }
+        }
+
+        Self {
+            handler,
+            base_url,
+            default_headers,
+            credential: None,
+            request_type: HttpRequestType::RegularApi,
+            telemetry: config.telemetry.clone(),
+            attempt_timeout: attempt_timeout.unwrap_or(default_timeout),
+            total_timeout: total_timeout.unwrap_or(default_timeout),
+        }
+    }
+
+    pub(crate) fn with_credential(mut self, session_id: SessionId, token_credential: Arc<TokenCredential>) -> Self {
+        self.credential = Some((session_id, token_credential));
+        self
+    }
+
+    /// Marks the requests as storage transfers rather than API calls, for handlers that treat them differently.
+    ///
+    /// The type is attached to every request as an [`HttpRequestType`] extension.
+    pub(crate) fn with_request_type(mut self, request_type: HttpRequestType) -> Self {
+        self.request_type = request_type;
+        self
+    }
+
+    pub(crate) async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<T> {
+        self.send(Method::GET, path, None, HeaderMap::new(), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<T> {
+        self.send(Method::POST, path, Some(serde_json::to_vec(body)?), HeaderMap::new(), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn put<B: Serialize, T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &B,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<T> {
+        self.send(Method::PUT, path, Some(serde_json::to_vec(body)?), HeaderMap::new(), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn delete<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<T> {
+        self.send(Method::DELETE, path, None, HeaderMap::new(), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+        headers: HeaderMap,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<T> {
+        let url = format!("{}{}", self.base_url, path.trim_start_matches('/'));
+
+        let attempts = async {
+            let access_token = match &self.credential {
+                Some((_, token_credential)) => {
+                    Some(token_credential.get_access_token(cancellation_token.clone()).await?.0)
+                }
+                None => None,
+            };
+
+            let response = self
+                .send_attempt(&method, &url, &body, &headers, access_token.as_deref(), &cancellation_token)
+                .await?;
+
+            let response = match (&self.credential, access_token) {
+                (Some((_, token_credential)), Some(rejected_access_token))
+                    if response.status() == StatusCode::UNAUTHORIZED =>
+                {
+                    let access_token = token_credential
+                        .get_refreshed_access_token(rejected_access_token, cancellation_token.clone())
+                        .await?;
+
+                    let response = self
+                        .send_attempt(&method, &url, &body, &headers, Some(&access_token), &cancellation_token)
+                        .await?;
+
+                    if response.status().is_success() {
+                        let payload = proton::ApiRetrySucceededEventPayload {
+                            url: url.clone(),
+                            failed_attempts: 1,
+                        };
+                        telemetry::record_metric(self.telemetry.as_ref(), &payload).await;
+                    }
+
+                    response
+                }
+                _ => response,
+            };
+
+            Self::read_response(response)
+        };
+
+        // Biased so that a request cancelled beforehand is never sent
+        tokio::select! {
+            biased;
+            _ = cancellation_token.cancelled() => Err(OperationCancelledError.into()),
+            result = tokio::time::timeout(self.total_timeout, attempts) => {
+                result.map_err(|_| anyhow::anyhow!("Request to {} timed out", url))?
+            }
+        }
+    }
+
+    async fn send_attempt(
+        &self,
+        method: &Method,
+        url: &str,
+        body: &Option<Vec<u8>>,
+        headers: &HeaderMap,
+        access_token: Option<&str>,
+        cancellation_token: &CancellationToken,
+    ) -> anyhow::Result<http::Response<Vec<u8>>> {
+        let mut request = http::Request::builder().method(method.clone()).uri(url);
+
+        let request_headers = request
+            .headers_mut()
+            .ok_or_else(|| anyhow::anyhow!("Invalid request to {}", url))?;
+        request_headers.extend(self.default_headers.clone());
+        request_headers.extend(headers.clone());
+
+        if let Some((session_id, _)) = &self.credential {
+            request_headers.insert(SESSION_ID_HEADER_NAME, HeaderValue::from_str(session_id.raw())?);
+        }
+        if let Some(access_token) = access_token {
+            request_headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", access_token))?);
+        }
+        if body.is_some() {
+            request_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+
+        let mut request = request.body(body.clone().unwrap_or_default())?;
+        request.extensions_mut().insert(self.request_type);
+
+        tokio::time::timeout(self.attempt_timeout, self.handler.send(request, cancellation_token.clone()))
+            .await
+            .map_err(|_| anyhow::anyhow!("Request attempt to {} timed out", url))?
+    }
+
+    fn read_response<T: DeserializeOwned>(response: http::Response<Vec<u8>>) -> anyhow::Result<T> {
+        let status = response.status();
+
+        match serde_json::from_slice::<ApiResponse>(response.body()) {
+            Ok(api_response) => api_response.into_result(status)?,
+            Err(_) if !status.is_success() => {
+                return Err(anyhow::anyhow!("Request failed with HTTP status {}", status));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(serde_json::from_slice(response.body())?)
+    }
+}
+
+/// Default [`HttpMessageHandler`], backed by `reqwest`.
+#[derive(Clone)]
+pub(crate) struct ReqwestHttpMessageHandler {
+    client: reqwest::Client,
+}
+
+impl ReqwestHttpMessageHandler {
+    /// Fails with the [`ProtonClientTlsPolicy::Strict`] policy, since the handler cannot pin certificates:
+    /// going without pinning takes asking for [`ProtonClientTlsPolicy::NoCertificatePinning`].
+    pub(crate) fn new(tls_policy: ProtonClientTlsPolicy) -> anyhow::Result<Self> {
+        let builder = reqwest::Client::builder();
+
+        let builder = match tls_policy {
+            ProtonClientTlsPolicy::Strict => {
+                return Err(anyhow::anyhow!(
+                    "Certificate pinning is not supported by the default HTTP handler, \
+                     either provide a handler or use the NoCertificatePinning TLS policy"
+                ));
+            }
+            ProtonClientTlsPolicy::NoCertificatePinning => builder,
+            ProtonClientTlsPolicy::NoCertificateValidation => builder.danger_accept_invalid_certs(true),
+        };
+
+        Ok(Self {
+            client: builder.build().context("Failed to initialize the TLS backend")?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpMessageHandler for ReqwestHttpMessageHandler {
+    async fn send(
+        &self,
+        request: http::Request<Vec<u8>>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<http::Response<Vec<u8>>> {
+        let request = reqwest::Request::try_from(request)?;
+
+        tokio::select! {
+            _ = cancellation_token.cancelled() => Err(OperationCancelledError.into()),
+            result = async {
+                let response = self.client.execute(request).await?;
+
+                let mut builder = http::Response::builder().status(response.status());
+                if let Some(headers) = builder.headers_mut() {
+                    headers.extend(response.headers().clone());
+                }
+
+                Ok(builder.body(response.bytes().await?.to_vec())?)
+            } => result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttpMessageHandler;
+
+    #[test]
+    fn default_handler_requires_opting_out_of_certificate_pinning() {
+        assert!(ReqwestHttpMessageHandler::new(ProtonClientTlsPolicy::Strict).is_err());
+        assert!(ReqwestHttpMessageHandler::new(ProtonClientTlsPolicy::NoCertificatePinning).is_ok());
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_type_of_their_client() {
+        let handler = FakeHttpMessageHandler::new()
+            .with_response("/tests/ping", r#"{"Code": 1000}"#)
+            .with_response("/blocks", r#"{"Code": 1000}"#);
+        let http_client = handler.http_client(&handler.client_config());
+
+        http_client
+            .get::<ApiResponse>("tests/ping", CancellationToken::new())
+            .await
+            .unwrap();
+        http_client
+            .with_request_type(HttpRequestType::StorageUpload)
+            .post::<_, ApiResponse>("blocks", &"content", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let requests = handler.requests();
+        assert_eq!(requests[1].method, Method::POST);
+        assert_eq!(requests[1].body, br#""content""#);
+        let request_types: Vec<_> = requests.iter().map(|request| request.request_type).collect();
+        assert_eq!(request_types, [Some(HttpRequestType::RegularApi), Some(HttpRequestType::StorageUpload)]);
+    }
+}
@@ -0,0 +1,485 @@
+use proton_crypto::crypto::{
+    DataEncoding, Decryptor, DecryptorSync, PGPProviderSync, VerifiedData, Verifier, VerifierSync,
+};
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use crate::{
+    addresses::{Address, AddressKey},
+    secret::SessionSecretCaching,
+    users::UserKey,
+};
+
+/// Private key unlocked with its passphrase, both kept in memory until dropped.
+///
+/// Both are wiped on drop: the passphrase is zeroized here, while the key material only lives in the
+/// crypto provider's key, which clears its secret parameters when dropped, so no copy of it is kept
+/// outside of the provider.
+pub struct UnlockedKey<K> {
+    id: String,
+    fingerprint: Option<String>,
+    /// Address the key belongs to, `None` for user keys.
+    address_id: Option<String>,
+    is_primary: bool,
+    /// Whether the key is an address key locked with the passphrase of the user keys rather than its own.
+    is_legacy: bool,
+    private_key: K,
+    passphrase: Zeroizing<Vec<u8>>,
+}
+
+impl<K> UnlockedKey<K> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+
+    pub fn address_id(&self) -> Option<&str> {
+        self.address_id.as_deref()
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.is_primary
+    }
+
+    pub fn private_key(&self) -> &K {
+        &self.private_key
+    }
+
+    pub(crate) fn is_legacy(&self) -> bool {
+        self.is_legacy
+    }
+}
+
+#[cfg(test)]
+impl<K> UnlockedKey<K> {
+    pub(crate) fn new(id: &str, address_id: Option<&str>, private_key: K, passphrase: &[u8]) -> Self {
+        Self {
+            id: id.to_string(),
+            fingerprint: None,
+            address_id: address_id.map(str::to_string),
+            is_primary: false,
+            is_legacy: false,
+            private_key,
+            passphrase: Zeroizing::new(passphrase.to_vec()),
+        }
+    }
+}
+
+/// Unlocked keys of a user and their addresses.
+///
+/// Keys whose passphrase is unknown, e.g. because the data password was not applied yet, are left out
+/// rather than failing the whole keyring.
+pub struct Keyring<P: PGPProviderSync> {
+    provider: P,
+    user_keys: Vec<UnlockedKey<P::PrivateKey>>,
+    address_keys: Vec<UnlockedKey<P::PrivateKey>>,
+}
+
+impl<P: PGPProviderSync> Keyring<P> {
+    /// Unlocks the active user and address keys with the passphrases from the secret cache.
+    ///
+    /// Address key passphrases missing from the cache are derived from the user keys and cached:
+    /// migrated keys carry their passphrase in a token encrypted and signed with a user key, legacy keys
+    /// share the passphrase of the user keys.
+    pub(crate) async fn unlock(
+        provider: P,
+        user_keys: &[UserKey],
+        addresses: &[Address],
+        secret_cache: &dyn SessionSecretCaching,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        let (user_keys, address_keys) =
+            unlock_keys(&provider, user_keys, addresses, secret_cache, cancellation_token).await?;
+
+        Ok(Self {
+            provider,
+            user_keys,
+            address_keys,
+        })
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    pub fn user_keys(&self) -> &[UnlockedKey<P::PrivateKey>] {
+        &self.user_keys
+    }
+
+    pub fn primary_user_key(&self) -> Option<&UnlockedKey<P::PrivateKey>> {
+        find_primary_key(self.user_keys.iter())
+    }
+
+    pub fn address_keys<'a>(&'a self, address_id: &'a str) -> impl Iterator<Item = &'a UnlockedKey<P::PrivateKey>> {
+        self.address_keys
+            .iter()
+            .filter(move |key| key.address_id.as_deref() == Some(address_id))
+    }
+
+    pub fn primary_address_key<'a>(&'a self, address_id: &'a str) -> Option<&'a UnlockedKey<P::PrivateKey>> {
+        find_primary_key(self.address_keys(address_id))
+    }
+
+    /// Looks up a user or address key by its ID.
+    pub fn get_key(&self, key_id: &str) -> Option<&UnlockedKey<P::PrivateKey>> {
+        self.all_keys().find(|key| key.id == key_id)
+    }
+
+    /// Looks up a user or address key by its fingerprint, in any letter case.
+    pub fn get_key_by_fingerprint(&self, fingerprint: &str) -> Option<&UnlockedKey<P::PrivateKey>> {
+        find_key_by_fingerprint(self.all_keys(), fingerprint)
+    }
+
+    pub(crate) fn all_keys(&self) -> impl Iterator<Item = &UnlockedKey<P::PrivateKey>> {
+        self.user_keys.iter().chain(self.address_keys.iter())
+    }
+}
+
+/// Primary key among the given ones, falling back to the first one when none is marked primary.
+fn find_primary_key<'a, K>(mut keys: impl Iterator<Item = &'a UnlockedKey<K>>) -> Option<&'a UnlockedKey<K>> {
+    let first_key = keys.next()?;
+    if first_key.is_primary {
+        return Some(first_key);
+    }
+
+    keys.find(|key| key.is_primary).or(Some(first_key))
+}
+
+fn find_key_by_fingerprint<'a, K>(
+    mut keys: impl Iterator<Item = &'a UnlockedKey<K>>,
+    fingerprint: &str,
+) -> Option<&'a UnlockedKey<K>> {
+    keys.find(|key| {
+        key.fingerprint
+            .as_deref()
+            .is_some_and(|key_fingerprint| key_fingerprint.eq_ignore_ascii_case(fingerprint))
+    })
+}
+
+/// Crypto operations needed to unlock the keys of a user, implemented by every PGP provider.
+pub(crate) trait KeyUnlocking {
+    type PrivateKey;
+
+    fn import_private_key(&self, armored_private_key: &str, passphrase: &[u8]) -> anyhow::Result<Self::PrivateKey>;
+
+    fn decrypt_token(&self, armored_token: &str, user_key: &Self::PrivateKey) -> anyhow::Result<Zeroizing<Vec<u8>>>;
+
+    fn verify_token_signature(&self, passphrase: &[u8], armored_signature: &str, user_key: &Self::PrivateKey) -> bool;
+}
+
+impl<P: PGPProviderSync> KeyUnlocking for P {
+    type PrivateKey = P::PrivateKey;
+
+    fn import_private_key(&self, armored_private_key: &str, passphrase: &[u8]) -> anyhow::Result<Self::PrivateKey> {
+        Ok(self.private_key_import(armored_private_key, passphrase, DataEncoding::Armor)?)
+    }
+
+    fn decrypt_token(&self, armored_token: &str, user_key: &Self::PrivateKey) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+        let decrypted_token = self
+            .new_decryptor()
+            .with_decryption_key(user_key)
+            .decrypt(armored_token, DataEncoding::Armor)?;
+
+        Ok(Zeroizing::new(decrypted_token.into_vec()))
+    }
+
+    fn verify_token_signature(&self, passphrase: &[u8], armored_signature: &str, user_key: &Self::PrivateKey) -> bool {
+        self.private_key_to_public_key(user_key).is_ok_and(|public_key| {
+            self.new_verifier()
+                .with_verification_key(&public_key)
+                .verify_detached(passphrase, armored_signature, DataEncoding::Armor)
+                .is_ok()
+        })
+    }
+}
+
+type UnlockedKeys<K> = (Vec<UnlockedKey<K>>, Vec<UnlockedKey<K>>);
+
+/// Unlocks the user keys, then the address keys, see [`Keyring::unlock`].
+async fn unlock_keys<C: KeyUnlocking>(
+    crypto: &C,
+    user_keys: &[UserKey],
+    addresses: &[Address],
+    secret_cache: &dyn SessionSecretCaching,
+    cancellation_token: CancellationToken,
+) -> anyhow::Result<UnlockedKeys<C::PrivateKey>> {
+    let mut unlocked_user_keys = Vec::new();
+    let mut unlocked_address_keys = Vec::new();
+
+    for user_key in user_keys.iter().filter(|key| key.is_active) {
+        let Some(passphrase) = secret_cache
+            .try_get_account_key_passphrase(user_key.id.clone(), cancellation_token.clone())
+            .await?
+        else {
+            continue;
+        };
+        let passphrase = Zeroizing::new(passphrase);
+
+        match crypto.import_private_key(&user_key.private_key, &passphrase) {
+            Ok(private_key) => unlocked_user_keys.push(UnlockedKey {
+                id: user_key.id.clone(),
+                fingerprint: user_key.fingerprint.clone(),
+                address_id: None,
+                is_primary: user_key.is_primary,
+                is_legacy: false,
+                private_key,
+                passphrase,
+            }),
+            Err(e) => log::warn!("Failed to unlock user key {}: {}", user_key.id, e),
+        }
+    }
+
+    if unlocked_user_keys.is_empty() {
+        log::debug!("No user key could be unlocked, address keys stay locked");
+        return Ok((unlocked_user_keys, unlocked_address_keys));
+    }
+
+    for address in addresses {
+        for address_key in address.keys.iter().filter(|key| key.is_active) {
+            let cached_passphrase = secret_cache
+                .try_get_address_key_passphrase(address_key.id.clone(), cancellation_token.clone())
+                .await?
+                .map(Zeroizing::new);
+
+            let passphrase = match cached_passphrase {
+                Some(passphrase) => passphrase,
+                None => match derive_address_key_passphrase(crypto, &unlocked_user_keys, address_key) {
+                    Ok(passphrase) => {
+                        secret_cache
+                            .set_address_key_passphrase(
+                                address.id.clone(),
+                                address_key.id.clone(),
+                                &passphrase,
+                                cancellation_token.clone(),
+                            )
+                            .await?;
+                        passphrase
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to unlock key {} of address {}: {}", address_key.id, address.id, e);
+                        continue;
+                    }
+                },
+            };
+
+            match crypto.import_private_key(&address_key.private_key, &passphrase) {
+                Ok(private_key) => unlocked_address_keys.push(UnlockedKey {
+                    id: address_key.id.clone(),
+                    fingerprint: address_key.fingerprint.clone(),
+                    address_id: Some(address.id.clone()),
+                    is_primary: address_key.is_primary,
+                    is_legacy: address_key.token.is_none(),
+                    private_key,
+                    passphrase,
+                }),
+                Err(e) => log::warn!("Failed to unlock key {} of address {}: {}", address_key.id, address.id, e),
+            }
+        }
+    }
+
+    Ok((unlocked_user_keys, unlocked_address_keys))
+}
+
+fn derive_address_key_passphrase<C: KeyUnlocking>(
+    crypto: &C,
+    user_keys: &[UnlockedKey<C::PrivateKey>],
+    address_key: &AddressKey,
+) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let Some(token) = &address_key.token else {
+        // Legacy keys are locked with the same passphrase as the user keys
+        return find_primary_key(user_keys.iter())
+            .map(|user_key| user_key.passphrase.clone())
+            .ok_or_else(|| anyhow::anyhow!("No user key is unlocked"));
+    };
+
+    let signature = address_key
+        .signature
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Token of key {} is not signed", address_key.id))?;
+
+    for user_key in user_keys {
+        let Ok(passphrase) = crypto.decrypt_token(token, &user_key.private_key) else {
+            continue;
+        };
+
+        if user_keys
+            .iter()
+            .any(|user_key| crypto.verify_token_signature(&passphrase, signature, &user_key.private_key))
+        {
+            return Ok(passphrase);
+        }
+
+        return Err(anyhow::anyhow!("Invalid token signature for key {}", address_key.id));
+    }
+
+    Err(anyhow::anyhow!("No user key can decrypt the token of key {}", address_key.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        addresses::{AddressKeyFlags, AddressStatus},
+        cache::InMemoryCacheRepository,
+        secret::SessionSecretCache,
+    };
+
+    /// Crypto where a private key is `"<name> locked with <passphrase>"`, a token `"<passphrase> for <user key name>"`
+    /// and a token signature `"<passphrase> signed by <user key name>"`.
+    struct FakeCrypto;
+
+    impl KeyUnlocking for FakeCrypto {
+        type PrivateKey = String;
+
+        fn import_private_key(&self, armored_private_key: &str, passphrase: &[u8]) -> anyhow::Result<String> {
+            let (name, key_passphrase) = armored_private_key.split_once(" locked with ").unwrap();
+            anyhow::ensure!(key_passphrase.as_bytes() == passphrase, "Wrong passphrase");
+
+            Ok(name.to_string())
+        }
+
+        fn decrypt_token(&self, armored_token: &str, user_key: &String) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+            let (passphrase, recipient) = armored_token.split_once(" for ").unwrap();
+            anyhow::ensure!(recipient == user_key, "Token is not encrypted to {}", user_key);
+
+            Ok(Zeroizing::new(passphrase.as_bytes().to_vec()))
+        }
+
+        fn verify_token_signature(&self, passphrase: &[u8], armored_signature: &str, user_key: &String) -> bool {
+            armored_signature == format!("{} signed by {}", String::from_utf8_lossy(passphrase), user_key)
+        }
+    }
+
+    fn user_key() -> UserKey {
+        UserKey {
+            id: "user-key".to_string(),
+            version: 3,
+            private_key: "user locked with user-passphrase".to_string(),
+            fingerprint: None,
+            is_primary: true,
+            is_active: true,
+        }
+    }
+
+    fn address(key_id: &str, private_key: &str, token: Option<&str>, signature: Option<&str>) -> Address {
+        Address {
+            id: "address-1".to_string(),
+            email: "alice@proton.me".to_string(),
+            status: AddressStatus::Enabled,
+            order: 1,
+            keys: vec![AddressKey {
+                id: key_id.to_string(),
+                version: 3,
+                private_key: private_key.to_string(),
+                token: token.map(str::to_string),
+                signature: signature.map(str::to_string),
+                fingerprint: None,
+                flags: AddressKeyFlags::ENCRYPTION,
+                is_primary: true,
+                is_active: true,
+            }],
+        }
+    }
+
+    async fn unlock(address: Address) -> (UnlockedKeys<String>, SessionSecretCache) {
+        let secret_cache = SessionSecretCache::new(Arc::new(InMemoryCacheRepository::new()));
+        secret_cache
+            .set_account_key_passphrase("user-key".to_string(), b"user-passphrase", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let keys = unlock_keys(&FakeCrypto, &[user_key()], &[address], &secret_cache, CancellationToken::new())
+            .await
+            .unwrap();
+
+        (keys, secret_cache)
+    }
+
+    async fn cached_address_key_passphrase(secret_cache: &SessionSecretCache, key_id: &str) -> Option<Vec<u8>> {
+        secret_cache
+            .try_get_address_key_passphrase(key_id.to_string(), CancellationToken::new())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn legacy_address_key_shares_the_user_key_passphrase() {
+        let ((user_keys, address_keys), secret_cache) =
+            unlock(address("legacy-key", "legacy locked with user-passphrase", None, None)).await;
+
+        assert_eq!(user_keys[0].private_key(), "user");
+        assert_eq!(address_keys[0].private_key(), "legacy");
+        assert!(address_keys[0].is_legacy());
+        assert_eq!(
+            cached_address_key_passphrase(&secret_cache, "legacy-key").await.as_deref(),
+            Some(b"user-passphrase".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn address_key_is_unlocked_with_its_signed_token() {
+        let ((_, address_keys), secret_cache) = unlock(address(
+            "migrated-key",
+            "migrated locked with token-passphrase",
+            Some("token-passphrase for user"),
+            Some("token-passphrase signed by user"),
+        ))
+        .await;
+
+        assert_eq!(address_keys[0].private_key(), "migrated");
+        assert!(!address_keys[0].is_legacy());
+        assert_eq!(
+            cached_address_key_passphrase(&secret_cache, "migrated-key").await.as_deref(),
+            Some(b"token-passphrase".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn address_key_with_a_bad_token_signature_stays_locked() {
+        let ((user_keys, address_keys), secret_cache) = unlock(address(
+            "migrated-key",
+            "migrated locked with token-passphrase",
+            Some("token-passphrase for user"),
+            Some("other-passphrase signed by user"),
+        ))
+        .await;
+
+        assert_eq!(user_keys.len(), 1);
+        assert!(address_keys.is_empty());
+        assert_eq!(cached_address_key_passphrase(&secret_cache, "migrated-key").await, None);
+    }
+
+    fn key(id: &str, fingerprint: Option<&str>, is_primary: bool) -> UnlockedKey<()> {
+        UnlockedKey {
+            id: id.to_string(),
+            fingerprint: fingerprint.map(str::to_string),
+            address_id: None,
+            is_primary,
+            is_legacy: false,
+            private_key: (),
+            passphrase: Zeroizing::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn primary_key_falls_back_to_the_first_key() {
+        let keys = [key("old", None, false), key("new", None, true)];
+        assert_eq!(find_primary_key(keys.iter()).unwrap().id(), "new");
+
+        let keys = [key("old", None, false), key("new", None, false)];
+        assert_eq!(find_primary_key(keys.iter()).unwrap().id(), "old");
+    }
+
+    #[test]
+    fn fingerprint_lookup_ignores_letter_case() {
+        let keys = [key("unknown", None, false), key("known", Some("ABCDEF0123"), true)];
+
+        assert_eq!(find_key_by_fingerprint(keys.iter(), "abcdef0123").unwrap().id(), "known");
+        assert!(find_key_by_fingerprint(keys.iter(), "0123").is_none());
+    }
+}
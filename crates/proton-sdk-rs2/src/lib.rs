@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::OnceCell;
 use tokio_util::sync::CancellationToken;
 
@@ -15,8 +16,41 @@ mod secret;
 mod api;
 mod auth;
 mod cache;
+mod http;
+mod serialization;
+mod telemetry;
+mod users;
+mod addresses;
+mod keyring;
+mod public_keys;
+mod events;
+mod feature_flags;
+mod active_sessions;
+#[cfg(test)]
+mod testing;
 
-#[derive(Debug, Clone, PartialEq)]
+pub mod bindings;
+
+pub use crate::{
+    active_sessions::{ActiveSession, OtherSessionsRevocation, SessionRevocation},
+    addresses::{Address, AddressKey, AddressKeyFlags, AddressStatus, get_default_address},
+    auth::TokenPersistenceTrait,
+    cache::{
+        CacheChange, CacheRepositoryTrait, EncryptedCacheRepository, FileCacheRepository, InMemoryCacheRepository,
+    },
+    client::{FeatureFlagProvider, HttpMessageHandler, ProtonClientOptions, TelemetryTrait},
+    events::{CoreEvent, CoreEventLoop},
+    feature_flags::UnleashFeatureFlagProvider,
+    keyring::{Keyring, UnlockedKey},
+    public_keys::{KeyListVerification, PublicAddressKeys, PublicKey},
+    session::{
+        ForkPayload, ProtonAPISession, ProtonSessionOptions, SessionFork, SessionSnapshot, UnauthenticatedSession,
+    },
+    users::{DelinquentState, SubscriptionFlags, User, UserKey, UserType},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct SessionId(String);
 
 impl SessionId {
@@ -35,6 +69,8 @@ impl ToString for SessionId {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct UserId(String);
 
 impl UserId {
@@ -53,10 +89,60 @@ impl ToString for UserId {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct EventId(String);
 
+impl EventId {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn raw(&self) -> &String {
+        &self.0
+    }
+}
+
+impl ToString for EventId {
+    fn to_string(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
 pub enum PasswordMode
 {
     Single = 1,
     Dual = 2,
 }
+
+impl TryFrom<u8> for PasswordMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(PasswordMode::Single),
+            2 => Ok(PasswordMode::Dual),
+            _ => Err(anyhow::anyhow!("Unknown password mode {}", value)),
+        }
+    }
+}
+
+impl From<PasswordMode> for u8 {
+    fn from(value: PasswordMode) -> Self {
+        value as u8
+    }
+}
+
+/// Error returned by operations whose cancellation token was cancelled before they completed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperationCancelledError;
+
+impl std::fmt::Display for OperationCancelledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Operation cancelled")
+    }
+}
+
+impl std::error::Error for OperationCancelledError {}
@@ -0,0 +1,500 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use proton_crypto::crypto::{AccessKeyInfo, DataEncoding, PGPProviderSync, Verifier, VerifierSync};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    addresses::AddressKeyFlags,
+    api::{
+        keys::KeysApiClient,
+        response::{PublicKeyGroup, PublicKeysResponse},
+    },
+    cache::{CacheRepositoryTrait, TypedCache},
+    http::HttpClient,
+};
+
+pub(crate) const PUBLIC_KEYS_CACHE_NAMESPACE: &str = "public-keys";
+const PUBLIC_KEYS_CACHE_SCHEMA_VERSION: u32 = 2;
+
+/// Keys pinned per address, kept apart from the cached keys since refreshes must never drop them.
+pub(crate) const TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE: &str = "trusted-public-keys";
+const TRUSTED_PUBLIC_KEYS_CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// Keys of other users change rarely, but a revoked key must not stay in use for long.
+const PUBLIC_KEYS_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Public keys to encrypt to and verify signatures from an email address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicAddressKeys {
+    pub email: String,
+    /// Whether the address is hosted by Proton, as opposed to an external address.
+    pub is_proton_address: bool,
+    /// Verified keys first, then the keys Proton found elsewhere for external addresses.
+    pub keys: Vec<PublicKey>,
+    pub key_list_verification: KeyListVerification,
+}
+
+impl PublicAddressKeys {
+    /// Key to encrypt to, preferring the keys served by Proton.
+    pub fn encryption_key(&self) -> Option<&PublicKey> {
+        self.keys.iter().find(|key| key.can_encrypt())
+    }
+
+    pub fn verification_keys(&self) -> impl Iterator<Item = &PublicKey> {
+        self.keys.iter().filter(|key| key.can_verify())
+    }
+
+    /// Keys served by Proton that matched a signed key list, which later key lists of the address must be signed with.
+    fn trusted_keys(&self) -> Vec<PublicKey> {
+        if self.key_list_verification != KeyListVerification::Verified {
+            return Vec::new();
+        }
+
+        self.keys.iter().filter(|key| key.is_internal).cloned().collect()
+    }
+}
+
+/// Whether the keys Proton serves for an address match a key list signed by the address owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyListVerification {
+    Verified,
+    /// The address has no signed key list, e.g. because it was created before they existed, so the keys could
+    /// have been substituted by the server.
+    Unverified,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicKey {
+    /// Armored public key.
+    pub public_key: String,
+    pub flags: AddressKeyFlags,
+    /// Whether Proton serves the key for the address, as opposed to a key found elsewhere for an external address.
+    pub is_internal: bool,
+}
+
+impl PublicKey {
+    pub fn can_encrypt(&self) -> bool {
+        self.flags.contains(AddressKeyFlags::ENCRYPTION)
+    }
+
+    pub fn can_verify(&self) -> bool {
+        self.flags.contains(AddressKeyFlags::VERIFICATION)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedPublicKeys {
+    fetched_at_unix_seconds: u64,
+    keys: PublicAddressKeys,
+}
+
+impl CachedPublicKeys {
+    fn is_fresh(&self, now_unix_seconds: u64) -> bool {
+        now_unix_seconds.saturating_sub(self.fetched_at_unix_seconds) < PUBLIC_KEYS_CACHE_TTL.as_secs()
+    }
+}
+
+fn get_unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Looks up the public keys of email addresses, cached for a few minutes per address.
+pub(crate) struct PublicKeysClient {
+    api_client: KeysApiClient,
+    cache: TypedCache<CachedPublicKeys>,
+    trusted_keys_cache: TypedCache<Vec<PublicKey>>,
+}
+
+impl PublicKeysClient {
+    pub(crate) fn new(http_client: HttpClient, entity_cache_repository: Arc<dyn CacheRepositoryTrait>) -> Self {
+        Self {
+            api_client: KeysApiClient::new(http_client),
+            cache: TypedCache::new(
+                entity_cache_repository.clone(),
+                PUBLIC_KEYS_CACHE_NAMESPACE,
+                PUBLIC_KEYS_CACHE_SCHEMA_VERSION,
+            ),
+            trusted_keys_cache: TypedCache::new(
+                entity_cache_repository,
+                TRUSTED_PUBLIC_KEYS_CACHE_NAMESPACE,
+                TRUSTED_PUBLIC_KEYS_CACHE_SCHEMA_VERSION,
+            ),
+        }
+    }
+
+    fn get_email_tag(email: &str) -> String {
+        format!("email:{}", email)
+    }
+
+    pub(crate) async fn get_public_keys<C: SignedKeyListVerifying>(
+        &self,
+        crypto: &C,
+        email: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<PublicAddressKeys> {
+        let email = email.trim().to_lowercase();
+
+        if let Some(cached_keys) = self.cache.try_get(&email, cancellation_token.clone()).await?
+            && cached_keys.is_fresh(get_unix_seconds())
+        {
+            return Ok(cached_keys.keys);
+        }
+
+        let trusted_keys = self
+            .trusted_keys_cache
+            .try_get(&email, cancellation_token.clone())
+            .await?
+            .unwrap_or_default();
+
+        let response = self.api_client.get_public_keys(&email, cancellation_token.clone()).await?;
+        let keys = Self::read_response(crypto, email.clone(), response, &trusted_keys)?;
+
+        // Unverified keys leave the keys trusted so far pinned
+        let newly_trusted_keys = keys.trusted_keys();
+        if !newly_trusted_keys.is_empty() {
+            self.trusted_keys_cache
+                .set(&email, &newly_trusted_keys, Vec::new(), cancellation_token.clone())
+                .await?;
+        }
+
+        let cached_keys = CachedPublicKeys {
+            fetched_at_unix_seconds: get_unix_seconds(),
+            keys,
+        };
+        self.cache
+            .set(&email, &cached_keys, vec![Self::get_email_tag(&email)], cancellation_token)
+            .await?;
+
+        Ok(cached_keys.keys)
+    }
+
+    fn read_response<C: SignedKeyListVerifying>(
+        crypto: &C,
+        email: String,
+        response: PublicKeysResponse,
+        trusted_keys: &[PublicKey],
+    ) -> anyhow::Result<PublicAddressKeys> {
+        let key_list_verification = verify_signed_key_list(crypto, &response.address, trusted_keys)
+            .map_err(|e| e.context(format!("Key list of {} cannot be trusted", email)))?;
+
+        let internal_keys = response.address.keys.into_iter().map(|key| (key, true));
+        let external_keys = response
+            .unverified
+            .into_iter()
+            .flat_map(|group| group.keys)
+            .map(|key| (key, false));
+
+        Ok(PublicAddressKeys {
+            email,
+            is_proton_address: response.is_proton_address,
+            keys: internal_keys
+                .chain(external_keys)
+                .map(|(key, is_internal)| PublicKey {
+                    public_key: key.public_key,
+                    flags: key.flags,
+                    is_internal,
+                })
+                .collect(),
+            key_list_verification,
+        })
+    }
+}
+
+/// Crypto operations needed to verify a signed key list, implemented by every PGP provider.
+pub(crate) trait SignedKeyListVerifying {
+    fn fingerprint(&self, armored_public_key: &str) -> anyhow::Result<String>;
+
+    fn verify_signature(&self, data: &str, armored_signature: &str, armored_public_key: &str) -> bool;
+}
+
+impl<P: PGPProviderSync> SignedKeyListVerifying for P {
+    fn fingerprint(&self, armored_public_key: &str) -> anyhow::Result<String> {
+        let public_key = self.public_key_import(armored_public_key, DataEncoding::Armor)?;
+
+        Ok(public_key.key_fingerprint().to_string())
+    }
+
+    fn verify_signature(&self, data: &str, armored_signature: &str, armored_public_key: &str) -> bool {
+        self.public_key_import(armored_public_key, DataEncoding::Armor)
+            .is_ok_and(|public_key| {
+                self.new_verifier()
+                    .with_verification_key(&public_key)
+                    .verify_detached(data, armored_signature, DataEncoding::Armor)
+                    .is_ok()
+            })
+    }
+}
+
+/// Entry of the data of a signed key list.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SignedKeyListEntry {
+    fingerprint: String,
+    flags: AddressKeyFlags,
+}
+
+/// Checks that the keys served for an address are exactly those of its signed key list, and that the list was
+/// signed with a key trusted for the address.
+///
+/// The trusted keys are those that matched the previous key list of the address. The first time an address is
+/// looked up, the list must be signed with one of its own keys. Once an address has trusted keys, its keys can
+/// no longer be served without a complete key list, which would let the server downgrade them to unverified.
+fn verify_signed_key_list<C: SignedKeyListVerifying>(
+    crypto: &C,
+    group: &PublicKeyGroup,
+    trusted_keys: &[PublicKey],
+) -> anyhow::Result<KeyListVerification> {
+    let Some((data, signature)) = group
+        .signed_key_list
+        .as_ref()
+        .and_then(|signed_key_list| Some((signed_key_list.data.as_ref()?, signed_key_list.signature.as_ref()?)))
+    else {
+        if !trusted_keys.is_empty() {
+            return Err(anyhow::anyhow!("Signed key list is missing although the keys were verified before"));
+        }
+
+        return Ok(KeyListVerification::Unverified);
+    };
+
+    let entries: Vec<SignedKeyListEntry> = serde_json::from_str(data).context("Signed key list is not readable")?;
+    if entries.len() != group.keys.len() {
+        return Err(anyhow::anyhow!("Signed key list does not match the served keys"));
+    }
+
+    for key in &group.keys {
+        let fingerprint = crypto.fingerprint(&key.public_key)?;
+
+        let is_listed = entries
+            .iter()
+            .any(|entry| entry.fingerprint.eq_ignore_ascii_case(&fingerprint) && entry.flags == key.flags);
+        if !is_listed {
+            return Err(anyhow::anyhow!("Key {} is not in the signed key list as served", fingerprint));
+        }
+    }
+
+    let signing_keys: Vec<&str> = if trusted_keys.is_empty() {
+        group.keys.iter().map(|key| key.public_key.as_str()).collect()
+    } else {
+        trusted_keys.iter().map(|key| key.public_key.as_str()).collect()
+    };
+
+    if !signing_keys
+        .iter()
+        .any(|public_key| crypto.verify_signature(data, signature, public_key))
+    {
+        return Err(anyhow::anyhow!("Signed key list is not signed with a trusted key"));
+    }
+
+    Ok(KeyListVerification::Verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use super::*;
+    use crate::{cache, testing::FakeHttpMessageHandler};
+
+    const PUBLIC_KEYS_RESPONSE: &str = r#"{
+        "Code": 1000,
+        "Address": {
+            "Keys": [
+                {"Flags": 1, "PublicKey": "compromised"},
+                {"Flags": 3, "PublicKey": "current"}
+            ]
+        },
+        "Unverified": {
+            "Keys": [
+                {"Flags": 3, "PublicKey": "wkd"}
+            ]
+        },
+        "IsProton": 0
+    }"#;
+
+    #[tokio::test]
+    async fn public_keys_are_cached_per_email() {
+        let handler = FakeHttpMessageHandler::new().with_response("/core/v4/keys/all", PUBLIC_KEYS_RESPONSE);
+        let config = handler.client_config();
+        let client = PublicKeysClient::new(handler.http_client(&config), config.entity_cache_repository.clone());
+        let provider = proton_crypto::new_pgp_provider();
+
+        let keys = client
+            .get_public_keys(&provider, "Bob+tag@example.com", CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert!(!keys.is_proton_address);
+        assert_eq!(keys.key_list_verification, KeyListVerification::Unverified);
+        assert_eq!(keys.encryption_key().unwrap().public_key, "current");
+        assert_eq!(keys.verification_keys().count(), 3);
+        assert!(!keys.keys[2].is_internal);
+
+        client
+            .get_public_keys(&provider, "bob+tag@example.com", CancellationToken::new())
+            .await
+            .unwrap();
+        let requests = handler.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].query.as_deref(), Some("Email=bob%2Btag%40example.com"));
+    }
+
+    #[test]
+    fn cached_public_keys_expire() {
+        let cached_keys = CachedPublicKeys {
+            fetched_at_unix_seconds: 1000,
+            keys: PublicAddressKeys {
+                email: "bob@example.com".to_string(),
+                is_proton_address: true,
+                keys: Vec::new(),
+                key_list_verification: KeyListVerification::Unverified,
+            },
+        };
+
+        assert!(cached_keys.is_fresh(1000 + PUBLIC_KEYS_CACHE_TTL.as_secs() - 1));
+        assert!(!cached_keys.is_fresh(1000 + PUBLIC_KEYS_CACHE_TTL.as_secs()));
+    }
+
+    /// Crypto where the fingerprint of a key is `"fp-<key>"` and a signature is `"<data> signed by <key>"`.
+    struct FakeCrypto;
+
+    impl SignedKeyListVerifying for FakeCrypto {
+        fn fingerprint(&self, armored_public_key: &str) -> anyhow::Result<String> {
+            Ok(format!("fp-{}", armored_public_key))
+        }
+
+        fn verify_signature(&self, data: &str, armored_signature: &str, armored_public_key: &str) -> bool {
+            armored_signature == format!("{} signed by {}", data, armored_public_key)
+        }
+    }
+
+    fn signed_group(keys: &[(&str, u32)], listed_keys: &[(&str, u32)], signing_key: &str) -> PublicKeyGroup {
+        let data = serde_json::to_string(
+            &listed_keys
+                .iter()
+                .map(|(key, flags)| serde_json::json!({"Fingerprint": format!("FP-{}", key), "Flags": flags, "Primary": 0}))
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+        serde_json::from_value(serde_json::json!({
+            "Keys": keys
+                .iter()
+                .map(|(key, flags)| serde_json::json!({"Flags": flags, "PublicKey": key}))
+                .collect::<Vec<_>>(),
+            "SignedKeyList": {"Data": data, "Signature": format!("{} signed by {}", data, signing_key)},
+        }))
+        .unwrap()
+    }
+
+    fn trusted_key(public_key: &str) -> PublicKey {
+        PublicKey {
+            public_key: public_key.to_string(),
+            flags: AddressKeyFlags::VERIFICATION,
+            is_internal: true,
+        }
+    }
+
+    #[test]
+    fn matching_key_list_signed_with_a_listed_key_is_verified() {
+        let group = signed_group(&[("old", 1), ("new", 3)], &[("new", 3), ("old", 1)], "new");
+
+        assert_eq!(verify_signed_key_list(&FakeCrypto, &group, &[]).unwrap(), KeyListVerification::Verified);
+    }
+
+    #[test]
+    fn tampered_key_list_is_rejected() {
+        // A key the owner did not list
+        let group = signed_group(&[("new", 3), ("injected", 3)], &[("new", 3)], "new");
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &[]).is_err());
+
+        // A compromised key served as still usable
+        let group = signed_group(&[("old", 3), ("new", 3)], &[("old", 1), ("new", 3)], "new");
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &[]).is_err());
+
+        // A listed key withheld
+        let group = signed_group(&[("new", 3)], &[("old", 1), ("new", 3)], "new");
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &[]).is_err());
+    }
+
+    #[test]
+    fn key_list_must_be_signed_with_a_previously_trusted_key() {
+        let group = signed_group(&[("substituted", 3)], &[("substituted", 3)], "substituted");
+
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &[trusted_key("new")]).is_err());
+
+        let group = signed_group(&[("rotated", 3)], &[("rotated", 3)], "new");
+        assert_eq!(
+            verify_signed_key_list(&FakeCrypto, &group, &[trusted_key("new")]).unwrap(),
+            KeyListVerification::Verified
+        );
+    }
+
+    #[test]
+    fn missing_key_list_leaves_the_keys_unverified() {
+        let group: PublicKeyGroup = serde_json::from_str(r#"{"Keys": [{"Flags": 3, "PublicKey": "key"}]}"#).unwrap();
+
+        assert_eq!(verify_signed_key_list(&FakeCrypto, &group, &[]).unwrap(), KeyListVerification::Unverified);
+    }
+
+    #[tokio::test]
+    async fn trusted_keys_outlive_the_cached_keys() {
+        let data = r#"[{"Fingerprint": "FP-new", "Flags": 3}]"#;
+        let response = serde_json::json!({
+            "Code": 1000,
+            "Address": {
+                "Keys": [{"Flags": 3, "PublicKey": "new"}],
+                "SignedKeyList": {"Data": data, "Signature": format!("{} signed by new", data)},
+            },
+        });
+        let handler = FakeHttpMessageHandler::new().with_response("/core/v4/keys/all", &response.to_string());
+        let config = handler.client_config();
+        let client = PublicKeysClient::new(handler.http_client(&config), config.entity_cache_repository.clone());
+
+        let keys = client
+            .get_public_keys(&FakeCrypto, "bob@example.com", CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(keys.key_list_verification, KeyListVerification::Verified);
+
+        // As after the contacts of the user were refreshed
+        cache::typed::clear_namespace(config.entity_cache_repository.as_ref(), PUBLIC_KEYS_CACHE_NAMESPACE, CancellationToken::new())
+            .await
+            .unwrap();
+        handler.set_response(
+            None,
+            "/core/v4/keys/all",
+            StatusCode::OK,
+            r#"{"Code": 1000, "Address": {"Keys": [{"Flags": 3, "PublicKey": "substituted"}]}}"#,
+        );
+
+        assert!(
+            client
+                .get_public_keys(&FakeCrypto, "bob@example.com", CancellationToken::new())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn verified_keys_cannot_be_downgraded_to_unverified() {
+        let trusted_keys = [trusted_key("new")];
+
+        let group: PublicKeyGroup =
+            serde_json::from_str(r#"{"Keys": [{"Flags": 3, "PublicKey": "substituted"}]}"#).unwrap();
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &trusted_keys).is_err());
+
+        let group: PublicKeyGroup = serde_json::from_str(
+            r#"{"Keys": [{"Flags": 3, "PublicKey": "substituted"}], "SignedKeyList": {"Data": "[]"}}"#,
+        )
+        .unwrap();
+        assert!(verify_signed_key_list(&FakeCrypto, &group, &trusted_keys).is_err());
+    }
+}
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 
-use base64::{Engine as _, engine::general_purpose};
 use tokio_util::sync::CancellationToken;
 
-use crate::cache::CacheRepositoryTrait;
+use crate::cache::{BytesCache, CacheRepositoryTrait};
+
+const SECRET_CACHE_SCHEMA_VERSION: u32 = 1;
 
 #[async_trait::async_trait]
 pub trait SessionSecretCaching {
@@ -13,27 +14,169 @@ pub trait SessionSecretCaching {
         passphrase: &[u8],
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<()>;
-    
+
     async fn try_get_account_key_passphrase(
         &self,
         key_id: String,
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    async fn set_address_key_passphrase(
+        &self,
+        address_id: String,
+        key_id: String,
+        passphrase: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    async fn try_get_address_key_passphrase(
+        &self,
+        key_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Stores the unlocked key of a share, tagged so that it goes away with its share or volume.
+    async fn set_share_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        share_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    async fn try_get_share_key(
+        &self,
+        share_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Stores the unlocked key of a node reached through the given share.
+    async fn set_node_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        node_id: String,
+        node_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    async fn try_get_node_key(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Stores the session key protecting the content of a file node reached through the given share.
+    async fn set_content_session_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        node_id: String,
+        session_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    async fn try_get_content_session_key(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Purges the keys of an address, e.g. after it was disabled.
+    async fn remove_address_secrets(
+        &self,
+        address_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    /// Purges the share key and every node key and content session key derived from it.
+    async fn remove_share_secrets(
+        &self,
+        share_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    /// Purges the key and content session key of a single node.
+    async fn remove_node_secrets(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
+
+    /// Purges every share, node and content secret belonging to a volume.
+    async fn remove_volume_secrets(
+        &self,
+        volume_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()>;
 }
 
 pub struct SessionSecretCache {
     repository: Arc<dyn CacheRepositoryTrait>,
+    account_cache: BytesCache,
+    address_cache: BytesCache,
+    share_cache: BytesCache,
+    node_cache: BytesCache,
+    content_cache: BytesCache,
 }
 
 impl SessionSecretCache {
     pub(crate) fn new(repository: Arc<dyn CacheRepositoryTrait>) -> Self {
         Self {
+            account_cache: BytesCache::new(repository.clone(), "account", SECRET_CACHE_SCHEMA_VERSION),
+            address_cache: BytesCache::new(repository.clone(), "address", SECRET_CACHE_SCHEMA_VERSION),
+            share_cache: BytesCache::new(repository.clone(), "share", SECRET_CACHE_SCHEMA_VERSION),
+            node_cache: BytesCache::new(repository.clone(), "node", SECRET_CACHE_SCHEMA_VERSION),
+            content_cache: BytesCache::new(repository.clone(), "content", SECRET_CACHE_SCHEMA_VERSION),
             repository,
         }
     }
 
     fn get_account_passphrase_cache_key(key_id: &String) -> String {
-        format!("account:passphrase:{}", key_id)
+        format!("passphrase:{}", key_id)
+    }
+
+    fn get_address_passphrase_cache_key(key_id: &String) -> String {
+        format!("passphrase:{}", key_id)
+    }
+
+    fn get_share_key_cache_key(share_id: &String) -> String {
+        format!("key:{}", share_id)
+    }
+
+    fn get_node_key_cache_key(volume_id: &String, node_id: &String) -> String {
+        format!("key:{}:{}", volume_id, node_id)
+    }
+
+    fn get_content_session_key_cache_key(volume_id: &String, node_id: &String) -> String {
+        format!("session-key:{}:{}", volume_id, node_id)
+    }
+
+    fn get_address_tag(address_id: &String) -> String {
+        format!("address:{}", address_id)
+    }
+
+    fn get_volume_tag(volume_id: &String) -> String {
+        format!("volume:{}", volume_id)
+    }
+
+    fn get_share_tag(share_id: &String) -> String {
+        format!("share:{}", share_id)
+    }
+
+    fn get_node_tag(volume_id: &String, node_id: &String) -> String {
+        format!("node:{}:{}", volume_id, node_id)
+    }
+
+    fn get_node_tags(volume_id: &String, share_id: &String, node_id: &String) -> Vec<String> {
+        vec![
+            Self::get_volume_tag(volume_id),
+            Self::get_share_tag(share_id),
+            Self::get_node_tag(volume_id, node_id),
+        ]
     }
 }
 
@@ -46,31 +189,272 @@ impl SessionSecretCaching for SessionSecretCache {
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<()> {
         let cache_key = Self::get_account_passphrase_cache_key(&key_id);
-        let serialized_value = general_purpose::STANDARD.encode(passphrase);
-        
-        Ok(
-            self.repository
-            .set(&cache_key, serialized_value, vec![], cancellation_token)
-            .await?
-        )
-    }
-    
+
+        self.account_cache
+            .set(&cache_key, passphrase, vec![], cancellation_token)
+            .await
+    }
+
     async fn try_get_account_key_passphrase(
         &self,
         key_id: String,
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<Option<Vec<u8>>> {
         let cache_key = Self::get_account_passphrase_cache_key(&key_id);
-        let serialized_value = self.repository
+
+        self.account_cache
             .try_get(&cache_key, cancellation_token)
-            .await?;
-        
-        match serialized_value {
-            Some(value) => {
-                let decoded = general_purpose::STANDARD.decode(value)?;
-                Ok(Some(decoded))
-            }
-            None => Ok(None),
-        }
+            .await
+    }
+
+    async fn set_address_key_passphrase(
+        &self,
+        address_id: String,
+        key_id: String,
+        passphrase: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let cache_key = Self::get_address_passphrase_cache_key(&key_id);
+        let tags = vec![Self::get_address_tag(&address_id)];
+
+        self.address_cache
+            .set(&cache_key, passphrase, tags, cancellation_token)
+            .await
     }
-}
\ No newline at end of file
+
+    async fn try_get_address_key_passphrase(
+        &self,
+        key_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let cache_key = Self::get_address_passphrase_cache_key(&key_id);
+
+        self.address_cache
+            .try_get(&cache_key, cancellation_token)
+            .await
+    }
+
+    async fn set_share_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        share_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let cache_key = Self::get_share_key_cache_key(&share_id);
+        let tags = vec![Self::get_volume_tag(&volume_id), Self::get_share_tag(&share_id)];
+
+        self.share_cache
+            .set(&cache_key, share_key, tags, cancellation_token)
+            .await
+    }
+
+    async fn try_get_share_key(
+        &self,
+        share_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let cache_key = Self::get_share_key_cache_key(&share_id);
+
+        self.share_cache
+            .try_get(&cache_key, cancellation_token)
+            .await
+    }
+
+    async fn set_node_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        node_id: String,
+        node_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let cache_key = Self::get_node_key_cache_key(&volume_id, &node_id);
+        let tags = Self::get_node_tags(&volume_id, &share_id, &node_id);
+
+        self.node_cache
+            .set(&cache_key, node_key, tags, cancellation_token)
+            .await
+    }
+
+    async fn try_get_node_key(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let cache_key = Self::get_node_key_cache_key(&volume_id, &node_id);
+
+        self.node_cache
+            .try_get(&cache_key, cancellation_token)
+            .await
+    }
+
+    async fn set_content_session_key(
+        &self,
+        volume_id: String,
+        share_id: String,
+        node_id: String,
+        session_key: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let cache_key = Self::get_content_session_key_cache_key(&volume_id, &node_id);
+        let tags = Self::get_node_tags(&volume_id, &share_id, &node_id);
+
+        self.content_cache
+            .set(&cache_key, session_key, tags, cancellation_token)
+            .await
+    }
+
+    async fn try_get_content_session_key(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let cache_key = Self::get_content_session_key_cache_key(&volume_id, &node_id);
+
+        self.content_cache
+            .try_get(&cache_key, cancellation_token)
+            .await
+    }
+
+    async fn remove_address_secrets(
+        &self,
+        address_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.repository
+            .remove_by_tag(&Self::get_address_tag(&address_id), cancellation_token)
+            .await
+    }
+
+    async fn remove_share_secrets(
+        &self,
+        share_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.repository
+            .remove_by_tag(&Self::get_share_tag(&share_id), cancellation_token)
+            .await
+    }
+
+    async fn remove_node_secrets(
+        &self,
+        volume_id: String,
+        node_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.repository
+            .remove_by_tag(&Self::get_node_tag(&volume_id, &node_id), cancellation_token)
+            .await
+    }
+
+    async fn remove_volume_secrets(
+        &self,
+        volume_id: String,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.repository
+            .remove_by_tag(&Self::get_volume_tag(&volume_id), cancellation_token)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::InMemoryCacheRepository;
+
+    async fn cache_share_secrets(secret_cache: &SessionSecretCache, share_id: &str, node_id: &str) {
+        let volume_id = "volume-1".to_string();
+
+        secret_cache
+            .set_share_key(volume_id.clone(), share_id.to_string(), b"share key", CancellationToken::new())
+            .await
+            .unwrap();
+        secret_cache
+            .set_node_key(
+                volume_id.clone(),
+                share_id.to_string(),
+                node_id.to_string(),
+                b"node key",
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        secret_cache
+            .set_content_session_key(
+                volume_id,
+                share_id.to_string(),
+                node_id.to_string(),
+                b"session key",
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn has_node_secrets(secret_cache: &SessionSecretCache, share_id: &str, node_id: &str) -> [bool; 3] {
+        let volume_id = "volume-1".to_string();
+
+        [
+            secret_cache
+                .try_get_share_key(share_id.to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+                .is_some(),
+            secret_cache
+                .try_get_node_key(volume_id.clone(), node_id.to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+                .is_some(),
+            secret_cache
+                .try_get_content_session_key(volume_id, node_id.to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+                .is_some(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn revoking_a_share_purges_the_secrets_derived_from_it() {
+        let secret_cache = SessionSecretCache::new(Arc::new(InMemoryCacheRepository::new()));
+        cache_share_secrets(&secret_cache, "revoked-share", "revoked-node").await;
+        cache_share_secrets(&secret_cache, "kept-share", "kept-node").await;
+        secret_cache
+            .set_account_key_passphrase("key-1".to_string(), b"passphrase", CancellationToken::new())
+            .await
+            .unwrap();
+
+        secret_cache
+            .remove_share_secrets("revoked-share".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(has_node_secrets(&secret_cache, "revoked-share", "revoked-node").await, [false; 3]);
+        assert_eq!(has_node_secrets(&secret_cache, "kept-share", "kept-node").await, [true; 3]);
+        assert!(
+            secret_cache
+                .try_get_account_key_passphrase("key-1".to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn removing_a_volume_purges_all_its_shares() {
+        let secret_cache = SessionSecretCache::new(Arc::new(InMemoryCacheRepository::new()));
+        cache_share_secrets(&secret_cache, "share-1", "node-1").await;
+        cache_share_secrets(&secret_cache, "share-2", "node-2").await;
+
+        secret_cache
+            .remove_volume_secrets("volume-1".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(has_node_secrets(&secret_cache, "share-1", "node-1").await, [false; 3]);
+        assert_eq!(has_node_secrets(&secret_cache, "share-2", "node-2").await, [false; 3]);
+    }
+}
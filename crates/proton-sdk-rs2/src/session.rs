@@ -1,44 +1,126 @@
 use std::{sync::Arc, time::Duration};
 
+use anyhow::Context;
+use base64::{Engine as _, engine::general_purpose};
+use proton_crypto::{crypto::PGPProviderSync, srp::{ClientProof, SRPProvider}};
+use tokio::sync::{broadcast::{self, error::RecvError}, watch};
 use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
 
-use crate::{PasswordMode, SessionId, UserId, auth::TokenCredential, cache::CacheRepositoryTrait, client::{ProtonClientConfiguration, ProtonClientOptions}, secret::SessionSecretCache};
+use crate::{active_sessions::{ActiveSession, OtherSessionsRevocation, SessionRevocation}, addresses::{self, Address, AddressesClient}, events::CoreEventLoop, feature_flags::UnleashFeatureFlagProvider, keyring::{KeyUnlocking, Keyring}, public_keys::{PublicAddressKeys, PublicKeysClient}, PasswordMode, SessionId, UserId, api::{ApiResponse, ProtonApiError, ResponseCode, auth::AuthenticationApiClient, keys::KeysApiClient, response::SesisonInitiationResponse, sessions::SessionsApiClient}, auth::{AuthenticationApiClientTrait, TokenCredential, TokenPersistenceTrait}, cache::CacheRepositoryTrait, client::{HttpMessageHandler, ProtonClientConfiguration, ProtonClientOptions}, http::HttpClient, secret::{SessionSecretCache, SessionSecretCaching}, users::{User, UsersClient}};
+
+pub use fork::{ForkPayload, SessionFork};
+pub use snapshot::SessionSnapshot;
+pub use unauthenticated::UnauthenticatedSession;
+
+mod fork;
+mod password;
+mod snapshot;
+mod unauthenticated;
+
+pub(crate) const KEY_SALT_LENGTH: usize = 16;
+const KEY_PASSPHRASE_BCRYPT_COST: u32 = 10;
+const BCRYPT_HASH_PREFIX_LENGTH: usize = 29;
 
 pub struct ProtonAPISession {
     session_id: SessionId,
     username: String,
     user_id: UserId,
-    token_credential: TokenCredential,
+    token_credential: Arc<TokenCredential>,
     scopes: Vec<String>,
     is_waiting_for_second_factor_code: bool,
+    is_waiting_for_data_password: bool,
     password_mode: PasswordMode,
     client_config: ProtonClientConfiguration,
     secret_cache: SessionSecretCache,
+    http_message_handler: Arc<dyn HttpMessageHandler>,
+    snapshot_tx: Arc<watch::Sender<SessionSnapshot>>,
 }
 
 impl ProtonAPISession {
+    /// Fails outside of a Tokio runtime, which keeps the session snapshot up to date.
     pub(crate) fn new(
-        session_id: SessionId,
-        username: String,
-        user_id: UserId,
-        token_credential: TokenCredential,
-        scopes: Vec<String>,
-        is_waiting_for_second_factor_code: bool,
-        password_mode: PasswordMode,
+        snapshot: SessionSnapshot,
+        client_config: ProtonClientConfiguration,
+        token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+    ) -> anyhow::Result<Self> {
+        let token_credential = Arc::new(Self::create_token_credential(
+            &client_config,
+            snapshot.session_id.clone(),
+            snapshot.access_token.clone(),
+            snapshot.refresh_token.clone(),
+            token_persistence,
+        ));
+
+        Self::with_token_credential(snapshot, client_config, token_credential)
+    }
+
+    /// Same as [`ProtonAPISession::new`] for a session whose tokens are already tracked by a credential.
+    pub(crate) fn with_token_credential(
+        snapshot: SessionSnapshot,
         client_config: ProtonClientConfiguration,
-    ) -> Self {
+        token_credential: Arc<TokenCredential>,
+    ) -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Handle::try_current()
+            .context("Sessions can only be created from within a Tokio runtime")?;
+
         let secret_cache = SessionSecretCache::new(client_config.secret_cache_repository.clone());
-        
-        Self {
-            session_id,
-            username,
-            user_id,
+        let http_message_handler: Arc<dyn HttpMessageHandler> = Arc::from((client_config.http_message_handler_factory)());
+
+        let (snapshot_tx, _) = watch::channel(snapshot.clone());
+        let snapshot_tx = Arc::new(snapshot_tx);
+        runtime.spawn(Self::track_refreshed_tokens(
+            token_credential.subscribe_tokens_refreshed(),
+            snapshot_tx.clone(),
+        ));
+
+        Ok(Self {
+            session_id: snapshot.session_id,
+            username: snapshot.username,
+            user_id: snapshot.user_id,
             token_credential,
-            scopes,
-            is_waiting_for_second_factor_code,
-            password_mode,
+            scopes: snapshot.scopes,
+            is_waiting_for_second_factor_code: snapshot.is_waiting_for_second_factor_code,
+            is_waiting_for_data_password: snapshot.is_waiting_for_data_password,
+            password_mode: snapshot.password_mode,
             client_config,
             secret_cache,
+            http_message_handler,
+            snapshot_tx,
+        })
+    }
+
+    pub(crate) fn create_token_credential(
+        client_config: &ProtonClientConfiguration,
+        session_id: SessionId,
+        access_token: String,
+        refresh_token: String,
+        token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+    ) -> TokenCredential {
+        let authentication_client = AuthenticationApiClient::new(
+            HttpClient::new(client_config, Arc::from((client_config.http_message_handler_factory)()), None, None, None),
+            client_config.refresh_redirect_uri.clone(),
+        );
+
+        TokenCredential::new(Arc::new(authentication_client), session_id, access_token, refresh_token, token_persistence)
+    }
+
+    async fn track_refreshed_tokens(
+        mut tokens_refreshed_rx: broadcast::Receiver<(String, String)>,
+        snapshot_tx: Arc<watch::Sender<SessionSnapshot>>,
+    ) {
+        loop {
+            match tokens_refreshed_rx.recv().await {
+                Ok((access_token, refresh_token)) => {
+                    snapshot_tx.send_modify(|snapshot| {
+                        snapshot.access_token = access_token;
+                        snapshot.refresh_token = refresh_token;
+                    });
+                }
+                // The newer tokens are still queued behind the ones that were skipped
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            }
         }
     }
     
@@ -47,63 +129,170 @@ impl ProtonAPISession {
         password: &[u8],
         app_version: semver::Version,
         session_options: ProtonSessionOptions,
+        cancellation_token: CancellationToken,
     ) -> anyhow::Result<ProtonAPISession> {
-        todo!()
+        let use_unauthenticated_session = session_options.use_unauthenticated_session;
+        let (client_config, token_persistence) = session_options.into_client_configuration(app_version)?;
+
+        if use_unauthenticated_session {
+            let unauthenticated_session =
+                UnauthenticatedSession::create_with_configuration(client_config, token_persistence, cancellation_token.clone())
+                    .await?;
+
+            return unauthenticated_session.begin(username, password, cancellation_token).await;
+        }
+
+        Self::authenticate(
+            username.into(),
+            password,
+            client_config,
+            token_persistence,
+            None,
+            &proton_crypto::new_srp_provider(),
+            cancellation_token,
+        )
+        .await
     }
 
+    /// Logs in through SRP, upgrading the given unauthenticated session in place if any.
+    async fn authenticate(
+        username: String,
+        password: &[u8],
+        client_config: ProtonClientConfiguration,
+        token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+        unauthenticated_session: Option<(SessionId, Arc<TokenCredential>)>,
+        srp: &impl LoginProving,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ProtonAPISession> {
+        let mut http_client =
+            HttpClient::new(&client_config, Arc::from((client_config.http_message_handler_factory)()), None, None, None);
+        if let Some((session_id, token_credential)) = &unauthenticated_session {
+            // Pre-login and post-login requests then share the same UID
+            http_client = http_client.with_credential(session_id.clone(), token_credential.clone());
+        }
+
+        let authentication_client = AuthenticationApiClient::new(http_client, client_config.refresh_redirect_uri.clone());
+
+        let initiation_response = authentication_client
+            .initiate_session(username.clone(), cancellation_token.clone())
+            .await?;
+
+        let password = std::str::from_utf8(password).context("Password is not valid UTF-8")?;
+        let client_proof = srp.generate_login_proof(&username, password, &initiation_response)?;
+
+        let authentication_response = authentication_client
+            .authenticate(username.clone(), initiation_response, client_proof, cancellation_token.clone())
+            .await?;
+
+        let snapshot = SessionSnapshot {
+            session_id: authentication_response.session_id,
+            user_id: authentication_response.user_id,
+            username,
+            access_token: authentication_response.access_token,
+            refresh_token: authentication_response.refresh_token,
+            scopes: authentication_response.scopes,
+            is_waiting_for_second_factor_code: authentication_response.second_factor.enabled != 0,
+            is_waiting_for_data_password: authentication_response.password_mode == PasswordMode::Dual,
+            password_mode: authentication_response.password_mode,
+            app_version: client_config.app_version.clone(),
+            event_id: authentication_response.event_id,
+        };
+
+        let session = match unauthenticated_session {
+            None => ProtonAPISession::new(snapshot, client_config, token_persistence)?,
+            Some((session_id, token_credential)) => {
+                if snapshot.session_id != session_id {
+                    return Err(anyhow::anyhow!("Login did not upgrade the unauthenticated session"));
+                }
+
+                token_credential
+                    .replace_tokens(snapshot.access_token.clone(), snapshot.refresh_token.clone())
+                    .await;
+
+                ProtonAPISession::with_token_credential(snapshot, client_config, token_credential)?
+            }
+        };
+
+        // In single password mode the login password also locks the keys, whose salts need a fully authenticated session
+        if session.password_mode == PasswordMode::Single
+            && !session.is_waiting_for_second_factor_code
+            && let Err(e) = session.cache_user_key_passphrases(password.as_bytes(), cancellation_token).await
+        {
+            log::warn!("User keys stay locked after login: {}", e);
+        }
+
+        Ok(session)
+    }
+
+    /// Resumes a session from a snapshot with the default client options, see [`ProtonAPISession::restore`].
     pub fn resume(
-        session_id: SessionId,
-        username: impl Into<String>,
-        user_id: UserId,
-        access_token: String,
-        refresh_token: String,
-        scopes: Vec<String>,
-        is_waiting_for_second_factor_code: bool,
-        password_mode: PasswordMode,
-        app_version: semver::Version,
+        snapshot: SessionSnapshot,
         secret_cache_repository: Arc<dyn CacheRepositoryTrait>,
-    ) -> ProtonAPISession {
-        ProtonAPISession::resume_with_options(
-            session_id, 
-            username, 
-            user_id, 
-            access_token, 
-            refresh_token, 
-            scopes, 
-            is_waiting_for_second_factor_code, 
-            password_mode, 
-            app_version, 
-            secret_cache_repository, 
-            ProtonClientOptions::default(),
-        )
+    ) -> anyhow::Result<ProtonAPISession> {
+        ProtonAPISession::resume_with_options(snapshot, secret_cache_repository, ProtonClientOptions::default())
     }
 
+    /// Resumes a session from a snapshot, see [`ProtonAPISession::restore`].
     pub fn resume_with_options(
-        session_id: SessionId,
-        username: impl Into<String>,
-        user_id: UserId,
-        access_token: String,
-        refresh_token: String,
-        scopes: Vec<String>,
-        is_waiting_for_second_factor_code: bool,
-        password_mode: PasswordMode,
-        app_version: semver::Version,
+        snapshot: SessionSnapshot,
         secret_cache_repository: Arc<dyn CacheRepositoryTrait>,
         options: ProtonClientOptions,
-    ) -> ProtonAPISession {
-        todo!()
+    ) -> anyhow::Result<ProtonAPISession> {
+        let mut session_options = ProtonSessionOptions::new(options);
+        session_options.secret_cache_repository = Some(secret_cache_repository);
+
+        ProtonAPISession::restore(snapshot, session_options)
     }
 
+    /// Resumes a session from a snapshot previously taken with [`ProtonAPISession::snapshot`].
+    ///
+    /// Fails outside of a Tokio runtime, which keeps the snapshot of the resumed session up to date.
+    pub fn restore(
+        snapshot: SessionSnapshot,
+        options: ProtonSessionOptions,
+    ) -> anyhow::Result<ProtonAPISession> {
+        let (client_config, token_persistence) = options.into_client_configuration(snapshot.app_version.clone())?;
+
+        ProtonAPISession::new(snapshot, client_config, token_persistence)
+    }
+
+    /// Replaces an expired session with a new session of the same user, keeping the configuration of the expired one.
+    ///
+    /// The snapshot of the new session is typically derived from the expired one, e.g.
+    /// `SessionSnapshot { session_id, access_token, refresh_token, ..expired_session.snapshot() }`.
+    /// Fails outside of a Tokio runtime, as [`ProtonAPISession::restore`] does.
     pub fn renew(
-        expired_session: ProtonAPISession,
-        session_id: SessionId,
-        access_token: String,
-        refresh_token: String,
-        scopes: Vec<String>,
-        is_waiting_for_second_factor_code: bool,
-        password_mode: PasswordMode,
-    ) -> ProtonAPISession {
-        todo!()
+        expired_session: &ProtonAPISession,
+        snapshot: SessionSnapshot,
+    ) -> anyhow::Result<ProtonAPISession> {
+        if snapshot.user_id != expired_session.user_id {
+            return Err(anyhow::anyhow!("Renewed session belongs to another user"));
+        }
+
+        let token_persistence = expired_session.token_credential.persistence();
+
+        ProtonAPISession::new(snapshot, expired_session.client_config.clone(), token_persistence)
+    }
+
+    /// Current state of the session, suitable for persisting and later passing to [`ProtonAPISession::restore`].
+    pub fn snapshot(&self) -> SessionSnapshot {
+        self.snapshot_tx.borrow().clone()
+    }
+
+    /// Notifies about every change to the [`ProtonAPISession::snapshot`], including token refreshes.
+    pub fn subscribe_snapshot(&self) -> watch::Receiver<SessionSnapshot> {
+        self.snapshot_tx.subscribe()
+    }
+
+    /// Notifies about every token refresh with the new access and refresh tokens.
+    pub fn subscribe_tokens_refreshed(&self) -> broadcast::Receiver<(String, String)> {
+        self.token_credential.subscribe_tokens_refreshed()
+    }
+
+    /// Notifies when refreshed tokens could not be persisted, in which case the host should store a fresh
+    /// [`ProtonAPISession::snapshot`] by other means, as the previous tokens no longer work.
+    pub fn subscribe_token_persistence_failed(&self) -> broadcast::Receiver<Arc<anyhow::Error>> {
+        self.token_credential.subscribe_token_persistence_failed()
     }
 
     pub async fn end_from_token(
@@ -123,30 +312,221 @@ impl ProtonAPISession {
         todo!()
     }
 
+    /// Unlocks the user keys of a session in dual password mode with its data (mailbox) password.
+    ///
+    /// Fails if the password unlocks none of the user keys, in which case the session keeps waiting for it.
     pub async fn apply_data_password(
         &mut self,
         password: &[u8],
         cancellation_token: CancellationToken,
     ) -> anyhow::Result<()> {
-        todo!()
+        if self.password_mode != PasswordMode::Dual {
+            return Err(anyhow::anyhow!("Session is not in dual password mode"));
+        }
+        if self.is_waiting_for_second_factor_code {
+            return Err(anyhow::anyhow!("Second factor code must be applied before the data password"));
+        }
+
+        self.cache_user_key_passphrases(password, cancellation_token).await?;
+
+        self.is_waiting_for_data_password = false;
+        self.snapshot_tx.send_modify(|snapshot| snapshot.is_waiting_for_data_password = false);
+
+        Ok(())
     }
 
     pub async fn refresh_scopes(cancellation_token: CancellationToken) -> anyhow::Result<()> {
         todo!()
     }
 
-    pub async fn end_from_session(
-        &self
-    ) -> anyhow::Result<bool> {
-        todo!()
+    pub async fn end_from_session(&self, cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        self.get_http_client(None, None, None)
+            .delete::<ApiResponse>("auth/v4", cancellation_token)
+            .await?;
+
+        Ok(())
     }
 
-    pub(crate) fn get_http_client(base_route_path: Option<String>, attempt_timeout: Option<Duration>, total_timeout: Option<Duration>) -> HttpClient {
-        todo!()
+    /// Sessions of the account on every client, including this one.
+    pub async fn list_sessions(&self, cancellation_token: CancellationToken) -> anyhow::Result<Vec<ActiveSession>> {
+        let mut sessions = self.sessions_client().get_sessions(cancellation_token).await?;
+
+        for session in &mut sessions {
+            session.is_current = session.session_id == self.session_id;
+        }
+
+        Ok(sessions)
     }
 
-    pub(crate) fn derive_secret_from_password(password: &[u8], salt: &[u8]) -> Vec<u8> {
-        todo!()
+    /// Revokes a session of the account, ending this one through [`ProtonAPISession::end_from_session`] if it is targeted.
+    pub async fn revoke_session(
+        &self,
+        session_id: &SessionId,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<SessionRevocation> {
+        if *session_id == self.session_id {
+            self.end_from_session(cancellation_token).await?;
+            return Ok(SessionRevocation::CurrentSessionEnded);
+        }
+
+        match self.sessions_client().revoke_session(session_id, cancellation_token).await {
+            Ok(()) => Ok(SessionRevocation::Revoked),
+            Err(e) if e.downcast_ref::<ProtonApiError>().is_some_and(|e| e.code == ResponseCode::DoesNotExist) => {
+                Ok(SessionRevocation::NotFound)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Revokes every session of the account but this one, skipping the request when there is none to revoke.
+    pub async fn revoke_other_sessions(&self, cancellation_token: CancellationToken) -> anyhow::Result<OtherSessionsRevocation> {
+        let sessions_client = self.sessions_client();
+        let mut revocation = OtherSessionsRevocation::default();
+
+        for session in sessions_client.get_sessions(cancellation_token.clone()).await? {
+            if session.session_id == self.session_id {
+                continue;
+            }
+
+            if session.is_revocable {
+                revocation.revoked_session_ids.push(session.session_id);
+            } else {
+                revocation.kept_session_ids.push(session.session_id);
+            }
+        }
+
+        if !revocation.revoked_session_ids.is_empty() {
+            sessions_client.revoke_other_sessions(cancellation_token).await?;
+        }
+
+        Ok(revocation)
+    }
+
+    fn sessions_client(&self) -> SessionsApiClient {
+        SessionsApiClient::new(self.get_http_client(None, None, None))
+    }
+
+    /// Account of the session's user, served from the entity cache when possible.
+    pub async fn get_user(&self, cancellation_token: CancellationToken) -> anyhow::Result<User> {
+        self.users_client().get_user(&self.user_id, cancellation_token).await
+    }
+
+    pub(crate) fn users_client(&self) -> UsersClient {
+        UsersClient::new(self.get_http_client(None, None, None), self.client_config.entity_cache_repository.clone())
+    }
+
+    /// Addresses of the session's user, served from the entity cache when possible.
+    pub async fn get_addresses(&self, cancellation_token: CancellationToken) -> anyhow::Result<Vec<Address>> {
+        self.addresses_client().get_addresses(&self.user_id, cancellation_token).await
+    }
+
+    /// Unlocks the keys of the session's user and addresses, which stay in memory until the keyring is dropped.
+    ///
+    /// The passphrases of the address keys get cached along the way for the operations that need them later.
+    pub async fn get_keyring(&self, cancellation_token: CancellationToken) -> anyhow::Result<Keyring<impl PGPProviderSync + use<>>> {
+        let user = self.get_user(cancellation_token.clone()).await?;
+        let addresses = self.get_addresses(cancellation_token.clone()).await?;
+
+        Keyring::unlock(proton_crypto::new_pgp_provider(), &user.keys, &addresses, &self.secret_cache, cancellation_token).await
+    }
+
+    /// Address Drive signs with on behalf of the user.
+    pub async fn get_default_address(&self, cancellation_token: CancellationToken) -> anyhow::Result<Address> {
+        let addresses = self.get_addresses(cancellation_token).await?;
+
+        addresses::get_default_address(&addresses)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("User has no enabled address"))
+    }
+
+    /// Public keys of any email address, e.g. to share with its owner.
+    pub async fn get_public_keys(
+        &self,
+        email: &str,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<PublicAddressKeys> {
+        PublicKeysClient::new(self.get_http_client(None, None, None), self.client_config.entity_cache_repository.clone())
+            .get_public_keys(&proton_crypto::new_pgp_provider(), email, cancellation_token)
+            .await
+    }
+
+    /// Event loop keeping the entity cache of the session up to date, resuming from the latest event of the snapshot.
+    pub fn create_event_loop(&self) -> CoreEventLoop {
+        CoreEventLoop::new(
+            self.get_http_client(None, None, None),
+            self.client_config.entity_cache_repository.clone(),
+            self.client_config.secret_cache_repository.clone(),
+            self.snapshot_tx.clone(),
+        )
+    }
+
+    /// Feature flag provider fetching the toggles as the session's user, including those targeting them.
+    pub fn create_feature_flag_provider(&self, refresh_interval: Duration) -> UnleashFeatureFlagProvider {
+        UnleashFeatureFlagProvider::with_http_client(self.get_http_client(None, None, None), refresh_interval)
+    }
+
+    pub(crate) fn addresses_client(&self) -> AddressesClient {
+        AddressesClient::new(self.get_http_client(None, None, None), self.client_config.entity_cache_repository.clone())
+    }
+
+    pub(crate) fn get_http_client(&self, base_route_path: Option<String>, attempt_timeout: Option<Duration>, total_timeout: Option<Duration>) -> HttpClient {
+        HttpClient::new(&self.client_config, self.http_message_handler.clone(), base_route_path, attempt_timeout, total_timeout)
+            .with_credential(self.session_id.clone(), self.token_credential.clone())
+    }
+
+    /// Derives the passphrase of the user keys from the (mailbox) password and the key salt of the account.
+    ///
+    /// The passphrase is the hash part of the password's bcrypt hash, as in the other Proton clients.
+    pub(crate) fn derive_secret_from_password(password: &[u8], salt: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let salt: [u8; KEY_SALT_LENGTH] = salt
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Key salt must be {} bytes long", KEY_SALT_LENGTH))?;
+
+        let hash = bcrypt::hash_with_salt(password, KEY_PASSPHRASE_BCRYPT_COST, salt)?.format_for_version(bcrypt::Version::TwoY);
+
+        // Skips the "$2y$10$" prefix and the 22 characters of the encoded salt
+        Ok(hash.as_bytes()[BCRYPT_HASH_PREFIX_LENGTH..].to_vec())
+    }
+
+    /// Derives the passphrase of the active user keys from the password and caches it for the keys it unlocks.
+    async fn cache_user_key_passphrases(&self, password: &[u8], cancellation_token: CancellationToken) -> anyhow::Result<()> {
+        let user = self.get_user(cancellation_token.clone()).await?;
+        let key_salts = KeysApiClient::new(self.get_http_client(None, None, None))
+            .get_key_salts(cancellation_token.clone())
+            .await?;
+
+        let provider = proton_crypto::new_pgp_provider();
+        let mut unlocked_key_count = 0;
+
+        for user_key in user.keys.iter().filter(|key| key.is_active) {
+            let Some(key_salt) = key_salts
+                .iter()
+                .find(|key_salt| key_salt.key_id == user_key.id)
+                .and_then(|key_salt| key_salt.key_salt.as_deref())
+            else {
+                log::debug!("User key {} has no salt", user_key.id);
+                continue;
+            };
+
+            let key_salt = general_purpose::STANDARD.decode(key_salt).context("Key salt is not valid base64")?;
+            let passphrase = Zeroizing::new(Self::derive_secret_from_password(password, &key_salt)?);
+
+            if let Err(e) = provider.import_private_key(&user_key.private_key, &passphrase) {
+                log::debug!("Password does not unlock user key {}: {}", user_key.id, e);
+                continue;
+            }
+
+            self.secret_cache
+                .set_account_key_passphrase(user_key.id.clone(), &passphrase, cancellation_token.clone())
+                .await?;
+            unlocked_key_count += 1;
+        }
+
+        if unlocked_key_count == 0 {
+            return Err(anyhow::anyhow!("Password does not unlock any user key"));
+        }
+
+        Ok(())
     }
 
     fn on_refresh_token_expired(&mut self) {
@@ -154,17 +534,266 @@ impl ProtonAPISession {
     }
 }
 
+/// SRP operation needed to log in, implemented by every SRP provider.
+pub(crate) trait LoginProving: Sync {
+    fn generate_login_proof(
+        &self,
+        username: &str,
+        password: &str,
+        initiation_response: &SesisonInitiationResponse,
+    ) -> anyhow::Result<ClientProof>;
+}
+
+impl<P: SRPProvider + Sync> LoginProving for P {
+    fn generate_login_proof(
+        &self,
+        username: &str,
+        password: &str,
+        initiation_response: &SesisonInitiationResponse,
+    ) -> anyhow::Result<ClientProof> {
+        self.generate_client_proof(
+            username,
+            password,
+            initiation_response.version.try_into()?,
+            &initiation_response.salt,
+            &initiation_response.modulus,
+            &initiation_response.server_ephemeral,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to generate SRP client proof: {}", e))
+    }
+}
+
 pub struct ProtonSessionOptions {
     pub client: ProtonClientOptions,
     pub secret_cache_repository: Option<Arc<dyn CacheRepositoryTrait>>,
+    /// Stores refreshed tokens before the session uses them, see [`TokenPersistenceTrait`].
+    pub token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+    /// Makes [`ProtonAPISession::begin`] log in through an [`UnauthenticatedSession`], so that the login
+    /// requests already carry the UID the session keeps afterwards.
+    pub use_unauthenticated_session: bool,
 }
 
 impl ProtonSessionOptions {
+    fn into_client_configuration(
+        self,
+        app_version: semver::Version,
+    ) -> anyhow::Result<(ProtonClientConfiguration, Option<Arc<dyn TokenPersistenceTrait>>)> {
+        let mut client_options = self.client;
+        if self.secret_cache_repository.is_some() {
+            client_options.secret_cache_repository = self.secret_cache_repository;
+        }
+
+        let client_config = ProtonClientConfiguration::new(app_version, client_options)?;
+
+        Ok((client_config, self.token_persistence))
+    }
+
     pub fn new(client_options: ProtonClientOptions) -> Self {
         let secret_cache_repository = client_options.secret_cache_repository.clone();
         Self {
             client: client_options,
             secret_cache_repository,
+            token_persistence: None,
+            use_unauthenticated_session: false,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode};
+
+    use super::*;
+    use crate::{OperationCancelledError, testing::FakeHttpMessageHandler};
+
+    const USER_RESPONSE: &str = r#"{
+        "Code": 1000,
+        "User": {
+            "ID": "user-1", "Name": "alice", "DisplayName": "Alice", "Email": "alice@proton.me", "Type": 1,
+            "Delinquent": 0, "UsedSpace": 0, "MaxSpace": 0, "Subscribed": 0,
+            "Keys": [{"ID": "key-1", "Version": 3, "PrivateKey": "not a key", "Primary": 1, "Active": 1}]
         }
+    }"#;
+
+    fn create_session(handler: &FakeHttpMessageHandler, password_mode: PasswordMode) -> ProtonAPISession {
+        let snapshot = SessionSnapshot {
+            session_id: SessionId::new("session-1".to_string()),
+            user_id: UserId::new("user-1".to_string()),
+            username: "alice".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            scopes: vec!["full".to_string()],
+            is_waiting_for_second_factor_code: false,
+            is_waiting_for_data_password: password_mode == PasswordMode::Dual,
+            password_mode,
+            app_version: semver::Version::new(1, 0, 0),
+            event_id: None,
+        };
+
+        ProtonAPISession::restore(snapshot, ProtonSessionOptions::new(handler.client_options())).unwrap()
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn data_password_unlocking_no_key_is_rejected() {
+        let handler = FakeHttpMessageHandler::new()
+            .with_response("/core/v4/users", USER_RESPONSE)
+            .with_response(
+                "/core/v4/keys/salts",
+                r#"{"Code": 1000, "KeySalts": [{"ID": "key-1", "KeySalt": "AAAAAAAAAAAAAAAAAAAAAA=="}]}"#,
+            );
+        let mut session = create_session(&handler, PasswordMode::Dual);
+
+        assert!(session.apply_data_password(b"wrong", CancellationToken::new()).await.is_err());
+
+        assert!(session.snapshot().is_waiting_for_data_password);
+        assert_eq!(
+            session
+                .secret_cache
+                .try_get_account_key_passphrase("key-1".to_string(), CancellationToken::new())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn data_password_only_applies_in_dual_password_mode() {
+        let handler = FakeHttpMessageHandler::new();
+        let mut session = create_session(&handler, PasswordMode::Single);
+
+        assert!(session.apply_data_password(b"password", CancellationToken::new()).await.is_err());
+        assert_eq!(handler.request_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_fetched_without_unlocking_the_keys() {
+        let handler = FakeHttpMessageHandler::new().with_response("/core/v4/addresses", r#"{"Code": 1000, "Addresses": []}"#);
+        let session = create_session(&handler, PasswordMode::Single);
+
+        assert!(session.get_addresses(CancellationToken::new()).await.unwrap().is_empty());
+
+        let paths: Vec<_> = handler.requests().into_iter().map(|request| request.path).collect();
+        assert_eq!(paths, ["/core/v4/addresses"]);
+    }
+
+    const SESSIONS_RESPONSE: &str = r#"{
+        "Code": 1000,
+        "Sessions": [
+            {"UID": "session-1", "ClientID": "windows-drive", "LocalizedClientName": "Drive for Windows", "CreateTime": 1700000000},
+            {"UID": "session-2", "ClientID": "web-drive", "LocalizedClientName": "Drive for web", "CreateTime": 1700000100},
+            {"UID": "session-3", "ClientID": "web-account", "LocalizedClientName": "Account", "CreateTime": 1700000200, "Revocable": 0}
+        ]
+    }"#;
+
+    #[tokio::test]
+    async fn only_the_current_session_is_marked_as_current() {
+        let handler = FakeHttpMessageHandler::new().with_response("/auth/v4/sessions", SESSIONS_RESPONSE);
+        let session = create_session(&handler, PasswordMode::Single);
+
+        let sessions = session.list_sessions(CancellationToken::new()).await.unwrap();
+
+        let current: Vec<_> = sessions.iter().map(|session| session.is_current).collect();
+        assert_eq!(current, [true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn revoking_the_current_session_ends_it() {
+        let handler = FakeHttpMessageHandler::new().with_response("/auth/v4", r#"{"Code": 1000}"#);
+        let session = create_session(&handler, PasswordMode::Single);
+
+        let revocation = session
+            .revoke_session(&SessionId::new("session-1".to_string()), CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(revocation, SessionRevocation::CurrentSessionEnded);
+        let requests = handler.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((&requests[0].method, requests[0].path.as_str()), (&Method::DELETE, "/auth/v4"));
+    }
+
+    #[tokio::test]
+    async fn revoking_the_current_session_is_cancellable() {
+        let handler = FakeHttpMessageHandler::new().with_response("/auth/v4", r#"{"Code": 1000}"#);
+        let session = create_session(&handler, PasswordMode::Single);
+        let cancellation_token = CancellationToken::new();
+        cancellation_token.cancel();
+
+        let result = session
+            .revoke_session(&SessionId::new("session-1".to_string()), cancellation_token)
+            .await;
+
+        assert!(result.unwrap_err().is::<OperationCancelledError>());
+    }
+
+    #[tokio::test]
+    async fn revoking_a_missing_session_reports_it_as_not_found() {
+        let handler = FakeHttpMessageHandler::new();
+        handler.set_response(
+            Some(Method::DELETE),
+            "/auth/v4/sessions/session-2",
+            StatusCode::UNPROCESSABLE_ENTITY,
+            r#"{"Code": 2501, "Error": "Session does not exist"}"#,
+        );
+        handler.set_response(
+            Some(Method::DELETE),
+            "/auth/v4/sessions/session-3",
+            StatusCode::UNPROCESSABLE_ENTITY,
+            r#"{"Code": 2001, "Error": "Invalid value"}"#,
+        );
+        let session = create_session(&handler, PasswordMode::Single);
+
+        let revocation = session
+            .revoke_session(&SessionId::new("session-2".to_string()), CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(revocation, SessionRevocation::NotFound);
+
+        assert!(session
+            .revoke_session(&SessionId::new("session-3".to_string()), CancellationToken::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn session_id_cannot_target_another_path() {
+        let handler = FakeHttpMessageHandler::new();
+        let session = create_session(&handler, PasswordMode::Single);
+
+        for session_id in ["../../core/v4/users", "session-2?Force=1", ""] {
+            assert!(session
+                .revoke_session(&SessionId::new(session_id.to_string()), CancellationToken::new())
+                .await
+                .is_err());
+        }
+        assert_eq!(handler.request_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn revoking_other_sessions_reports_which_were_revoked() {
+        let handler = FakeHttpMessageHandler::new();
+        handler.set_response(Some(Method::GET), "/auth/v4/sessions", StatusCode::OK, SESSIONS_RESPONSE);
+        handler.set_response(Some(Method::DELETE), "/auth/v4/sessions", StatusCode::OK, r#"{"Code": 1000}"#);
+        let session = create_session(&handler, PasswordMode::Single);
+
+        let revocation = session.revoke_other_sessions(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(revocation.revoked_session_ids, [SessionId::new("session-2".to_string())]);
+        assert_eq!(revocation.kept_session_ids, [SessionId::new("session-3".to_string())]);
+        assert_eq!(handler.requests().last().unwrap().method, Method::DELETE);
+    }
+
+    #[tokio::test]
+    async fn revoking_other_sessions_without_any_sends_no_revocation() {
+        let handler = FakeHttpMessageHandler::new().with_response(
+            "/auth/v4/sessions",
+            r#"{"Code": 1000, "Sessions": [{"UID": "session-1", "ClientID": "windows-drive", "LocalizedClientName": "Drive for Windows", "CreateTime": 1700000000}]}"#,
+        );
+        let session = create_session(&handler, PasswordMode::Single);
+
+        let revocation = session.revoke_other_sessions(CancellationToken::new()).await.unwrap();
+
+        assert_eq!(revocation, OtherSessionsRevocation::default());
+        let methods: Vec<_> = handler.requests().into_iter().map(|request| request.method).collect();
+        assert_eq!(methods, [Method::GET]);
+    }
+}
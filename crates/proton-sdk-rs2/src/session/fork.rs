@@ -0,0 +1,443 @@
+use std::sync::Arc;
+
+use aes_gcm::{
+    AesGcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, consts::U16},
+    aes::Aes256,
+};
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    PasswordMode,
+    api::{account::AccountApiClient, sessions::SessionsApiClient},
+    http::HttpClient,
+    secret::SessionSecretCaching,
+    session::{ProtonAPISession, ProtonSessionOptions, SessionSnapshot},
+    users::UsersClient,
+};
+
+/// AES-256-GCM with the 16-byte IV of the web clients' payload encryption.
+type PayloadCipher = AesGcm<Aes256, U16>;
+
+const PAYLOAD_NONCE_LENGTH: usize = 16;
+const DEFAULT_PAYLOAD_TYPE: &str = "default";
+
+/// What a parent session hands over to its child besides the tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkPayload {
+    /// No payload, the child having to unlock the keys itself before using them.
+    WithoutKeyPassword,
+    /// The passphrase of the user keys, so the child can use the keys right away.
+    KeyPassword,
+}
+
+/// Fork created for a child client, which needs both the selector and the payload key to resume it.
+pub struct SessionFork {
+    pub selector: String,
+    /// Key the payload is encrypted with, which is never sent to the server.
+    ///
+    /// It must reach the child through the same channel as the selector, e.g. a local socket or a URL fragment.
+    pub payload_key: [u8; 32],
+}
+
+/// Payload in the format of the web clients, which the child decrypts with the key it got along with the selector.
+#[derive(Serialize, Deserialize)]
+struct ForkPayloadContent {
+    #[serde(rename = "keyPassword")]
+    key_password: String,
+    #[serde(rename = "type", default = "default_payload_type")]
+    payload_type: String,
+}
+
+impl Drop for ForkPayloadContent {
+    fn drop(&mut self) {
+        self.key_password.zeroize();
+    }
+}
+
+fn default_payload_type() -> String {
+    DEFAULT_PAYLOAD_TYPE.to_string()
+}
+
+impl ProtonAPISession {
+    /// Forks the session for another client, e.g. a CLI logging in through a desktop app.
+    ///
+    /// An independent fork is not revoked when the parent session ends.
+    pub async fn fork(
+        &self,
+        child_client_id: &str,
+        is_independent: bool,
+        payload: ForkPayload,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<SessionFork> {
+        let payload_key: [u8; 32] = PayloadCipher::generate_key(&mut OsRng).into();
+
+        let encrypted_payload = match payload {
+            ForkPayload::WithoutKeyPassword => None,
+            ForkPayload::KeyPassword => {
+                let content = ForkPayloadContent {
+                    key_password: self.get_key_password(cancellation_token.clone()).await?,
+                    payload_type: default_payload_type(),
+                };
+
+                Some(encrypt_payload(&content, &payload_key)?)
+            }
+        };
+
+        let selector = self
+            .sessions_client()
+            .fork_session(child_client_id, is_independent, encrypted_payload.as_deref(), cancellation_token)
+            .await?;
+
+        Ok(SessionFork { selector, payload_key })
+    }
+
+    /// Resumes a session forked by another client, from the selector and payload key of its [`SessionFork`].
+    ///
+    /// The forked session has tokens of its own, so it is refreshed and ended independently of its parent.
+    pub async fn from_fork(
+        selector: &str,
+        payload_key: &[u8; 32],
+        app_version: semver::Version,
+        options: ProtonSessionOptions,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ProtonAPISession> {
+        let (client_config, token_persistence) = options.into_client_configuration(app_version.clone())?;
+
+        let unauthenticated_http_client = HttpClient::new(
+            &client_config,
+            Arc::from((client_config.http_message_handler_factory)()),
+            None,
+            None,
+            None,
+        );
+
+        let response = SessionsApiClient::new(unauthenticated_http_client.clone())
+            .get_forked_session(selector, cancellation_token.clone())
+            .await?;
+
+        let content = response
+            .payload
+            .as_deref()
+            .map(|encrypted_payload| decrypt_payload(encrypted_payload, payload_key))
+            .transpose()?;
+
+        let token_credential = Arc::new(Self::create_token_credential(
+            &client_config,
+            response.session_id.clone(),
+            response.access_token.clone(),
+            response.refresh_token.clone(),
+            token_persistence,
+        ));
+
+        // The payload only carries the key password, the rest of the account is read with the child's own tokens
+        let http_client = unauthenticated_http_client.with_credential(response.session_id.clone(), token_credential.clone());
+        let user = UsersClient::new(http_client.clone(), client_config.entity_cache_repository.clone())
+            .get_user(&response.user_id, cancellation_token.clone())
+            .await?;
+        let password_mode = AccountApiClient::new(http_client)
+            .get_password_mode(cancellation_token.clone())
+            .await?;
+
+        let snapshot = SessionSnapshot {
+            session_id: response.session_id,
+            user_id: response.user_id,
+            username: user.name.clone().unwrap_or_else(|| user.email.clone()),
+            access_token: response.access_token,
+            refresh_token: response.refresh_token,
+            scopes: response.scopes,
+            // The parent session already went through the second factor
+            is_waiting_for_second_factor_code: false,
+            is_waiting_for_data_password: content.is_none() && password_mode == PasswordMode::Dual,
+            password_mode,
+            app_version,
+            event_id: None,
+        };
+
+        let session = ProtonAPISession::with_token_credential(snapshot, client_config, token_credential)?;
+
+        if let Some(content) = &content {
+            // Every user key is locked with the same passphrase
+            for user_key in user.keys.iter().filter(|key| key.is_active) {
+                session
+                    .secret_cache
+                    .set_account_key_passphrase(
+                        user_key.id.clone(),
+                        content.key_password.as_bytes(),
+                        cancellation_token.clone(),
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Passphrase of the user keys, which the primary user key is expected to have cached.
+    async fn get_key_password(&self, cancellation_token: CancellationToken) -> anyhow::Result<String> {
+        let user = self.get_user(cancellation_token.clone()).await?;
+
+        let mut active_keys: Vec<_> = user.keys.iter().filter(|key| key.is_active).collect();
+        active_keys.sort_by_key(|key| !key.is_primary);
+
+        for user_key in active_keys {
+            if let Some(passphrase) = self
+                .secret_cache
+                .try_get_account_key_passphrase(user_key.id.clone(), cancellation_token.clone())
+                .await?
+            {
+                return String::from_utf8(passphrase).map_err(|_| anyhow::anyhow!("Key passphrase is not valid UTF-8"));
+            }
+        }
+
+        Err(anyhow::anyhow!("User keys are locked, the data password must be applied first"))
+    }
+}
+
+/// Encrypts the payload with AES-256-GCM, as the base64 of the IV followed by the ciphertext.
+fn encrypt_payload(content: &ForkPayloadContent, key: &[u8; 32]) -> anyhow::Result<String> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(content)?);
+
+    let cipher = PayloadCipher::new(key.into());
+    let nonce = PayloadCipher::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt fork payload"))?;
+
+    let mut encrypted = Vec::with_capacity(PAYLOAD_NONCE_LENGTH + ciphertext.len());
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+
+    Ok(general_purpose::STANDARD.encode(encrypted))
+}
+
+fn decrypt_payload(encrypted_payload: &str, key: &[u8; 32]) -> anyhow::Result<ForkPayloadContent> {
+    let encrypted = general_purpose::STANDARD.decode(encrypted_payload)?;
+
+    if encrypted.len() < PAYLOAD_NONCE_LENGTH {
+        return Err(anyhow::anyhow!("Fork payload is truncated"));
+    }
+
+    let (nonce, ciphertext) = encrypted.split_at(PAYLOAD_NONCE_LENGTH);
+    let cipher = PayloadCipher::new(key.into());
+
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt fork payload"))?,
+    );
+
+    let content: ForkPayloadContent = serde_json::from_slice(&plaintext)?;
+    if content.payload_type != DEFAULT_PAYLOAD_TYPE {
+        return Err(anyhow::anyhow!("Unsupported fork payload type {}", content.payload_type));
+    }
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeHttpMessageHandler;
+
+    const PAYLOAD_KEY: [u8; 32] = [3; 32];
+
+    const USER_RESPONSE: &str = r#"{
+        "Code": 1000,
+        "User": {
+            "ID": "user-1", "Name": "alice", "DisplayName": "Alice", "Email": "alice@proton.me", "Type": 1,
+            "Delinquent": 0, "UsedSpace": 0, "MaxSpace": 0, "Subscribed": 0,
+            "Keys": [
+                {"ID": "key-1", "Version": 3, "PrivateKey": "not a key", "Primary": 1, "Active": 1},
+                {"ID": "key-2", "Version": 3, "PrivateKey": "not a key", "Primary": 0, "Active": 0}
+            ]
+        }
+    }"#;
+
+    fn fork_handler(encrypted_payload: Option<&str>, password_mode: PasswordMode) -> FakeHttpMessageHandler {
+        let body = serde_json::json!({
+            "Code": 1000,
+            "UID": "child-session",
+            "UserID": "user-1",
+            "AccessToken": "child-access",
+            "RefreshToken": "child-refresh",
+            "Scopes": ["full"],
+            "Payload": encrypted_payload,
+        });
+        let settings = serde_json::json!({"Code": 1000, "UserSettings": {"Password": {"Mode": u8::from(password_mode)}}});
+
+        FakeHttpMessageHandler::new()
+            .with_response("/auth/v4/sessions/forks/selector-1", &body.to_string())
+            .with_response("/core/v4/users", USER_RESPONSE)
+            .with_response("/core/v4/settings", &settings.to_string())
+    }
+
+    fn payload_content() -> ForkPayloadContent {
+        ForkPayloadContent {
+            key_password: "passphrase".to_string(),
+            payload_type: default_payload_type(),
+        }
+    }
+
+    async fn resume_fork(handler: &FakeHttpMessageHandler) -> ProtonAPISession {
+        ProtonAPISession::from_fork(
+            "selector-1",
+            &PAYLOAD_KEY,
+            semver::Version::new(1, 0, 0),
+            ProtonSessionOptions::new(handler.client_options()),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn payload_only_decrypts_with_its_key() {
+        let encrypted_payload = encrypt_payload(&payload_content(), &PAYLOAD_KEY).unwrap();
+
+        let content = decrypt_payload(&encrypted_payload, &PAYLOAD_KEY).unwrap();
+        assert_eq!(content.key_password, "passphrase");
+
+        assert!(decrypt_payload(&encrypted_payload, &[4; 32]).is_err());
+    }
+
+    #[test]
+    fn payload_has_the_format_of_the_web_clients() {
+        let encrypted = general_purpose::STANDARD
+            .decode(encrypt_payload(&payload_content(), &PAYLOAD_KEY).unwrap())
+            .unwrap();
+
+        let (nonce, ciphertext) = encrypted.split_at(16);
+        let plaintext = PayloadCipher::new(&PAYLOAD_KEY.into())
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&plaintext).unwrap(),
+            serde_json::json!({"keyPassword": "passphrase", "type": "default"})
+        );
+    }
+
+    #[test]
+    fn payload_of_another_type_is_rejected() {
+        let content = ForkPayloadContent {
+            key_password: "passphrase".to_string(),
+            payload_type: "offline".to_string(),
+        };
+        let encrypted_payload = encrypt_payload(&content, &PAYLOAD_KEY).unwrap();
+
+        assert!(decrypt_payload(&encrypted_payload, &PAYLOAD_KEY).is_err());
+    }
+
+    #[tokio::test]
+    async fn forked_session_is_resumed_with_the_key_password() {
+        let encrypted_payload = encrypt_payload(&payload_content(), &PAYLOAD_KEY).unwrap();
+        let handler = fork_handler(Some(&encrypted_payload), PasswordMode::Dual);
+
+        let session = resume_fork(&handler).await;
+
+        let requests = handler.requests();
+        assert!(requests[0].headers.get(http::header::AUTHORIZATION).is_none());
+        assert_eq!(requests[1].headers.get(http::header::AUTHORIZATION).unwrap(), "Bearer child-access");
+
+        let snapshot = session.snapshot();
+        assert_eq!(snapshot.session_id.raw(), "child-session");
+        assert_eq!(snapshot.username, "alice");
+        assert_eq!(snapshot.access_token, "child-access");
+        assert_eq!(snapshot.password_mode, PasswordMode::Dual);
+        assert!(!snapshot.is_waiting_for_data_password);
+        assert_eq!(
+            session
+                .secret_cache
+                .try_get_account_key_passphrase("key-1".to_string(), CancellationToken::new())
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(b"passphrase".as_slice())
+        );
+        assert_eq!(
+            session
+                .secret_cache
+                .try_get_account_key_passphrase("key-2".to_string(), CancellationToken::new())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn forked_session_without_payload_waits_for_the_data_password() {
+        let handler = fork_handler(None, PasswordMode::Dual);
+
+        let session = resume_fork(&handler).await;
+
+        assert!(session.snapshot().is_waiting_for_data_password);
+    }
+
+    #[tokio::test]
+    async fn fork_sends_the_key_password_only_when_asked_to() {
+        let handler = FakeHttpMessageHandler::new()
+            .with_response("/core/v4/users", USER_RESPONSE)
+            .with_response("/auth/v4/sessions/forks", r#"{"Code": 1000, "Selector": "selector-1"}"#);
+        let session = ProtonAPISession::restore(
+            SessionSnapshot {
+                session_id: crate::SessionId::new("session-1".to_string()),
+                user_id: crate::UserId::new("user-1".to_string()),
+                username: "alice".to_string(),
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                scopes: vec!["full".to_string()],
+                is_waiting_for_second_factor_code: false,
+                is_waiting_for_data_password: false,
+                password_mode: PasswordMode::Single,
+                app_version: semver::Version::new(1, 0, 0),
+                event_id: None,
+            },
+            ProtonSessionOptions::new(handler.client_options()),
+        )
+        .unwrap();
+        session
+            .secret_cache
+            .set_account_key_passphrase("key-1".to_string(), b"passphrase", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let fork = session
+            .fork("windows-drive", false, ForkPayload::KeyPassword, CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(fork.selector, "selector-1");
+
+        let request: serde_json::Value = serde_json::from_slice(&handler.requests().last().unwrap().body).unwrap();
+        let content = decrypt_payload(request["Payload"].as_str().unwrap(), &fork.payload_key).unwrap();
+        assert_eq!(content.key_password, "passphrase");
+
+        session
+            .fork("windows-drive", false, ForkPayload::WithoutKeyPassword, CancellationToken::new())
+            .await
+            .unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&handler.requests().last().unwrap().body).unwrap();
+        assert!(request.get("Payload").is_none());
+    }
+
+    #[tokio::test]
+    async fn selector_cannot_target_another_path() {
+        let handler = fork_handler(None, PasswordMode::Single);
+
+        let result = ProtonAPISession::from_fork(
+            "../../../core/v4/users",
+            &PAYLOAD_KEY,
+            semver::Version::new(1, 0, 0),
+            ProtonSessionOptions::new(handler.client_options()),
+            CancellationToken::new(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(handler.request_count(), 0);
+    }
+}
@@ -0,0 +1,495 @@
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use anyhow::Context;
+use proton_crypto::{
+    crypto::{DataEncoding, PGPProviderSync},
+    srp::SRPProvider,
+};
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use crate::{
+    PasswordMode,
+    addresses::Address,
+    api::{
+        account::{AccountApiClient, PasswordProof, PasswordVerifier, PrivateKeyUpdate},
+        auth::AuthenticationApiClient,
+        response::ServerProofResponse,
+    },
+    auth::AuthenticationApiClientTrait,
+    keyring::{Keyring, UnlockedKey},
+    secret::SessionSecretCaching,
+    session::{KEY_SALT_LENGTH, ProtonAPISession},
+    users::UserKey,
+};
+
+impl ProtonAPISession {
+    /// Changes the login password.
+    ///
+    /// In single-password mode the user keys are locked with a passphrase derived from the login password,
+    /// so they are re-encrypted along the way.
+    pub async fn change_password(
+        &self,
+        current_password: &[u8],
+        new_password: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        if self.password_mode == PasswordMode::Single {
+            return self
+                .update_key_password(current_password, new_password, true, cancellation_token)
+                .await;
+        }
+
+        let account_client = self.account_client();
+        let (proof, expected_server_proof) = self.prove_password(current_password, cancellation_token.clone()).await?;
+        let verifier = Self::generate_verifier(&account_client, new_password, cancellation_token.clone()).await?;
+
+        Self::submit_password_update(&account_client, &proof, &expected_server_proof, &verifier, cancellation_token)
+            .await
+    }
+
+    /// Sends the verifier of a new login password, which the server must answer with the expected proof.
+    async fn submit_password_update(
+        account_client: &AccountApiClient,
+        proof: &PasswordProof,
+        expected_server_proof: &[u8],
+        verifier: &PasswordVerifier,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let response = account_client
+            .update_password(proof, verifier, cancellation_token)
+            .await?;
+
+        verify_server_proof(&response, expected_server_proof)
+    }
+
+    /// Changes the mailbox password of a two-password account, re-encrypting the user keys with it.
+    pub async fn change_mailbox_password(
+        &self,
+        login_password: &[u8],
+        new_mailbox_password: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        if self.password_mode != PasswordMode::Dual {
+            return Err(anyhow::anyhow!("Account has no separate mailbox password"));
+        }
+
+        self.update_key_password(login_password, new_mailbox_password, false, cancellation_token)
+            .await
+    }
+
+    /// Re-encrypts the user keys and legacy address keys with a passphrase derived from a new password.
+    ///
+    /// Refuses to do so unless every active user key and legacy address key is unlocked, as the keys left out would
+    /// stay locked with a passphrase derived from the previous password. The secret cache is only updated once the
+    /// server accepted the keys and proved it knows the password.
+    async fn update_key_password(
+        &self,
+        login_password: &[u8],
+        new_key_password: &[u8],
+        is_login_password: bool,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let user = self.get_user(cancellation_token.clone()).await?;
+        let addresses = self.get_addresses(cancellation_token.clone()).await?;
+        let keyring = Keyring::unlock(
+            proton_crypto::new_pgp_provider(),
+            &user.keys,
+            &addresses,
+            &self.secret_cache,
+            cancellation_token.clone(),
+        )
+        .await?;
+        if keyring.user_keys().is_empty() {
+            return Err(anyhow::anyhow!("User keys are locked, the data password must be applied first"));
+        }
+
+        let locked_key_ids = find_locked_keys(&keyring.all_keys().collect::<Vec<_>>(), &user.keys, &addresses);
+        if !locked_key_ids.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Keys {} are locked and would be lost by changing the password",
+                locked_key_ids.join(", ")
+            ));
+        }
+
+        let mut key_salt = [0u8; KEY_SALT_LENGTH];
+        OsRng.fill_bytes(&mut key_salt);
+        let new_passphrase = Zeroizing::new(Self::derive_secret_from_password(new_key_password, &key_salt)?);
+
+        let user_keys = keyring.user_keys().iter().collect::<Vec<_>>();
+        let legacy_address_keys = keyring.all_keys().filter(|key| key.is_legacy()).collect::<Vec<_>>();
+        let user_key_updates = Self::export_keys(&keyring, &user_keys, &new_passphrase)?;
+        let legacy_address_key_updates = Self::export_keys(&keyring, &legacy_address_keys, &new_passphrase)?;
+
+        let account_client = self.account_client();
+        let (proof, expected_server_proof) = self.prove_password(login_password, cancellation_token.clone()).await?;
+        let verifier = match is_login_password {
+            true => Some(Self::generate_verifier(&account_client, new_key_password, cancellation_token.clone()).await?),
+            false => None,
+        };
+
+        let re_encrypted_keys = user_keys.iter().chain(&legacy_address_keys).copied().collect::<Vec<_>>();
+        self.submit_key_updates(
+            &re_encrypted_keys,
+            &new_passphrase,
+            &expected_server_proof,
+            account_client.update_private_keys(
+                &proof,
+                &key_salt,
+                &user_key_updates,
+                &legacy_address_key_updates,
+                verifier.as_ref(),
+                cancellation_token.clone(),
+            ),
+            cancellation_token.clone(),
+        )
+        .await?;
+
+        // The cached user and addresses still hold the keys locked with the previous passphrase
+        self.users_client()
+            .invalidate_user(&self.user_id, cancellation_token.clone())
+            .await?;
+        self.addresses_client()
+            .invalidate_addresses(&self.user_id, cancellation_token)
+            .await
+    }
+
+    /// Sends the re-encrypted keys, caching their new passphrase only if the server accepts them with the
+    /// expected proof.
+    async fn submit_key_updates<K>(
+        &self,
+        re_encrypted_keys: &[&UnlockedKey<K>],
+        new_passphrase: &[u8],
+        expected_server_proof: &[u8],
+        update_request: impl Future<Output = anyhow::Result<ServerProofResponse>>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let response = update_request.await?;
+        verify_server_proof(&response, expected_server_proof)?;
+
+        self.cache_key_passphrases(re_encrypted_keys, new_passphrase, cancellation_token)
+            .await
+    }
+
+    fn export_keys<P: PGPProviderSync>(
+        keyring: &Keyring<P>,
+        keys: &[&UnlockedKey<P::PrivateKey>],
+        passphrase: &[u8],
+    ) -> anyhow::Result<Vec<PrivateKeyUpdate>> {
+        keys.iter()
+            .map(|key| {
+                let private_key = keyring
+                    .provider()
+                    .private_key_export(key.private_key(), passphrase, DataEncoding::Armor)
+                    .with_context(|| format!("Failed to re-encrypt key {}", key.id()))?;
+
+                Ok(PrivateKeyUpdate {
+                    key_id: key.id().to_string(),
+                    private_key: String::from_utf8(private_key.as_ref().to_vec())?,
+                })
+            })
+            .collect()
+    }
+
+    async fn cache_key_passphrases<K>(
+        &self,
+        keys: &[&UnlockedKey<K>],
+        passphrase: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        for key in keys {
+            match key.address_id() {
+                Some(address_id) => {
+                    self.secret_cache
+                        .set_address_key_passphrase(
+                            address_id.to_string(),
+                            key.id().to_string(),
+                            passphrase,
+                            cancellation_token.clone(),
+                        )
+                        .await?
+                }
+                None => {
+                    self.secret_cache
+                        .set_account_key_passphrase(key.id().to_string(), passphrase, cancellation_token.clone())
+                        .await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Proves knowledge of the login password through the same SRP exchange as a login.
+    async fn prove_password(
+        &self,
+        password: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<(PasswordProof, Vec<u8>)> {
+        let authentication_client = AuthenticationApiClient::new(
+            self.get_http_client(None, None, None),
+            self.client_config.refresh_redirect_uri.clone(),
+        );
+
+        let initiation_response = authentication_client
+            .initiate_session(self.username.clone(), cancellation_token)
+            .await?;
+
+        let password = std::str::from_utf8(password).context("Password is not valid UTF-8")?;
+        let client_proof = proton_crypto::new_srp_provider()
+            .generate_client_proof(
+                &self.username,
+                password,
+                initiation_response.version.try_into()?,
+                &initiation_response.salt,
+                &initiation_response.modulus,
+                &initiation_response.server_ephemeral,
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to generate SRP client proof: {}", e))?;
+
+        let proof = PasswordProof {
+            client_ephemeral: client_proof.client_ephemeral,
+            client_proof: client_proof.client_proof,
+            srp_session_id: initiation_response.srp_session_id,
+        };
+
+        Ok((proof, client_proof.expected_server_proof))
+    }
+
+    async fn generate_verifier(
+        account_client: &AccountApiClient,
+        password: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<PasswordVerifier> {
+        let modulus = account_client.get_modulus(cancellation_token).await?;
+
+        let password = std::str::from_utf8(password).context("Password is not valid UTF-8")?;
+        let verifier = proton_crypto::new_srp_provider()
+            .generate_client_verifier(password, None, &modulus.modulus)
+            .map_err(|e| anyhow::anyhow!("Failed to generate SRP verifier: {}", e))?;
+
+        Ok(PasswordVerifier {
+            version: verifier.version,
+            modulus_id: modulus.modulus_id,
+            salt: verifier.salt,
+            verifier: verifier.verifier,
+        })
+    }
+
+    fn account_client(&self) -> AccountApiClient {
+        AccountApiClient::new(self.get_http_client(None, None, None))
+    }
+}
+
+fn verify_server_proof(response: &ServerProofResponse, expected_server_proof: &[u8]) -> anyhow::Result<()> {
+    if response.server_proof != expected_server_proof {
+        return Err(anyhow::anyhow!("Server proof verification failed"));
+    }
+
+    Ok(())
+}
+
+/// IDs of the active user keys and legacy address keys missing from the unlocked keys.
+fn find_locked_keys<'a, K>(
+    unlocked_keys: &[&UnlockedKey<K>],
+    user_keys: &'a [UserKey],
+    addresses: &'a [Address],
+) -> Vec<&'a str> {
+    let user_key_ids = user_keys.iter().filter(|key| key.is_active).map(|key| key.id.as_str());
+    let legacy_address_key_ids = addresses
+        .iter()
+        .flat_map(|address| &address.keys)
+        .filter(|key| key.is_active && key.token.is_none())
+        .map(|key| key.id.as_str());
+
+    user_key_ids
+        .chain(legacy_address_key_ids)
+        .filter(|key_id| !unlocked_keys.iter().any(|key| key.id() == *key_id))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PasswordMode, SessionId, UserId,
+        session::{ProtonSessionOptions, SessionSnapshot},
+        testing::FakeHttpMessageHandler,
+    };
+
+    #[test]
+    fn key_passphrase_is_the_bcrypt_hash_part() {
+        let salt = [7u8; KEY_SALT_LENGTH];
+
+        let passphrase = ProtonAPISession::derive_secret_from_password(b"correct horse", &salt).unwrap();
+
+        assert_eq!(passphrase.len(), 31);
+        assert_eq!(passphrase, ProtonAPISession::derive_secret_from_password(b"correct horse", &salt).unwrap());
+        assert_ne!(passphrase, ProtonAPISession::derive_secret_from_password(b"battery staple", &salt).unwrap());
+        assert!(ProtonAPISession::derive_secret_from_password(b"correct horse", &salt[1..]).is_err());
+    }
+
+    #[test]
+    fn key_passphrase_matches_crypt_blowfish() {
+        // From crypt("apple", "$2y$10$a0DqbEjja1PxWUvyXVXjae") with libxcrypt, the salt being "saltiestsaltever" in
+        // the bcrypt flavor of base64 as other Proton clients encode it
+        let passphrase = ProtonAPISession::derive_secret_from_password(b"apple", b"saltiestsaltever").unwrap();
+
+        assert_eq!(passphrase, b"gvjUbNI0.ZrMXVnsJB13xo4Dnfufc5e");
+    }
+
+    fn user_key(id: &str, is_active: bool) -> UserKey {
+        UserKey {
+            id: id.to_string(),
+            version: 3,
+            private_key: String::new(),
+            fingerprint: None,
+            is_primary: false,
+            is_active,
+        }
+    }
+
+    #[test]
+    fn locked_user_and_legacy_address_keys_are_found() {
+        let addresses: Vec<Address> = serde_json::from_str(
+            r#"[{
+                "ID": "address-1", "Email": "alice@proton.me", "Status": 1, "Order": 1,
+                "Keys": [
+                    {"ID": "legacy-key", "Version": 3, "PrivateKey": "", "Flags": 3, "Primary": 1, "Active": 1},
+                    {"ID": "migrated-key", "Version": 3, "PrivateKey": "", "Token": "", "Flags": 3, "Primary": 0, "Active": 1}
+                ]
+            }]"#,
+        )
+        .unwrap();
+        let user_keys = [user_key("user-key", true), user_key("other-user-key", true), user_key("lost-key", false)];
+        let unlocked_user_key = UnlockedKey::new("user-key", None, (), b"passphrase");
+
+        let locked_key_ids = find_locked_keys(&[&unlocked_user_key], &user_keys, &addresses);
+
+        assert_eq!(locked_key_ids, ["other-user-key", "legacy-key"]);
+    }
+
+    fn create_session(handler: &FakeHttpMessageHandler) -> ProtonAPISession {
+        let snapshot = SessionSnapshot {
+            session_id: SessionId::new("session-1".to_string()),
+            user_id: UserId::new("user-1".to_string()),
+            username: "alice".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            scopes: vec!["full".to_string()],
+            is_waiting_for_second_factor_code: false,
+            is_waiting_for_data_password: false,
+            password_mode: PasswordMode::Single,
+            app_version: semver::Version::new(1, 0, 0),
+            event_id: None,
+        };
+
+        ProtonAPISession::restore(snapshot, ProtonSessionOptions::new(handler.client_options())).unwrap()
+    }
+
+    async fn cached_passphrases(session: &ProtonAPISession) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+        let user_key_passphrase = session
+            .secret_cache
+            .try_get_account_key_passphrase("user-key".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+        let address_key_passphrase = session
+            .secret_cache
+            .try_get_address_key_passphrase("legacy-key".to_string(), CancellationToken::new())
+            .await
+            .unwrap();
+
+        (user_key_passphrase, address_key_passphrase)
+    }
+
+    #[tokio::test]
+    async fn rejected_keys_leave_the_cached_passphrases_untouched() {
+        let session = create_session(&FakeHttpMessageHandler::new());
+        let user_key = UnlockedKey::new("user-key", None, (), b"old");
+        let legacy_address_key = UnlockedKey::new("legacy-key", Some("address-1"), (), b"old");
+        session
+            .cache_key_passphrases(&[&user_key, &legacy_address_key], b"old", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let result = session
+            .submit_key_updates(
+                &[&user_key, &legacy_address_key],
+                b"new",
+                b"proof",
+                async { Err(anyhow::anyhow!("Keys rejected")) },
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(cached_passphrases(&session).await, (Some(b"old".to_vec()), Some(b"old".to_vec())));
+
+        // Accepted by a server that does not know the password
+        let result = session
+            .submit_key_updates(
+                &[&user_key, &legacy_address_key],
+                b"new",
+                b"proof",
+                async { Ok(ServerProofResponse { server_proof: b"forged".to_vec() }) },
+                CancellationToken::new(),
+            )
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(cached_passphrases(&session).await, (Some(b"old".to_vec()), Some(b"old".to_vec())));
+
+        session
+            .submit_key_updates(
+                &[&user_key, &legacy_address_key],
+                b"new",
+                b"proof",
+                async { Ok(ServerProofResponse { server_proof: b"proof".to_vec() }) },
+                CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(cached_passphrases(&session).await, (Some(b"new".to_vec()), Some(b"new".to_vec())));
+    }
+
+    #[tokio::test]
+    async fn dual_password_change_sends_the_new_verifier_and_checks_the_server_proof() {
+        let handler = FakeHttpMessageHandler::new().with_response(
+            "/core/v4/settings/password",
+            r#"{"Code": 1000, "ServerProof": "cHJvb2Y="}"#,
+        );
+        let account_client = AccountApiClient::new(handler.http_client(&handler.client_config()));
+        let proof = PasswordProof {
+            client_ephemeral: b"ephemeral".to_vec(),
+            client_proof: b"client-proof".to_vec(),
+            srp_session_id: "srp-session".to_string(),
+        };
+        let verifier = PasswordVerifier {
+            version: 4,
+            modulus_id: "modulus-1".to_string(),
+            salt: "c2FsdA==".to_string(),
+            verifier: "dmVyaWZpZXI=".to_string(),
+        };
+
+        ProtonAPISession::submit_password_update(&account_client, &proof, b"proof", &verifier, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let requests = handler.requests();
+        assert_eq!(requests[0].method, http::Method::PUT);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&requests[0].body).unwrap(),
+            serde_json::json!({
+                "ClientEphemeral": "ZXBoZW1lcmFs",
+                "ClientProof": "Y2xpZW50LXByb29m",
+                "SRPSession": "srp-session",
+                "Auth": {"Version": 4, "ModulusID": "modulus-1", "Salt": "c2FsdA==", "Verifier": "dmVyaWZpZXI="},
+            })
+        );
+
+        let result =
+            ProtonAPISession::submit_password_update(&account_client, &proof, b"other", &verifier, CancellationToken::new())
+                .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{EventId, PasswordMode, SessionId, UserId};
+
+const ENCRYPTION_FORMAT_VERSION: u8 = 1;
+const NONCE_LENGTH: usize = 12;
+
+/// Everything needed to resume a session without logging in again.
+///
+/// The snapshot contains the session tokens, so hosts should store it as they would a password,
+/// for instance encrypted with [`SessionSnapshot::encrypt`].
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub session_id: SessionId,
+    pub user_id: UserId,
+    pub username: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub scopes: Vec<String>,
+    pub is_waiting_for_second_factor_code: bool,
+    pub is_waiting_for_data_password: bool,
+    pub password_mode: PasswordMode,
+    pub app_version: semver::Version,
+    /// Latest core event the session caught up with, from which its event loop resumes.
+    #[serde(default)]
+    pub event_id: Option<EventId>,
+}
+
+impl SessionSnapshot {
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Serializes and encrypts the snapshot with AES-256-GCM under a key provided by the host.
+    pub fn encrypt(&self, key: &[u8; 32]) -> anyhow::Result<Vec<u8>> {
+        let cipher = Aes256Gcm::new(key.into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher
+            .encrypt(&nonce, self.to_bytes()?.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt session snapshot"))?;
+
+        let mut encrypted = Vec::with_capacity(1 + NONCE_LENGTH + ciphertext.len());
+        encrypted.push(ENCRYPTION_FORMAT_VERSION);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+
+        Ok(encrypted)
+    }
+
+    pub fn decrypt(encrypted: &[u8], key: &[u8; 32]) -> anyhow::Result<Self> {
+        let Some((&ENCRYPTION_FORMAT_VERSION, encrypted)) = encrypted.split_first() else {
+            return Err(anyhow::anyhow!("Unsupported session snapshot format"));
+        };
+
+        if encrypted.len() < NONCE_LENGTH {
+            return Err(anyhow::anyhow!("Session snapshot is truncated"));
+        }
+
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let cipher = Aes256Gcm::new(key.into());
+
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt session snapshot"))?;
+
+        Self::from_bytes(&plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ProtonClientOptions,
+        proton::ProtonClientTlsPolicy,
+        session::{ProtonAPISession, ProtonSessionOptions},
+    };
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn snapshot() -> SessionSnapshot {
+        SessionSnapshot {
+            session_id: SessionId::new("session-1".to_string()),
+            user_id: UserId::new("user-1".to_string()),
+            username: "alice".to_string(),
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            scopes: vec!["full".to_string()],
+            is_waiting_for_second_factor_code: false,
+            is_waiting_for_data_password: true,
+            password_mode: PasswordMode::Dual,
+            app_version: semver::Version::new(1, 2, 3),
+            event_id: Some(EventId::new("event-1".to_string())),
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_bytes() {
+        let bytes = snapshot().to_bytes().unwrap();
+
+        assert!(SessionSnapshot::from_bytes(&bytes).unwrap() == snapshot());
+    }
+
+    #[test]
+    fn snapshot_without_event_id_is_still_read() {
+        let mut value = serde_json::to_value(snapshot()).unwrap();
+        value.as_object_mut().unwrap().remove("event_id");
+
+        let restored = SessionSnapshot::from_bytes(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert!(restored.event_id.is_none());
+    }
+
+    #[test]
+    fn encrypted_snapshot_only_decrypts_with_its_key() {
+        let encrypted = snapshot().encrypt(&KEY).unwrap();
+
+        assert!(SessionSnapshot::decrypt(&encrypted, &KEY).unwrap() == snapshot());
+        assert!(SessionSnapshot::decrypt(&encrypted, &[8; 32]).is_err());
+    }
+
+    #[test]
+    fn tampered_snapshot_is_rejected() {
+        let encrypted = snapshot().encrypt(&KEY).unwrap();
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(SessionSnapshot::decrypt(&tampered, &KEY).is_err());
+
+        let mut other_version = encrypted.clone();
+        other_version[0] = ENCRYPTION_FORMAT_VERSION + 1;
+        assert!(SessionSnapshot::decrypt(&other_version, &KEY).is_err());
+
+        assert!(SessionSnapshot::decrypt(&encrypted[..NONCE_LENGTH], &KEY).is_err());
+        assert!(SessionSnapshot::decrypt(&[], &KEY).is_err());
+    }
+
+    fn client_options() -> ProtonClientOptions {
+        ProtonClientOptions {
+            tls_policy: Some(ProtonClientTlsPolicy::NoCertificatePinning),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn restoring_outside_of_a_runtime_fails_instead_of_panicking() {
+        let options = ProtonSessionOptions::new(client_options());
+
+        assert!(ProtonAPISession::restore(snapshot(), options).is_err());
+    }
+
+    #[tokio::test]
+    async fn restored_session_has_the_snapshot_state() {
+        let options = ProtonSessionOptions::new(client_options());
+
+        let session = ProtonAPISession::restore(snapshot(), options).unwrap();
+
+        assert!(session.snapshot() == snapshot());
+    }
+}
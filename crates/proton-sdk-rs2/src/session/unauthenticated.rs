@@ -0,0 +1,245 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    SessionId,
+    api::auth::AuthenticationApiClient,
+    auth::{TokenCredential, TokenPersistenceTrait},
+    client::ProtonClientConfiguration,
+    feature_flags::UnleashFeatureFlagProvider,
+    http::HttpClient,
+    session::{LoginProving, ProtonAPISession, ProtonSessionOptions},
+};
+
+/// Session without a user, for the calls made before logging in, e.g. fetching feature flags.
+///
+/// Logging in through [`UnauthenticatedSession::begin`] upgrades the session rather than creating another one,
+/// so the server sees pre-login and post-login traffic under the same UID.
+pub struct UnauthenticatedSession {
+    session_id: SessionId,
+    token_credential: Arc<TokenCredential>,
+    client_config: ProtonClientConfiguration,
+    token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+}
+
+impl UnauthenticatedSession {
+    pub async fn create(
+        app_version: semver::Version,
+        options: ProtonSessionOptions,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        let (client_config, token_persistence) = options.into_client_configuration(app_version)?;
+
+        Self::create_with_configuration(client_config, token_persistence, cancellation_token).await
+    }
+
+    pub(crate) async fn create_with_configuration(
+        client_config: ProtonClientConfiguration,
+        token_persistence: Option<Arc<dyn TokenPersistenceTrait>>,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        let authentication_client = AuthenticationApiClient::new(
+            HttpClient::new(&client_config, Arc::from((client_config.http_message_handler_factory)()), None, None, None),
+            client_config.refresh_redirect_uri.clone(),
+        );
+
+        let response = authentication_client
+            .create_unauthenticated_session(cancellation_token)
+            .await?;
+
+        let token_credential = Arc::new(ProtonAPISession::create_token_credential(
+            &client_config,
+            response.session_id.clone(),
+            response.access_token,
+            response.refresh_token,
+            token_persistence.clone(),
+        ));
+
+        Ok(Self {
+            session_id: response.session_id,
+            token_credential,
+            client_config,
+            token_persistence,
+        })
+    }
+
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    /// Logs in, turning this session into an authenticated one with the same UID.
+    pub async fn begin(
+        self,
+        username: impl Into<String>,
+        password: &[u8],
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ProtonAPISession> {
+        self.begin_with(username, password, &proton_crypto::new_srp_provider(), cancellation_token)
+            .await
+    }
+
+    pub(crate) async fn begin_with(
+        self,
+        username: impl Into<String>,
+        password: &[u8],
+        srp: &impl LoginProving,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<ProtonAPISession> {
+        ProtonAPISession::authenticate(
+            username.into(),
+            password,
+            self.client_config,
+            self.token_persistence,
+            Some((self.session_id, self.token_credential)),
+            srp,
+            cancellation_token,
+        )
+        .await
+    }
+
+    /// Feature flag provider fetching the toggles under this session, before the user is known.
+    pub fn create_feature_flag_provider(&self, refresh_interval: Duration) -> UnleashFeatureFlagProvider {
+        UnleashFeatureFlagProvider::with_http_client(self.get_http_client(), refresh_interval)
+    }
+
+    pub(crate) fn get_http_client(&self) -> HttpClient {
+        HttpClient::new(
+            &self.client_config,
+            Arc::from((self.client_config.http_message_handler_factory)()),
+            None,
+            None,
+            None,
+        )
+        .with_credential(self.session_id.clone(), self.token_credential.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proton_crypto::srp::ClientProof;
+
+    use super::*;
+    use crate::{
+        api::{ApiResponse, response::SesisonInitiationResponse},
+        http::SESSION_ID_HEADER_NAME,
+        testing::FakeHttpMessageHandler,
+    };
+
+    const UNAUTHENTICATED_SESSION_RESPONSE: &str =
+        r#"{"Code": 1000, "UID": "unauth-session", "AccessToken": "access", "RefreshToken": "refresh"}"#;
+
+    /// Stands in for SRP, which the fake API cannot take part in.
+    struct FakeSrp;
+
+    impl LoginProving for FakeSrp {
+        fn generate_login_proof(
+            &self,
+            _username: &str,
+            _password: &str,
+            _initiation_response: &SesisonInitiationResponse,
+        ) -> anyhow::Result<ClientProof> {
+            Ok(ClientProof {
+                client_ephemeral: b"client-ephemeral".to_vec(),
+                client_proof: b"client-proof".to_vec(),
+                expected_server_proof: b"server-proof".to_vec(),
+            })
+        }
+    }
+
+    fn login_handler(session_id: &str) -> FakeHttpMessageHandler {
+        let authentication_response = serde_json::json!({
+            "Code": 1000,
+            "UID": session_id,
+            "UserID": "user-1",
+            "AccessToken": "login-access",
+            "RefreshToken": "login-refresh",
+            "Scopes": ["full"],
+            "ServerProof": "c2VydmVyLXByb29m",
+            "PasswordMode": 2,
+            "2FA": {"Enabled": 0},
+        });
+
+        FakeHttpMessageHandler::new()
+            .with_response("/auth/v4/sessions", UNAUTHENTICATED_SESSION_RESPONSE)
+            .with_response(
+                "/auth/v4/info",
+                r#"{"Code": 1000, "Version": 4, "Modulus": "modulus", "ServerEphemeral": "AA==", "Salt": "AA==", "SRPSession": "srp-session"}"#,
+            )
+            .with_response("/auth/v4", &authentication_response.to_string())
+            .with_response("/tests/ping", r#"{"Code": 1000}"#)
+    }
+
+    async fn create_session(handler: &FakeHttpMessageHandler) -> UnauthenticatedSession {
+        UnauthenticatedSession::create(
+            semver::Version::new(1, 0, 0),
+            ProtonSessionOptions::new(handler.client_options()),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn pre_login_requests_carry_the_unauthenticated_session() {
+        let handler = FakeHttpMessageHandler::new()
+            .with_response("/auth/v4/sessions", UNAUTHENTICATED_SESSION_RESPONSE)
+            .with_response("/tests/ping", r#"{"Code": 1000}"#);
+
+        let session = create_session(&handler).await;
+
+        assert_eq!(session.session_id().raw(), "unauth-session");
+        session
+            .get_http_client()
+            .get::<ApiResponse>("tests/ping", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let requests = handler.requests();
+        assert!(requests[0].headers.get(SESSION_ID_HEADER_NAME).is_none());
+        assert_eq!(requests[1].headers[SESSION_ID_HEADER_NAME], "unauth-session");
+        assert_eq!(requests[1].headers[http::header::AUTHORIZATION], "Bearer access");
+    }
+
+    #[tokio::test]
+    async fn login_upgrades_the_session_with_the_same_uid() {
+        let handler = login_handler("unauth-session");
+        let session = create_session(&handler).await;
+
+        let session = session
+            .begin_with("alice", b"password", &FakeSrp, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let snapshot = session.snapshot();
+        assert_eq!(snapshot.session_id.raw(), "unauth-session");
+        assert_eq!(snapshot.access_token, "login-access");
+
+        session
+            .get_http_client(None, None, None)
+            .get::<ApiResponse>("tests/ping", CancellationToken::new())
+            .await
+            .unwrap();
+
+        let requests = handler.requests();
+        let paths: Vec<_> = requests.iter().map(|request| request.path.as_str()).collect();
+        assert_eq!(paths, ["/auth/v4/sessions", "/auth/v4/info", "/auth/v4", "/tests/ping"]);
+        for request in &requests[1..] {
+            assert_eq!(request.headers[SESSION_ID_HEADER_NAME], "unauth-session");
+        }
+        assert_eq!(requests[2].headers[http::header::AUTHORIZATION], "Bearer access");
+        assert_eq!(requests[3].headers[http::header::AUTHORIZATION], "Bearer login-access");
+    }
+
+    #[tokio::test]
+    async fn login_into_another_session_is_rejected() {
+        let handler = login_handler("other-session");
+        let session = create_session(&handler).await;
+
+        let result = session
+            .begin_with("alice", b"password", &FakeSrp, CancellationToken::new())
+            .await;
+
+        assert!(result.is_err());
+    }
+}
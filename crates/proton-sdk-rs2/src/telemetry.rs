@@ -0,0 +1,127 @@
+//! Catalogue of the metrics reported through [`TelemetryTrait`], each with a typed payload from the proto.
+
+#[cfg(test)]
+use std::sync::Mutex;
+
+use prost::Name;
+use prost_types::Any;
+
+use crate::{client::TelemetryTrait, proton};
+
+/// Payload of a metric, identifying the metric it is recorded under.
+pub trait MetricPayload: Name + Default {
+    const METRIC_NAME: &'static str;
+}
+
+impl MetricPayload for proton::ApiRetrySucceededEventPayload {
+    const METRIC_NAME: &'static str = "api_retry_succeeded";
+}
+
+impl MetricPayload for proton::UploadSuccessEventPayload {
+    const METRIC_NAME: &'static str = "upload_success";
+}
+
+impl MetricPayload for proton::UploadFailureEventPayload {
+    const METRIC_NAME: &'static str = "upload_failure";
+}
+
+impl MetricPayload for proton::DownloadSuccessEventPayload {
+    const METRIC_NAME: &'static str = "download_success";
+}
+
+impl MetricPayload for proton::DownloadFailureEventPayload {
+    const METRIC_NAME: &'static str = "download_failure";
+}
+
+impl MetricPayload for proton::DecryptionErrorEventPayload {
+    const METRIC_NAME: &'static str = "decryption_error";
+}
+
+impl MetricPayload for proton::VerificationErrorEventPayload {
+    const METRIC_NAME: &'static str = "verification_error";
+}
+
+/// Records a metric under the name of its payload, packed as `Any`.
+pub async fn record_metric<P: MetricPayload>(telemetry: &dyn TelemetryTrait, payload: &P) {
+    match Any::from_msg(payload) {
+        Ok(payload) => telemetry.record_metric(P::METRIC_NAME.to_string(), Some(payload)).await,
+        Err(e) => log::warn!("Failed to encode the {} metric: {}", P::METRIC_NAME, e),
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RecordedMetric {
+    pub name: String,
+    pub payload: Option<Any>,
+}
+
+/// [`TelemetryTrait`] keeping every metric in memory, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct RecordingTelemetry {
+    metrics: Mutex<Vec<RecordedMetric>>,
+}
+
+#[cfg(test)]
+impl RecordingTelemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn metrics(&self) -> Vec<RecordedMetric> {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Payloads of the metrics recorded under the name of `P`, in the order they were recorded.
+    pub fn payloads<P: MetricPayload>(&self) -> Vec<P> {
+        self.metrics()
+            .into_iter()
+            .filter(|metric| metric.name == P::METRIC_NAME)
+            .filter_map(|metric| metric.payload?.to_msg().ok())
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl TelemetryTrait for RecordingTelemetry {
+    async fn record_metric(&self, name: String, payload: Option<Any>) {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(RecordedMetric { name, payload });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_typed_payloads() {
+        let telemetry = RecordingTelemetry::new();
+
+        let upload = proton::UploadSuccessEventPayload {
+            uploaded_size: 1024,
+            duration_milliseconds: 250,
+        };
+        record_metric(&telemetry, &upload).await;
+        record_metric(&telemetry, &proton::DownloadSuccessEventPayload::default()).await;
+
+        let metrics = telemetry.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].name, "upload_success");
+        assert_eq!(
+            metrics[0].payload.as_ref().unwrap().type_url,
+            "type.googleapis.com/proton.sdk.UploadSuccessEventPayload"
+        );
+
+        assert_eq!(telemetry.payloads::<proton::UploadSuccessEventPayload>(), vec![upload]);
+        assert!(telemetry.payloads::<proton::UploadFailureEventPayload>().is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+//! Fakes shared by the unit tests of the modules talking to the API.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use http::{HeaderMap, Method, StatusCode};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    client::{HttpMessageHandler, ProtonClientConfiguration, ProtonClientOptions},
+    http::HttpClient,
+    proton::HttpRequestType,
+};
+
+/// Request received by a [`FakeHttpMessageHandler`].
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub request_type: Option<HttpRequestType>,
+}
+
+#[derive(Default)]
+struct FakeApi {
+    /// Responses by method and path, a method of `None` answering any method.
+    responses: HashMap<(Option<Method>, String), (StatusCode, Vec<u8>)>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// Fake API answering each path with a canned response and recording the requests it is sent.
+///
+/// Clones share the same responses and requests, so a test can keep one to inspect what its client sent.
+#[derive(Clone, Default)]
+pub(crate) struct FakeHttpMessageHandler {
+    api: Arc<Mutex<FakeApi>>,
+}
+
+impl FakeHttpMessageHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests to `path` with any method, with a `200 OK` carrying `body`.
+    pub fn with_response(self, path: &str, body: &str) -> Self {
+        self.set_response(None, path, StatusCode::OK, body);
+        self
+    }
+
+    pub fn set_response(&self, method: Option<Method>, path: &str, status: StatusCode, body: &str) {
+        self.api
+            .lock()
+            .unwrap()
+            .responses
+            .insert((method, path.to_string()), (status, body.as_bytes().to_vec()));
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.api.lock().unwrap().requests.clone()
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.api.lock().unwrap().requests.len()
+    }
+
+    /// Client options sending every request to this fake.
+    pub fn client_options(&self) -> ProtonClientOptions {
+        let handler = self.clone();
+
+        ProtonClientOptions {
+            custom_http_message_handler_factory: Some(Arc::new(move || Box::new(handler.clone()))),
+            ..Default::default()
+        }
+    }
+
+    /// Configuration of an app in version 1.0.0 sending every request to this fake.
+    pub fn client_config(&self) -> ProtonClientConfiguration {
+        ProtonClientConfiguration::new(semver::Version::new(1, 0, 0), self.client_options()).unwrap()
+    }
+
+    /// Unauthenticated client sending every request to this fake.
+    pub fn http_client(&self, config: &ProtonClientConfiguration) -> HttpClient {
+        HttpClient::new(config, Arc::new(self.clone()), None, None, None)
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpMessageHandler for FakeHttpMessageHandler {
+    async fn send(
+        &self,
+        request: http::Request<Vec<u8>>,
+        _cancellation_token: CancellationToken,
+    ) -> anyhow::Result<http::Response<Vec<u8>>> {
+        let (parts, body) = request.into_parts();
+        let path = parts.uri.path().to_string();
+
+        let mut api = self.api.lock().unwrap();
+        api.requests.push(RecordedRequest {
+            method: parts.method.clone(),
+            path: path.clone(),
+            query: parts.uri.query().map(str::to_string),
+            headers: parts.headers,
+            body,
+            request_type: parts.extensions.get::<HttpRequestType>().copied(),
+        });
+
+        let (status, body) = api
+            .responses
+            .get(&(Some(parts.method.clone()), path.clone()))
+            .or_else(|| api.responses.get(&(None, path.clone())))
+            .cloned()
+            .unwrap_or_else(|| panic!("Unexpected {} request to {}", parts.method, path));
+
+        Ok(http::Response::builder().status(status).body(body)?)
+    }
+}
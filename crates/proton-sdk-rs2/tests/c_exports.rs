@@ -0,0 +1,307 @@
+use std::{
+    ffi::c_void,
+    net::TcpListener,
+    path::PathBuf,
+    io::{Cursor, Read},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use libloading::{Library, Symbol};
+use prost::Message;
+use proton_sdk_rs2::{
+    bindings::{ArrayAction, ByteArray, RequestAction},
+    proton::{self, request::Payload, response},
+};
+
+type HandleRequest = unsafe extern "C" fn(ByteArray, *const c_void, ArrayAction);
+type CompleteRequest = unsafe extern "C" fn(i64, ByteArray);
+
+fn library_path() -> PathBuf {
+    // Integration tests run from target/<profile>/deps, where cargo also places the cdylib built for them
+    let exe = std::env::current_exe().unwrap();
+    let deps_dir = exe.parent().unwrap();
+    let file_name = libloading::library_filename("proton_sdk_rs2");
+
+    [deps_dir, deps_dir.parent().unwrap()]
+        .into_iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.exists())
+        .expect("The cdylib should be built alongside the integration tests")
+}
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+extern "C" fn forward_response(state: *const c_void, array: ByteArray) {
+    // Each request owns its sender, and its response action is invoked at most once
+    let sender = unsafe { Box::from_raw(state as *mut Sender<Vec<u8>>) };
+    let bytes = unsafe { std::slice::from_raw_parts(array.pointer, array.length) };
+
+    let _ = sender.send(bytes.to_vec());
+}
+
+fn start(library: &Library, payload: Payload) -> Receiver<Vec<u8>> {
+    let handle_request: Symbol<HandleRequest> = unsafe { library.get(b"proton_sdk_handle_request") }.unwrap();
+
+    let request = proton::Request { payload: Some(payload) }.encode_to_vec();
+    let (sender, receiver) = mpsc::channel();
+
+    unsafe {
+        handle_request(
+            ByteArray { pointer: request.as_ptr(), length: request.len() },
+            Box::into_raw(Box::new(sender)) as *const c_void,
+            forward_response,
+        );
+    }
+
+    receiver
+}
+
+fn send(library: &Library, payload: Payload) -> proton::Response {
+    let response = start(library, payload).recv_timeout(RESPONSE_TIMEOUT).unwrap();
+
+    proton::Response::decode(response.as_slice()).unwrap()
+}
+
+fn begin_request(base_url: String, cancellation_token_source_handle: i64) -> Payload {
+    Payload::SessionBegin(proton::SessionBeginRequest {
+        username: "user".to_string(),
+        password: "password".to_string(),
+        app_version: "1.0.0".to_string(),
+        options: Some(proton::ProtonClientOptions {
+            base_url,
+            tls_policy: proton::ProtonClientTlsPolicy::NoCertificatePinning as i32,
+            ..Default::default()
+        }),
+        cancellation_token_source_handle,
+        ..Default::default()
+    })
+}
+
+fn int64_result(response: proton::Response) -> i64 {
+    let Some(response::Result::Value(value)) = response.result else {
+        panic!("Expected a handle, got {:?}", response.result);
+    };
+    assert_eq!(value.type_url, "type.googleapis.com/google.protobuf.Int64Value");
+
+    i64::decode(value.value.as_slice()).unwrap()
+}
+
+#[test]
+fn checked_in_header_matches_the_exports() {
+    let generated_header = include_str!(concat!(env!("OUT_DIR"), "/proton_sdk.h"));
+    let checked_in_header = include_str!("../include/proton_sdk.h");
+
+    assert!(
+        generated_header == checked_in_header,
+        "include/proton_sdk.h is outdated, rebuild with PROTON_SDK_UPDATE_HEADER=1 to regenerate it"
+    );
+}
+
+#[test]
+fn session_resume_through_c_exports() {
+    let library = unsafe { Library::new(library_path()) }.unwrap();
+
+    let response = send(
+        &library,
+        Payload::SessionResume(proton::SessionResumeRequest {
+            username: "user".to_string(),
+            app_version: "1.0.0".to_string(),
+            session_id: "session-id".to_string(),
+            user_id: "user-id".to_string(),
+            access_token: "access-token".to_string(),
+            refresh_token: "refresh-token".to_string(),
+            scopes: vec!["full".to_string()],
+            options: Some(proton::ProtonClientOptions {
+                tls_policy: proton::ProtonClientTlsPolicy::NoCertificatePinning as i32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    );
+
+    let session_handle = int64_result(response);
+
+    let response = send(&library, Payload::SessionFree(proton::SessionFreeRequest { session_handle }));
+    assert_eq!(response.result, None);
+
+    let response = send(&library, Payload::SessionFree(proton::SessionFreeRequest { session_handle }));
+    assert!(matches!(response.result, Some(response::Result::Error(_))));
+}
+
+#[test]
+fn cancelled_login_reports_successful_cancellation() {
+    let library = unsafe { Library::new(library_path()) }.unwrap();
+
+    // Accepts connections without ever answering, so the login stays in flight until cancelled
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}/", server.local_addr().unwrap());
+
+    let cancellation_token_source_handle = int64_result(send(
+        &library,
+        Payload::CancellationTokenSourceCreate(proton::CancellationTokenSourceCreateRequest {}),
+    ));
+
+    let login = start(&library, begin_request(base_url, cancellation_token_source_handle));
+
+    thread::sleep(Duration::from_millis(200));
+    let response = send(
+        &library,
+        Payload::CancellationTokenSourceCancel(proton::CancellationTokenSourceCancelRequest {
+            cancellation_token_source_handle,
+        }),
+    );
+    assert_eq!(response.result, None);
+
+    let response = proton::Response::decode(login.recv_timeout(RESPONSE_TIMEOUT).unwrap().as_slice()).unwrap();
+
+    let Some(response::Result::Error(error)) = response.result else {
+        panic!("Expected the login to fail, got {:?}", response.result);
+    };
+    assert_eq!(error.domain, proton::ErrorDomain::SuccessfulCancellation as i32);
+
+    let response = send(
+        &library,
+        Payload::CancellationTokenSourceFree(proton::CancellationTokenSourceFreeRequest {
+            cancellation_token_source_handle,
+        }),
+    );
+    assert_eq!(response.result, None);
+}
+
+#[test]
+fn freed_cancellation_source_suppresses_completion() {
+    let library = unsafe { Library::new(library_path()) }.unwrap();
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}/", server.local_addr().unwrap());
+
+    let cancellation_token_source_handle = int64_result(send(
+        &library,
+        Payload::CancellationTokenSourceCreate(proton::CancellationTokenSourceCreateRequest {}),
+    ));
+
+    let login = start(&library, begin_request(base_url, cancellation_token_source_handle));
+
+    thread::sleep(Duration::from_millis(200));
+    let response = send(
+        &library,
+        Payload::CancellationTokenSourceFree(proton::CancellationTokenSourceFreeRequest {
+            cancellation_token_source_handle,
+        }),
+    );
+    assert_eq!(response.result, None);
+
+    assert_eq!(login.recv_timeout(Duration::from_secs(1)), Err(RecvTimeoutError::Timeout));
+}
+
+/// Host answering HTTP requests itself, with the content of every response served through stream reads.
+struct TestHost {
+    complete_request: CompleteRequest,
+    requests: Mutex<Vec<proton::HttpRequest>>,
+    response_content: Mutex<Cursor<Vec<u8>>>,
+}
+
+const RESPONSE_CONTENT_HANDLE: i64 = 7;
+
+impl TestHost {
+    fn complete(&self, completion_handle: i64, value: prost_types::Any) {
+        let response = proton::Response { result: Some(response::Result::Value(value)) }.encode_to_vec();
+
+        unsafe {
+            (self.complete_request)(completion_handle, ByteArray { pointer: response.as_ptr(), length: response.len() });
+        }
+    }
+}
+
+extern "C" fn send_http_request(state: *const c_void, request: ByteArray, completion_handle: i64) {
+    let host = unsafe { &*(state as *const TestHost) };
+    let request = unsafe { std::slice::from_raw_parts(request.pointer, request.length) };
+
+    host.requests.lock().unwrap().push(proton::HttpRequest::decode(request).unwrap());
+
+    let response = proton::HttpResponse {
+        status_code: 200,
+        headers: vec![proton::HttpHeader {
+            name: "content-type".to_string(),
+            values: vec!["application/json".to_string()],
+        }],
+        bindings_content_handle: RESPONSE_CONTENT_HANDLE,
+    };
+    host.complete(
+        completion_handle,
+        prost_types::Any {
+            type_url: "type.googleapis.com/proton.sdk.HttpResponse".to_string(),
+            value: response.encode_to_vec(),
+        },
+    );
+}
+
+extern "C" fn read_stream(state: *const c_void, request: ByteArray, completion_handle: i64) {
+    let host = unsafe { &*(state as *const TestHost) };
+    let request = unsafe { std::slice::from_raw_parts(request.pointer, request.length) };
+    let request = proton::StreamReadRequest::decode(request).unwrap();
+    assert_eq!(request.stream_handle, RESPONSE_CONTENT_HANDLE);
+
+    // Serve the content in small chunks to exercise repeated reads
+    let buffer_length = (request.buffer_length as usize).min(4);
+    let buffer = unsafe { std::slice::from_raw_parts_mut(request.buffer_pointer as *mut u8, buffer_length) };
+    let bytes_read = host.response_content.lock().unwrap().read(buffer).unwrap();
+
+    host.complete(
+        completion_handle,
+        prost_types::Any {
+            type_url: "type.googleapis.com/google.protobuf.Int32Value".to_string(),
+            value: (bytes_read as i32).encode_to_vec(),
+        },
+    );
+}
+
+#[test]
+fn http_requests_are_delegated_to_the_host() {
+    let library = unsafe { Library::new(library_path()) }.unwrap();
+    let complete_request: Symbol<CompleteRequest> = unsafe { library.get(b"proton_sdk_complete_request") }.unwrap();
+
+    let host: &'static TestHost = Box::leak(Box::new(TestHost {
+        complete_request: *complete_request,
+        requests: Mutex::new(Vec::new()),
+        response_content: Mutex::new(Cursor::new(br#"{"Code":1000}"#.to_vec())),
+    }));
+
+    let session_handle = int64_result(send(
+        &library,
+        Payload::SessionResume(proton::SessionResumeRequest {
+            username: "user".to_string(),
+            app_version: "1.0.0".to_string(),
+            session_id: "session-id".to_string(),
+            user_id: "user-id".to_string(),
+            access_token: "access-token".to_string(),
+            refresh_token: "refresh-token".to_string(),
+            options: Some(proton::ProtonClientOptions {
+                base_url: "https://drive-api.proton.me/".to_string(),
+                http_request_action: send_http_request as RequestAction as usize as i64,
+                stream_read_action: read_stream as RequestAction as usize as i64,
+                callback_state: host as *const TestHost as i64,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    ));
+
+    let response = send(&library, Payload::SessionEnd(proton::SessionEndRequest { session_handle, ..Default::default() }));
+    assert_eq!(response.result, None);
+
+    let requests = host.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "DELETE");
+    assert_eq!(requests[0].url, "https://drive-api.proton.me/auth/v4");
+    assert_eq!(requests[0].sdk_content_handle, 0);
+    let session_id_header = requests[0].headers.iter().find(|header| header.name == "x-pm-uid").unwrap();
+    assert_eq!(session_id_header.values, vec!["session-id".to_string()]);
+
+    send(&library, Payload::SessionFree(proton::SessionFreeRequest { session_handle }));
+}